@@ -5,37 +5,50 @@ use {
         rpc::JsonRpcConfig,
         validator::{Validator, ValidatorConfig, ValidatorExit, ValidatorStartProgress},
     },
-    safecoin_client::rpc_client::RpcClient,
+    safecoin_client::{
+        client_error::{ClientErrorKind, Result as ClientResult},
+        rpc_client::RpcClient,
+        rpc_config::RpcConfirmedTransactionConfig,
+    },
     safecoin_ledger::{blockstore::create_new_ledger, create_new_tmp_ledger},
+    safecoin_transaction_status::UiTransactionEncoding,
     solana_net_utils::PortRange,
     solana_runtime::{
         bank_forks::{ArchiveFormat, SnapshotConfig, SnapshotVersion},
         genesis_utils::create_genesis_config_with_leader_ex,
         hardened_unpack::MAX_GENESIS_ARCHIVE_UNPACKED_SIZE,
+        snapshot_utils,
     },
     solana_sdk::{
         account::{Account, AccountSharedData},
-        clock::{Slot, DEFAULT_MS_PER_SLOT},
+        clock::{Epoch, Slot, DEFAULT_MS_PER_SLOT},
         commitment_config::CommitmentConfig,
         epoch_schedule::EpochSchedule,
+        feature::{self, Feature},
+        feature_set::FEATURE_NAMES,
         fee_calculator::{FeeCalculator, FeeRateGovernor},
+        genesis_config::ClusterType,
         hash::Hash,
         native_token::sol_to_lamports,
         pubkey::Pubkey,
         rent::Rent,
-        signature::{read_keypair_file, write_keypair_file, Keypair, Signer},
+        signature::{read_keypair_file, write_keypair_file, Keypair, Signature, Signer},
+        sysvar::{self, clock::Clock, slot_hashes::SlotHashes, slot_history::SlotHistory, Sysvar},
     },
     std::{
-        collections::HashMap,
-        fs::remove_dir_all,
+        collections::{BTreeMap, HashMap},
+        fs::{self, remove_dir_all, File},
         net::{IpAddr, Ipv4Addr, SocketAddr},
         path::{Path, PathBuf},
         sync::{Arc, RwLock},
         thread::sleep,
-        time::Duration,
+        time::{Duration, Instant},
     },
 };
 
+/// How long starting a validator waits for its JSON RPC service to report it healthy
+const HEALTHY_TIMEOUT_SECS: u64 = 30;
+
 #[derive(Clone)]
 pub struct ProgramInfo {
     pub program_id: Pubkey,
@@ -43,6 +56,42 @@ pub struct ProgramInfo {
     pub program_path: PathBuf,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonAccount {
+    lamports: u64,
+    owner: String,
+    data: String,
+    executable: bool,
+    rent_epoch: Epoch,
+}
+
+fn parse_json_account(
+    address: &str,
+    account: serde_json::Value,
+) -> Result<(Pubkey, AccountSharedData), String> {
+    let address = address
+        .parse::<Pubkey>()
+        .map_err(|err| format!("invalid address: {}", err))?;
+    let account: JsonAccount = serde_json::from_value(account).map_err(|err| err.to_string())?;
+    let owner = account
+        .owner
+        .parse::<Pubkey>()
+        .map_err(|err| format!("invalid owner {}: {}", account.owner, err))?;
+    let data =
+        base64::decode(&account.data).map_err(|err| format!("invalid base64 data: {}", err))?;
+    Ok((
+        address,
+        AccountSharedData::from(Account {
+            lamports: account.lamports,
+            data,
+            owner,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+        }),
+    ))
+}
+
 #[derive(Debug)]
 pub struct TestValidatorNodeConfig {
     gossip_addr: SocketAddr,
@@ -70,15 +119,21 @@ impl Default for TestValidatorNodeConfig {
 pub struct TestValidatorGenesis {
     fee_rate_governor: FeeRateGovernor,
     ledger_path: Option<PathBuf>,
+    preserve_ledger: Option<bool>,
     pub rent: Rent,
     rpc_config: JsonRpcConfig,
     rpc_ports: Option<(u16, u16)>, // (JsonRpc, JsonRpcPubSub), None == random ports
     warp_slot: Option<Slot>,
+    snapshot_archive_path: Option<PathBuf>,
     no_bpf_jit: bool,
+    no_faucet: bool,
     accounts: HashMap<Pubkey, AccountSharedData>,
     programs: Vec<ProgramInfo>,
     epoch_schedule: Option<EpochSchedule>,
+    cluster_type: Option<ClusterType>,
+    features: HashMap<Pubkey, bool>, // feature id => active at genesis
     node_config: TestValidatorNodeConfig,
+    vote_account: Option<(Keypair, u64)>, // (vote account keypair, stake lamports)
     pub validator_exit: Arc<RwLock<ValidatorExit>>,
     pub start_progress: Arc<RwLock<ValidatorStartProgress>>,
     pub authorized_voter_keypairs: Arc<RwLock<Vec<Arc<Keypair>>>>,
@@ -91,6 +146,14 @@ impl TestValidatorGenesis {
         self
     }
 
+    /// Keep the ledger directory when the `TestValidator` is dropped.
+    ///
+    /// Defaults to `true` when a `ledger_path` is provided, and `false` for a temporary ledger.
+    pub fn preserve_ledger(&mut self, preserve_ledger: bool) -> &mut Self {
+        self.preserve_ledger = Some(preserve_ledger);
+        self
+    }
+
     /// Check if a given TestValidator ledger has already been initialized
     pub fn ledger_exists(ledger_path: &Path) -> bool {
         ledger_path.join("vote-account-keypair.json").exists()
@@ -106,6 +169,33 @@ impl TestValidatorGenesis {
         self
     }
 
+    /// Create the genesis config for `cluster_type`, `ClusterType::Development` by default.
+    ///
+    /// Every feature is active at genesis for `ClusterType::Development`. For the other cluster
+    /// types features start inactive, as on a real cluster at genesis.
+    pub fn cluster_type(&mut self, cluster_type: ClusterType) -> &mut Self {
+        self.cluster_type = Some(cluster_type);
+        self
+    }
+
+    /// Activate the feature `feature_id` at genesis, whatever the cluster type
+    pub fn activate_feature(&mut self, feature_id: Pubkey) -> &mut Self {
+        self.set_feature(feature_id, true)
+    }
+
+    /// Leave the feature `feature_id` inactive, even for `ClusterType::Development`
+    pub fn deactivate_feature(&mut self, feature_id: Pubkey) -> &mut Self {
+        self.set_feature(feature_id, false)
+    }
+
+    fn set_feature(&mut self, feature_id: Pubkey, active: bool) -> &mut Self {
+        if !FEATURE_NAMES.contains_key(&feature_id) {
+            warn!("Unknown feature id: {}", feature_id);
+        }
+        self.features.insert(feature_id, active);
+        self
+    }
+
     pub fn rent(&mut self, rent: Rent) -> &mut Self {
         self.rent = rent;
         self
@@ -126,11 +216,35 @@ impl TestValidatorGenesis {
         self
     }
 
+    /// Start the validator without a faucet and without funding the mint account at genesis, so
+    /// the only funded keypair is the validator identity
+    pub fn no_faucet(&mut self) -> &mut Self {
+        self.no_faucet = true;
+        self
+    }
+
     pub fn warp_slot(&mut self, warp_slot: Slot) -> &mut Self {
         self.warp_slot = Some(warp_slot);
         self
     }
 
+    /// Boot from the full snapshot archive at `snapshot_archive_path` instead of replaying the
+    /// ledger from genesis, producing blocks on top of the snapshot slot.
+    ///
+    /// The archive is copied into the ledger directory, so it must keep its
+    /// `snapshot-<slot>-<hash>` file name and be the highest snapshot there. Its bank must descend
+    /// from the ledger's genesis and stake the validator identity, such as an archive taken from
+    /// a preserved `ledger_path`, or the validator will never be scheduled as leader.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_snapshot_archive<P: Into<PathBuf>>(
+        &mut self,
+        snapshot_archive_path: P,
+    ) -> &mut Self {
+        self.snapshot_archive_path = Some(snapshot_archive_path.into());
+        self
+    }
+
+    /// Execute BPF programs with the JIT, the default, or in the interpreter if `bpf_jit` is false
     pub fn bpf_jit(&mut self, bpf_jit: bool) -> &mut Self {
         self.no_bpf_jit = !bpf_jit;
         self
@@ -151,11 +265,21 @@ impl TestValidatorGenesis {
         self
     }
 
+    /// Bind the validator's sockets, including JSON RPC and JSON RPC PubSub, to `bind_ip_addr`
+    /// instead of all interfaces
     pub fn bind_ip_addr(&mut self, bind_ip_addr: IpAddr) -> &mut Self {
         self.node_config.bind_ip_addr = bind_ip_addr;
         self
     }
 
+    /// Use `vote_keypair` for the validator's vote account and fund its genesis stake account
+    /// with `stake_lamports`, all but the rent-exempt reserve of which is delegated, instead of
+    /// a fresh vote account with the default stake of 10,000 SAFE
+    pub fn with_vote_account(&mut self, vote_keypair: Keypair, stake_lamports: u64) -> &mut Self {
+        self.vote_account = Some((vote_keypair, stake_lamports));
+        self
+    }
+
     /// Add an account to the test environment
     pub fn add_account(&mut self, address: Pubkey, account: AccountSharedData) -> &mut Self {
         self.accounts.insert(address, account);
@@ -187,13 +311,36 @@ impl TestValidatorGenesis {
         self
     }
 
+    /// Add the accounts in the JSON file at `path` to the test environment
+    ///
+    /// The file holds an object mapping each account address to its `lamports`, `owner`,
+    /// base64-encoded `data`, `executable` and `rent_epoch`.
+    ///
+    /// This function panics if the file can't be read or an account is malformed.
+    pub fn add_accounts_from_json(&mut self, path: &Path) -> &mut Self {
+        let file = File::open(path)
+            .unwrap_or_else(|err| panic!("Unable to open {}: {}", path.display(), err));
+        let accounts: BTreeMap<String, serde_json::Value> = serde_json::from_reader(file)
+            .unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err));
+        for (address, account) in accounts {
+            let (address, account) = parse_json_account(&address, account).unwrap_or_else(|err| {
+                panic!("Invalid account {} in {}: {}", address, path.display(), err)
+            });
+            self.add_account(address, account);
+        }
+        self
+    }
+
     /// Add an account to the test environment with the account data in the provided `filename`
+    ///
+    /// The account's `rent_epoch` is 0 unless `rent_epoch` is provided.
     pub fn add_account_with_file_data(
         &mut self,
         address: Pubkey,
         lamports: u64,
         owner: Pubkey,
         filename: &str,
+        rent_epoch: Option<Epoch>,
     ) -> &mut Self {
         self.add_account(
             address,
@@ -206,19 +353,22 @@ impl TestValidatorGenesis {
                 ),
                 owner,
                 executable: false,
-                rent_epoch: 0,
+                rent_epoch: rent_epoch.unwrap_or_default(),
             }),
         )
     }
 
     /// Add an account to the test environment with the account data in the provided as a base 64
     /// string
+    ///
+    /// The account's `rent_epoch` is 0 unless `rent_epoch` is provided.
     pub fn add_account_with_base64_data(
         &mut self,
         address: Pubkey,
         lamports: u64,
         owner: Pubkey,
         data_base64: &str,
+        rent_epoch: Option<Epoch>,
     ) -> &mut Self {
         self.add_account(
             address,
@@ -228,7 +378,7 @@ impl TestValidatorGenesis {
                     .unwrap_or_else(|err| panic!("Failed to base64 decode: {}", err)),
                 owner,
                 executable: false,
-                rent_epoch: 0,
+                rent_epoch: rent_epoch.unwrap_or_default(),
             }),
         )
     }
@@ -249,10 +399,34 @@ impl TestValidatorGenesis {
         self
     }
 
+    /// Add a list of BPF programs to the test environment, see `add_program`.
+    ///
+    /// This function panics if a program id is repeated or was already added.
+    pub fn add_programs(&mut self, programs: &[(&str, Pubkey)]) -> &mut Self {
+        let programs = programs
+            .iter()
+            .map(|(program_name, program_id)| ProgramInfo {
+                program_id: *program_id,
+                loader: solana_sdk::bpf_loader::id(),
+                program_path: solana_program_test::find_file(&format!("{}.so", program_name))
+                    .unwrap_or_else(|| panic!("Unable to locate program {}", program_name)),
+            })
+            .collect::<Vec<_>>();
+        self.add_programs_with_path(&programs)
+    }
+
     /// Add a list of programs to the test environment.
-    ///pub fn add_programs_with_path<'a>(&'a mut self, programs: &[ProgramInfo]) -> &'a mut Self {
+    ///
+    /// This function panics if a program id is repeated or was already added.
     pub fn add_programs_with_path(&mut self, programs: &[ProgramInfo]) -> &mut Self {
         for program in programs {
+            if self
+                .programs
+                .iter()
+                .any(|existing| existing.program_id == program.program_id)
+            {
+                panic!("Program {} added more than once", program.program_id);
+            }
             self.programs.push(program.clone());
         }
         self
@@ -271,14 +445,27 @@ impl TestValidatorGenesis {
     /// Start a test validator
     ///
     /// Returns a new `TestValidator` as well as the keypair for the mint account that will receive tokens
-    /// created at genesis.
+    /// created at genesis. If `no_faucet` is set, the validator identity keypair is returned
+    /// instead, as the mint account is left unfunded.
     ///
     /// This function panics on initialization failure.
     pub fn start(&self) -> (TestValidator, Keypair) {
         let mint_keypair = Keypair::new();
-        TestValidator::start(mint_keypair.pubkey(), self)
-            .map(|test_validator| (test_validator, mint_keypair))
-            .expect("Test validator failed to start")
+        let test_validator = TestValidator::start(mint_keypair.pubkey(), self)
+            .expect("Test validator failed to start");
+        if self.no_faucet {
+            let validator_identity = read_keypair_file(
+                test_validator
+                    .ledger_path
+                    .join("validator-keypair.json")
+                    .to_str()
+                    .unwrap(),
+            )
+            .expect("Failed to read validator identity keypair");
+            (test_validator, validator_identity)
+        } else {
+            (test_validator, mint_keypair)
+        }
     }
 }
 
@@ -287,6 +474,7 @@ pub struct TestValidator {
     preserve_ledger: bool,
     rpc_pubsub_url: String,
     rpc_url: String,
+    rpc_addrs: (SocketAddr, SocketAddr), // (JsonRpc, JsonRpcPubSub)
     tpu: SocketAddr,
     gossip: SocketAddr,
     validator: Option<Validator>,
@@ -306,6 +494,10 @@ impl TestValidator {
                 exemption_threshold: 1.0,
                 ..Rent::default()
             })
+            .rpc_config(JsonRpcConfig {
+                enable_rpc_transaction_history: true,
+                ..JsonRpcConfig::default()
+            })
             .faucet_addr(faucet_addr)
             .start_with_mint_address(mint_address)
             .expect("validator start failed")
@@ -327,6 +519,10 @@ impl TestValidator {
                 exemption_threshold: 1.0,
                 ..Rent::default()
             })
+            .rpc_config(JsonRpcConfig {
+                enable_rpc_transaction_history: true,
+                ..JsonRpcConfig::default()
+            })
             .faucet_addr(faucet_addr)
             .start_with_mint_address(mint_address)
             .expect("validator start failed")
@@ -343,11 +539,18 @@ impl TestValidator {
         config: &TestValidatorGenesis,
     ) -> Result<PathBuf, Box<dyn std::error::Error>> {
         let validator_identity = Keypair::new();
-        let validator_vote_account = Keypair::new();
+        let new_vote_account = Keypair::new();
+        let (validator_vote_account, validator_stake_lamports) = match &config.vote_account {
+            Some((vote_keypair, stake_lamports)) => (vote_keypair, *stake_lamports),
+            None => (&new_vote_account, sol_to_lamports(10_000.)),
+        };
         let validator_stake_account = Keypair::new();
         let validator_identity_lamports = sol_to_lamports(33_406_471.);
-        let validator_stake_lamports = sol_to_lamports(10_000.);
-        let mint_lamports = sol_to_lamports(500.);
+        let mint_lamports = if config.no_faucet {
+            0
+        } else {
+            sol_to_lamports(500.)
+        };
 
         let mut accounts = config.accounts.clone();
         for (address, account) in solana_program_test::programs::spl_programs(&config.rent) {
@@ -377,12 +580,30 @@ impl TestValidator {
             validator_identity_lamports,
             config.fee_rate_governor.clone(),
             config.rent,
-            solana_sdk::genesis_config::ClusterType::Development,
+            config.cluster_type.unwrap_or(ClusterType::Development),
             accounts.into_iter().collect(),
         );
         genesis_config.epoch_schedule = config
             .epoch_schedule
             .unwrap_or_else(EpochSchedule::without_warmup);
+        for (feature_id, active) in &config.features {
+            if *active {
+                genesis_config.accounts.insert(
+                    *feature_id,
+                    Account::from(feature::create_account(
+                        &Feature {
+                            activated_at: Some(0),
+                        },
+                        genesis_config
+                            .rent
+                            .minimum_balance(Feature::size_of())
+                            .max(1),
+                    )),
+                );
+            } else {
+                genesis_config.accounts.remove(feature_id);
+            }
+        }
 
         let ledger_path = match &config.ledger_path {
             None => create_new_tmp_ledger!(&genesis_config).0,
@@ -417,7 +638,7 @@ impl TestValidator {
         assert!(!TestValidatorGenesis::ledger_exists(&ledger_path));
 
         write_keypair_file(
-            &validator_vote_account,
+            validator_vote_account,
             ledger_path
                 .join("vote-account-keypair.json")
                 .to_str()
@@ -427,13 +648,60 @@ impl TestValidator {
         Ok(ledger_path)
     }
 
+    /// Copy a snapshot archive into `ledger_path`, where the validator looks for the snapshot to
+    /// boot from
+    fn install_snapshot_archive(
+        ledger_path: &Path,
+        snapshot_archive_path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file_name = snapshot_archive_path.file_name().ok_or_else(|| {
+            format!(
+                "Invalid snapshot archive path: {}",
+                snapshot_archive_path.display()
+            )
+        })?;
+        let installed_path = ledger_path.join(file_name);
+        if !installed_path.exists() {
+            fs::copy(snapshot_archive_path, &installed_path).map_err(|err| {
+                format!(
+                    "Failed to copy snapshot archive {}: {}",
+                    snapshot_archive_path.display(),
+                    err
+                )
+            })?;
+        }
+
+        let archives = snapshot_utils::get_snapshot_archives(ledger_path);
+        if !archives.iter().any(|(path, _)| *path == installed_path) {
+            return Err(format!(
+                "Not a snapshot archive: {}",
+                snapshot_archive_path.display()
+            )
+            .into());
+        }
+        match archives.first() {
+            Some((path, _)) if *path != installed_path => Err(format!(
+                "Ledger {} already has a newer snapshot archive: {}",
+                ledger_path.display(),
+                path.display()
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
     /// Starts a TestValidator at the provided ledger directory
     fn start(
         mint_address: Pubkey,
         config: &TestValidatorGenesis,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let preserve_ledger = config.ledger_path.is_some();
+        let preserve_ledger = config
+            .preserve_ledger
+            .unwrap_or_else(|| config.ledger_path.is_some());
         let ledger_path = TestValidator::initialize_ledger(mint_address, config)?;
+        if let Some(snapshot_archive_path) = &config.snapshot_archive_path {
+            TestValidator::install_snapshot_archive(&ledger_path, snapshot_archive_path)?;
+        }
 
         let validator_identity =
             read_keypair_file(ledger_path.join("validator-keypair.json").to_str().unwrap())?;
@@ -454,15 +722,27 @@ impl TestValidator {
             node.info.rpc = SocketAddr::new(node.info.gossip.ip(), rpc);
             node.info.rpc_pubsub = SocketAddr::new(node.info.gossip.ip(), rpc_pubsub);
         }
+        // RPC bound to a single interface is only reachable through it
+        if !config.node_config.bind_ip_addr.is_unspecified() {
+            node.info.rpc.set_ip(config.node_config.bind_ip_addr);
+            node.info.rpc_pubsub.set_ip(config.node_config.bind_ip_addr);
+        }
 
         let vote_account_address = validator_vote_account.pubkey();
         let rpc_url = format!("http://{}", node.info.rpc);
         let rpc_pubsub_url = format!("ws://{}/", node.info.rpc_pubsub);
+        let rpc_addrs = (
+            SocketAddr::new(config.node_config.bind_ip_addr, node.info.rpc.port()),
+            SocketAddr::new(config.node_config.bind_ip_addr, node.info.rpc_pubsub.port()),
+        );
         let tpu = node.info.tpu;
         let gossip = node.info.gossip;
 
         let mut rpc_config = config.rpc_config.clone();
         rpc_config.identity_pubkey = validator_identity.pubkey();
+        if config.no_faucet {
+            rpc_config.faucet_addr = None;
+        }
 
         {
             let mut authorized_voter_keypairs = config.authorized_voter_keypairs.write().unwrap();
@@ -475,13 +755,7 @@ impl TestValidator {
         }
 
         let validator_config = ValidatorConfig {
-            rpc_addrs: Some((
-                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), node.info.rpc.port()),
-                SocketAddr::new(
-                    IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-                    node.info.rpc_pubsub.port(),
-                ),
-            )),
+            rpc_addrs: Some(rpc_addrs),
             rpc_config,
             accounts_hash_interval_slots: 100,
             account_paths: vec![ledger_path.join("accounts")],
@@ -520,12 +794,27 @@ impl TestValidator {
         discover_cluster(&gossip, 1)
             .map_err(|err| format!("TestValidator startup failed: {:?}", err))?;
 
+        let test_validator = TestValidator {
+            ledger_path,
+            preserve_ledger,
+            rpc_pubsub_url,
+            rpc_url,
+            rpc_addrs,
+            tpu,
+            gossip,
+            validator,
+            vote_account_address,
+        };
+        test_validator.wait_for_healthy(Duration::from_secs(HEALTHY_TIMEOUT_SECS))?;
+
         // This is a hack to delay until the fees are non-zero for test consistency
         // (fees from genesis are zero until the first block with a transaction in it is completed
         //  due to a bug in the Bank)
         {
-            let rpc_client =
-                RpcClient::new_with_commitment(rpc_url.clone(), CommitmentConfig::processed());
+            let rpc_client = RpcClient::new_with_commitment(
+                test_validator.rpc_url(),
+                CommitmentConfig::processed(),
+            );
 
             if let Ok(result) = rpc_client.get_fee_rate_governor() {
                 let fee_rate_governor = result.value;
@@ -548,16 +837,7 @@ impl TestValidator {
             }
         }
 
-        Ok(TestValidator {
-            ledger_path,
-            preserve_ledger,
-            rpc_pubsub_url,
-            rpc_url,
-            tpu,
-            gossip,
-            validator,
-            vote_account_address,
-        })
+        Ok(test_validator)
     }
 
     /// Return the validator's TPU address
@@ -580,6 +860,21 @@ impl TestValidator {
         self.rpc_pubsub_url.clone()
     }
 
+    /// Return the address the validator's JSON RPC service is bound to
+    pub fn rpc_addr(&self) -> &SocketAddr {
+        &self.rpc_addrs.0
+    }
+
+    /// Return the address the validator's JSON RPC PubSub service is bound to
+    pub fn rpc_pubsub_addr(&self) -> &SocketAddr {
+        &self.rpc_addrs.1
+    }
+
+    /// Return the validator's ledger directory
+    pub fn ledger_path(&self) -> &Path {
+        &self.ledger_path
+    }
+
     /// Return the validator's vote account address
     pub fn vote_account_address(&self) -> Pubkey {
         self.vote_account_address
@@ -597,6 +892,122 @@ impl TestValidator {
         (rpc_client, recent_blockhash, fee_calculator)
     }
 
+    /// Block until the validator's JSON RPC service reports it healthy, failing with the last
+    /// `getHealth` error if it does not within `timeout`
+    ///
+    /// `TestValidatorGenesis::start` already waits for this before returning
+    pub fn wait_for_healthy(&self, timeout: Duration) -> ClientResult<()> {
+        let rpc_client = RpcClient::new(self.rpc_url.clone());
+        let start = Instant::now();
+        loop {
+            match rpc_client.get_health() {
+                Ok(()) => return Ok(()),
+                Err(err) if start.elapsed() >= timeout => return Err(err),
+                Err(_) => sleep(Duration::from_millis(DEFAULT_MS_PER_SLOT / 4)),
+            }
+        }
+    }
+
+    /// Block until the validator has processed a slot past the current one, returning the new
+    /// slot
+    pub fn advance_slot(&self) -> ClientResult<Slot> {
+        let rpc_client =
+            RpcClient::new_with_commitment(self.rpc_url.clone(), CommitmentConfig::processed());
+        let slot = rpc_client.get_slot()?;
+        Self::wait_for_slot(&rpc_client, slot + 1)
+    }
+
+    /// Block until the validator has processed `target_slot` or a later slot, returning the slot
+    /// reached
+    pub fn advance_to_slot(&self, target_slot: Slot) -> ClientResult<Slot> {
+        let rpc_client =
+            RpcClient::new_with_commitment(self.rpc_url.clone(), CommitmentConfig::processed());
+        Self::wait_for_slot(&rpc_client, target_slot)
+    }
+
+    /// Request `lamports` for `pubkey` from the validator's faucet and wait for the airdrop
+    /// transaction to be confirmed, returning its signature
+    ///
+    /// Requires the validator to have been started with a faucet, see
+    /// `TestValidatorGenesis::faucet_addr`
+    pub fn airdrop(&self, pubkey: &Pubkey, lamports: u64) -> ClientResult<Signature> {
+        let rpc_client =
+            RpcClient::new_with_commitment(self.rpc_url.clone(), CommitmentConfig::confirmed());
+        let signature = rpc_client.request_airdrop(pubkey, lamports)?;
+        rpc_client.poll_for_signature(&signature)?;
+        if let Some(Err(err)) = rpc_client.get_signature_status(&signature)? {
+            return Err(err.into());
+        }
+        Ok(signature)
+    }
+
+    /// Return the program log messages of the confirmed transaction `signature`, in the order
+    /// they were emitted
+    ///
+    /// Requires `enable_rpc_transaction_history` in the validator's `JsonRpcConfig`, which
+    /// `with_no_fees` and `with_custom_fees` turn on
+    pub fn get_transaction_logs(&self, signature: &Signature) -> ClientResult<Vec<String>> {
+        let rpc_client = RpcClient::new(self.rpc_url.clone());
+        let transaction = rpc_client.get_confirmed_transaction_with_config(
+            signature,
+            RpcConfirmedTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Json),
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )?;
+        transaction
+            .transaction
+            .meta
+            .and_then(|meta| meta.log_messages)
+            .ok_or_else(|| {
+                ClientErrorKind::Custom(format!("no log messages for transaction {}", signature))
+                    .into()
+            })
+    }
+
+    /// Fetch and deserialize the sysvar at `sysvar_id` as of the most recent processed slot
+    pub fn get_sysvar<S: Sysvar>(&self, sysvar_id: &Pubkey) -> ClientResult<S> {
+        if !S::check_id(sysvar_id) {
+            return Err(ClientErrorKind::Custom(format!(
+                "{} is not the requested sysvar",
+                sysvar_id
+            ))
+            .into());
+        }
+        let rpc_client =
+            RpcClient::new_with_commitment(self.rpc_url.clone(), CommitmentConfig::processed());
+        let account = rpc_client.get_account(sysvar_id)?;
+        bincode::deserialize(&account.data).map_err(|err| {
+            ClientErrorKind::Custom(format!("invalid sysvar {}: {}", sysvar_id, err)).into()
+        })
+    }
+
+    /// Return the `Clock` sysvar
+    pub fn get_clock(&self) -> ClientResult<Clock> {
+        self.get_sysvar(&sysvar::clock::id())
+    }
+
+    /// Return the `SlotHashes` sysvar
+    pub fn get_slot_hashes(&self) -> ClientResult<SlotHashes> {
+        self.get_sysvar(&sysvar::slot_hashes::id())
+    }
+
+    /// Return the `SlotHistory` sysvar, recording which slots up to the current one were rooted
+    /// or processed on this fork
+    pub fn get_slot_history(&self) -> ClientResult<SlotHistory> {
+        self.get_sysvar(&sysvar::slot_history::id())
+    }
+
+    fn wait_for_slot(rpc_client: &RpcClient, target_slot: Slot) -> ClientResult<Slot> {
+        loop {
+            let slot = rpc_client.get_slot()?;
+            if slot >= target_slot {
+                return Ok(slot);
+            }
+            sleep(Duration::from_millis(DEFAULT_MS_PER_SLOT / 4));
+        }
+    }
+
     pub fn join(mut self) {
         if let Some(validator) = self.validator.take() {
             validator.join();
@@ -28,14 +28,14 @@ use crate::{
 };
 use crossbeam_channel::unbounded;
 use safecoin_ledger::{
+    bank_forks_utils,
     blockstore::{Blockstore, CompletedSlotsReceiver},
     blockstore_processor::TransactionStatusSender,
     leader_schedule_cache::LeaderScheduleCache,
 };
 use solana_runtime::{
     accounts_background_service::{
-        AbsRequestHandler, AbsRequestSender, AccountsBackgroundService, SendDroppedBankCallback,
-        SnapshotRequestHandler,
+        AbsRequestHandler, AbsRequestSender, AccountsBackgroundService, SnapshotRequestHandler,
     },
     bank_forks::{BankForks, SnapshotConfig},
     commitment::BlockCommitmentCache,
@@ -232,14 +232,9 @@ impl Tvu {
                 .unwrap_or((None, None))
         };
 
-        let (pruned_banks_sender, pruned_banks_receiver) = unbounded();
-
         // Before replay starts, set the callbacks in each of the banks in BankForks
-        for bank in bank_forks.read().unwrap().banks().values() {
-            bank.set_callback(Some(Box::new(SendDroppedBankCallback::new(
-                pruned_banks_sender.clone(),
-            ))));
-        }
+        let pruned_banks_receiver =
+            bank_forks_utils::send_pruned_banks(&bank_forks.read().unwrap());
 
         let accounts_background_request_sender = AbsRequestSender::new(snapshot_request_sender);
 
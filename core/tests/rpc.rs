@@ -11,18 +11,25 @@ use safecoin_client::{
     rpc_response::{Response, RpcSignatureResult, SlotUpdate},
     tpu_client::{TpuClient, TpuClientConfig},
 };
-use solana_core::{rpc_pubsub::gen_client::Client as PubsubClient, test_validator::TestValidator};
+use safecoin_faucet::faucet::run_local_faucet;
+use solana_core::{
+    rpc_pubsub::gen_client::Client as PubsubClient,
+    test_validator::{TestValidator, TestValidatorGenesis},
+};
+use solana_net_utils::find_available_port_in_range;
 use solana_sdk::{
     commitment_config::CommitmentConfig,
     hash::Hash,
+    native_token::sol_to_lamports,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
+    slot_history::Check,
     system_transaction,
     transaction::Transaction,
 };
 use std::{
     collections::HashSet,
-    net::UdpSocket,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
     sync::{mpsc::channel, Arc},
     thread::sleep,
     time::{Duration, Instant},
@@ -375,6 +382,111 @@ fn test_rpc_subscriptions() {
     }
 }
 
+#[test]
+fn test_test_validator_airdrop() {
+    solana_logger::setup();
+
+    let mint_keypair = Keypair::new();
+    let mint_pubkey = mint_keypair.pubkey();
+    let faucet_addr = run_local_faucet(mint_keypair, None);
+    let test_validator = TestValidator::with_no_fees(mint_pubkey, Some(faucet_addr));
+    let rpc_client =
+        RpcClient::new_with_commitment(test_validator.rpc_url(), CommitmentConfig::confirmed());
+
+    let bob_pubkey = Pubkey::new_unique();
+    let signature = test_validator.airdrop(&bob_pubkey, 42).unwrap();
+    assert_eq!(
+        rpc_client.get_signature_status(&signature).unwrap(),
+        Some(Ok(()))
+    );
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 42);
+}
+
+#[test]
+fn test_test_validator_with_vote_account() {
+    solana_logger::setup();
+
+    let vote_keypair = Keypair::new();
+    let vote_pubkey = vote_keypair.pubkey();
+    let stake_lamports = sol_to_lamports(1_000_000.);
+    let (test_validator, _mint_keypair) = TestValidatorGenesis::default()
+        .with_vote_account(vote_keypair, stake_lamports)
+        .start();
+    assert_eq!(test_validator.vote_account_address(), vote_pubkey);
+
+    let rpc_client = RpcClient::new(test_validator.rpc_url());
+    let vote_accounts = rpc_client.get_vote_accounts().unwrap();
+    let vote_account = vote_accounts
+        .current
+        .iter()
+        .chain(vote_accounts.delinquent.iter())
+        .find(|vote_account| vote_account.vote_pubkey == vote_pubkey.to_string())
+        .unwrap();
+    // The stake account keeps its rent-exempt reserve out of the delegation
+    assert!(vote_account.activated_stake <= stake_lamports);
+    assert!(vote_account.activated_stake > sol_to_lamports(10_000.));
+}
+
+#[test]
+fn test_test_validator_bind_addresses() {
+    solana_logger::setup();
+
+    let bind_ip_addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let rpc_port = find_available_port_in_range(bind_ip_addr, (10_000, 20_000)).unwrap();
+    let (test_validator, _mint_keypair) = TestValidatorGenesis::default()
+        .bind_ip_addr(bind_ip_addr)
+        .rpc_port(rpc_port)
+        .start();
+    assert_eq!(
+        *test_validator.rpc_addr(),
+        SocketAddr::new(bind_ip_addr, rpc_port)
+    );
+    assert_eq!(
+        *test_validator.rpc_pubsub_addr(),
+        SocketAddr::new(bind_ip_addr, rpc_port + 1)
+    );
+    assert_eq!(test_validator.gossip().ip(), bind_ip_addr);
+
+    let rpc_client = RpcClient::new(format!("http://{}", test_validator.rpc_addr()));
+    assert!(rpc_client.get_version().is_ok());
+}
+
+#[test]
+fn test_test_validator_wait_for_healthy() {
+    solana_logger::setup();
+
+    let (test_validator, _mint_keypair) = TestValidatorGenesis::default().start();
+    // `start` only returns once the validator is healthy
+    test_validator
+        .wait_for_healthy(Duration::from_millis(0))
+        .unwrap();
+    RpcClient::new(test_validator.rpc_url())
+        .get_health()
+        .unwrap();
+}
+
+#[test]
+fn test_test_validator_sysvars() {
+    solana_logger::setup();
+
+    let (test_validator, _mint_keypair) = TestValidatorGenesis::default().start();
+    let slot = test_validator.advance_to_slot(3).unwrap();
+
+    let slot_history = test_validator.get_slot_history().unwrap();
+    assert!(slot_history.next_slot > slot);
+    assert_eq!(slot_history.check(0), Check::Found);
+    assert_eq!(slot_history.check(slot), Check::Found);
+    assert_eq!(
+        slot_history.check(slot_history.next_slot + 1),
+        Check::Future
+    );
+    assert!(test_validator.get_clock().unwrap().slot >= slot);
+    assert!(!test_validator.get_slot_hashes().unwrap().is_empty());
+    assert!(test_validator
+        .get_sysvar::<solana_sdk::sysvar::clock::Clock>(&solana_sdk::sysvar::rent::id())
+        .is_err());
+}
+
 #[test]
 fn test_tpu_send_transaction() {
     let mint_keypair = Keypair::new();
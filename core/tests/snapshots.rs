@@ -166,6 +166,7 @@ mod tests {
             None,
             AccountSecondaryIndexes::default(),
             false,
+            None,
         )
         .unwrap();
 
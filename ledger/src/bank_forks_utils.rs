@@ -1,34 +1,385 @@
 use crate::{
     blockstore::Blockstore,
     blockstore_processor::{
-        self, BlockstoreProcessorError, BlockstoreProcessorResult, CacheBlockMetaSender,
-        ProcessOptions, TransactionStatusSender,
+        self, BlockstoreProcessorError, CacheBlockMetaSender, ProcessFromRootResult,
+        ProcessOptions, ReplayReport, TransactionStatusSender,
     },
     entry::VerifyRecyclers,
     leader_schedule_cache::LeaderScheduleCache,
 };
+use crossbeam_channel::unbounded;
 use log::*;
 use solana_runtime::{
-    bank_forks::{BankForks, SnapshotConfig},
-    snapshot_utils,
+    accounts_background_service::{DroppedSlotsReceiver, SendDroppedBankCallback},
+    accounts_index::{
+        AccountIndex, AccountSecondaryIndexes, AccountSecondaryIndexesIncludeExclude,
+    },
+    bank::Bank,
+    bank_forks::{ArchiveFormat, BankForks, SnapshotConfig},
+    inline_spl_token_v2_0,
+    snapshot_package::AccountsPackageSender,
+    snapshot_utils::{self, SnapshotError},
+};
+use solana_sdk::{
+    clock::Slot,
+    genesis_config::{ClusterType, GenesisConfig},
+    hash::Hash,
+    pubkey::Pubkey,
 };
-use solana_sdk::{clock::Slot, genesis_config::GenesisConfig, hash::Hash};
-use std::{fs, path::PathBuf, process, result};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process, result,
+    time::Duration,
+};
+use thiserror::Error;
 
 pub type LoadResult = result::Result<
-    (BankForks, LeaderScheduleCache, Option<(Slot, Hash)>),
+    (
+        BankForks,
+        LeaderScheduleCache,
+        Option<(Slot, Hash)>,
+        LoadStats,
+    ),
     BlockstoreProcessorError,
 >;
 
+#[derive(Error, Debug)]
+pub enum SnapshotLoadError {
+    #[error("no snapshot archive found in {0:?}")]
+    NoSnapshotArchive(PathBuf),
+
+    #[error("account paths not present when booting from snapshot")]
+    MissingAccountPaths,
+
+    #[error("snapshot error: {0}")]
+    SnapshotError(#[from] SnapshotError),
+
+    #[error("snapshot has mismatch: archive: {0:?}, deserialized: {1:?}")]
+    MismatchedSnapshotHash((Slot, Hash), (Slot, Hash)),
+
+    #[error("no snapshot archives at the same slot in {0:?} and {1:?}")]
+    NoSharedSnapshotSlot(PathBuf, PathBuf),
+}
+
+/// The snapshot archives available to `load`, and the one it boots from
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SnapshotSelection {
+    pub available: Vec<(Slot, Hash)>,
+    pub chosen: Option<(Slot, Hash)>,
+}
+
+/// Report which snapshot archives `load` considers for `snapshot_config`, highest slot first,
+/// and which of them it boots from
+pub fn snapshot_selection(snapshot_config: &SnapshotConfig) -> SnapshotSelection {
+    let available: Vec<_> =
+        snapshot_utils::get_snapshot_archives(&snapshot_config.snapshot_package_output_path)
+            .into_iter()
+            .map(|(_, (slot, hash, _))| (slot, hash))
+            .collect();
+    let chosen = available.first().copied();
+    SnapshotSelection { available, chosen }
+}
+
+/// The highest snapshot archive in `snapshot_output_dir`. When archives of several formats exist
+/// at that slot, the one in `preferred_archive_format` is chosen if there is one.
+pub fn highest_snapshot_archive(
+    snapshot_output_dir: &Path,
+    preferred_archive_format: Option<ArchiveFormat>,
+) -> Option<(PathBuf, (Slot, Hash, ArchiveFormat))> {
+    let mut archives = snapshot_utils::get_snapshot_archives(snapshot_output_dir);
+    let highest_slot = (archives.first()?.1).0;
+    let preferred = preferred_archive_format.and_then(|preferred_archive_format| {
+        archives
+            .iter()
+            .take_while(|(_, (slot, _, _))| *slot == highest_slot)
+            .position(|(_, (_, _, archive_format))| *archive_format == preferred_archive_format)
+    });
+    Some(archives.swap_remove(preferred.unwrap_or(0)))
+}
+
+/// Rough rate at which an archive of `archive_format` is unpacked and rebuilt into a bank, in
+/// archive bytes per second. Decompression dominates, so slower codecs restore slower.
+fn restore_bytes_per_second(archive_format: ArchiveFormat) -> u64 {
+    const MB: u64 = 1024 * 1024;
+    match archive_format {
+        ArchiveFormat::TarBzip2 => 15 * MB,
+        ArchiveFormat::TarGzip => 60 * MB,
+        ArchiveFormat::TarZstd => 150 * MB,
+        ArchiveFormat::Tar => 300 * MB,
+    }
+}
+
+/// Estimate how long `load` takes to restore the snapshot archive at `archive_path`, from the
+/// size of the archive and the typical unpack throughput of `archive_format`. Zero if the archive
+/// cannot be read.
+///
+/// This is a coarse estimate for capacity planning, not a guarantee: actual restore time depends
+/// on the disks, the CPU and the number of accounts in the snapshot.
+pub fn estimate_restore_duration(archive_path: &Path, archive_format: ArchiveFormat) -> Duration {
+    fs::metadata(archive_path)
+        .map(|metadata| {
+            Duration::from_secs_f64(
+                metadata.len() as f64 / restore_bytes_per_second(archive_format) as f64,
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// The secondary account indexes `load` builds, and the program ids each of them covers
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EffectiveAccountIndexes {
+    /// Accounts are indexed by owning program id
+    pub program_id: bool,
+    /// Token accounts are indexed by token owner
+    pub token_owner: bool,
+    /// Token accounts are indexed by mint
+    pub token_mint: bool,
+    /// Token program ids whose accounts the token owner and mint indexes cover
+    pub token_program_ids: Vec<Pubkey>,
+    /// Keys included in, or excluded from, the active indexes
+    pub keys: Option<AccountSecondaryIndexesIncludeExclude>,
+}
+
+impl EffectiveAccountIndexes {
+    pub fn new(account_indexes: &AccountSecondaryIndexes) -> Self {
+        let token_owner = account_indexes.contains(&AccountIndex::SafeTokenOwner);
+        let token_mint = account_indexes.contains(&AccountIndex::SafeTokenMint);
+        let token_program_ids = if token_owner || token_mint {
+            vec![inline_spl_token_v2_0::id()]
+        } else {
+            vec![]
+        };
+        Self {
+            program_id: account_indexes.contains(&AccountIndex::ProgramId),
+            token_owner,
+            token_mint,
+            token_program_ids,
+            keys: if account_indexes.is_empty() {
+                None
+            } else {
+                account_indexes.keys.clone()
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.program_id || self.token_owner || self.token_mint)
+    }
+}
+
+/// What `load` did besides building the bank forks
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LoadStats {
+    pub account_indexes: EffectiveAccountIndexes,
+    /// Hash of bank 0 computed from the genesis config, when the ledger was processed from
+    /// genesis rather than from a snapshot. Nodes of the same cluster must agree on it.
+    pub genesis_bank_hash: Option<Hash>,
+    /// Account storage found under each account path after restoring from a snapshot. Empty
+    /// when the ledger was processed from genesis.
+    pub account_path_usage: HashMap<PathBuf, AccountPathUsage>,
+    /// Set when `ProcessOptions::collect_replay_report` is
+    pub replay_report: Option<ReplayReport>,
+    /// Number of accounts in the restored snapshot owned by each of
+    /// `ProcessOptions::count_accounts_by_owner`. Empty when the ledger was processed from
+    /// genesis.
+    pub accounts_by_owner: HashMap<Pubkey, usize>,
+}
+
+/// Account storage held by a single account path
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AccountPathUsage {
+    pub bytes: u64,
+    pub files: usize,
+}
+
+/// Total up the files and bytes under each account path. Paths that cannot be read are
+/// reported with zero usage.
+pub fn account_path_usage(account_paths: &[PathBuf]) -> HashMap<PathBuf, AccountPathUsage> {
+    fn visit(dir: &Path, usage: &mut AccountPathUsage) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Unable to read account path {:?}: {}", dir, err);
+                return;
+            }
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => visit(&entry.path(), usage),
+                Ok(metadata) => {
+                    usage.bytes += metadata.len();
+                    usage.files += 1;
+                }
+                Err(_) => (),
+            }
+        }
+    }
+
+    account_paths
+        .iter()
+        .map(|path| {
+            let mut usage = AccountPathUsage::default();
+            visit(path, &mut usage);
+            (path.clone(), usage)
+        })
+        .collect()
+}
+
+/// Count the accounts of `bank` owned by each of `program_ids`, in a single scan of the accounts
+/// db. Program ids that own no account are reported with a count of zero.
+pub fn count_accounts_by_owner(bank: &Bank, program_ids: &[Pubkey]) -> HashMap<Pubkey, usize> {
+    let mut accounts_by_owner: HashMap<Pubkey, usize> = bank.rc.accounts.accounts_db.scan_accounts(
+        &bank.ancestors,
+        |accounts_by_owner: &mut HashMap<Pubkey, usize>, account_tuple| {
+            if let Some((_pubkey, account, _slot)) = account_tuple {
+                if account.lamports > 0 && program_ids.contains(&account.owner) {
+                    *accounts_by_owner.entry(account.owner).or_default() += 1;
+                }
+            }
+        },
+    );
+    for program_id in program_ids {
+        accounts_by_owner.entry(*program_id).or_default();
+    }
+    accounts_by_owner
+}
+
 fn to_loadresult(
-    brp: BlockstoreProcessorResult,
+    brp: ProcessFromRootResult,
     snapshot_hash: Option<(Slot, Hash)>,
+    load_stats: LoadStats,
 ) -> LoadResult {
-    brp.map(|(bank_forks, leader_schedule_cache)| {
-        (bank_forks, leader_schedule_cache, snapshot_hash)
+    brp.map(|(bank_forks, leader_schedule_cache, replay_report)| {
+        let load_stats = LoadStats {
+            replay_report,
+            ..load_stats
+        };
+        (bank_forks, leader_schedule_cache, snapshot_hash, load_stats)
     })
 }
 
+/// Check that each account path exists (creating it if needed), is a writable directory, and
+/// that no two account paths are the same or nested within each other
+pub fn validate_account_paths(account_paths: &[PathBuf]) -> Result<(), BlockstoreProcessorError> {
+    let invalid_path = |path: &Path, reason: String| {
+        BlockstoreProcessorError::InvalidAccountPath(path.to_path_buf(), reason)
+    };
+
+    let mut canonical_paths: Vec<(&PathBuf, PathBuf)> = Vec::with_capacity(account_paths.len());
+    for path in account_paths {
+        fs::create_dir_all(path)
+            .map_err(|err| invalid_path(path, format!("unable to create: {}", err)))?;
+        if !path.is_dir() {
+            return Err(invalid_path(path, "not a directory".to_string()));
+        }
+        tempfile::tempfile_in(path)
+            .map_err(|err| invalid_path(path, format!("not writable: {}", err)))?;
+        let canonical_path = fs::canonicalize(path)
+            .map_err(|err| invalid_path(path, format!("unable to canonicalize: {}", err)))?;
+
+        for (other_path, other_canonical_path) in &canonical_paths {
+            if canonical_path.starts_with(other_canonical_path)
+                || other_canonical_path.starts_with(&canonical_path)
+            {
+                return Err(invalid_path(
+                    path,
+                    format!("overlaps with account path {:?}", other_path),
+                ));
+            }
+        }
+        canonical_paths.push((path, canonical_path));
+    }
+    Ok(())
+}
+
+/// Check that `genesis_config` is the genesis the ledger of `blockstore` was created with, as
+/// recorded in the genesis file of the ledger directory. Passes if there is no genesis file.
+pub fn check_genesis_hash(
+    genesis_config: &GenesisConfig,
+    blockstore: &Blockstore,
+) -> Result<(), BlockstoreProcessorError> {
+    let ledger_genesis_config = match GenesisConfig::load(blockstore.ledger_path()) {
+        Ok(ledger_genesis_config) => ledger_genesis_config,
+        Err(err) => {
+            warn!("Unable to check genesis hash against the ledger: {}", err);
+            return Ok(());
+        }
+    };
+    let genesis_hash = genesis_config.hash();
+    let ledger_genesis_hash = ledger_genesis_config.hash();
+    if genesis_hash != ledger_genesis_hash {
+        return Err(BlockstoreProcessorError::GenesisMismatch(
+            genesis_hash,
+            ledger_genesis_hash,
+        ));
+    }
+    Ok(())
+}
+
+/// Warn if the snapshot at `snapshot_slot` is more than `max_snapshot_age_slots` behind the
+/// highest slot in `blockstore`, or fail if `reject_stale_snapshot` is set
+fn check_snapshot_age(
+    blockstore: &Blockstore,
+    snapshot_slot: Slot,
+    process_options: &ProcessOptions,
+) -> Result<(), BlockstoreProcessorError> {
+    let max_snapshot_age_slots = match process_options.max_snapshot_age_slots {
+        Some(max_snapshot_age_slots) => max_snapshot_age_slots,
+        None => return Ok(()),
+    };
+    let tip_slot = blockstore
+        .slot_meta_iterator(snapshot_slot)
+        .ok()
+        .and_then(|metas| metas.map(|(slot, _meta)| slot).last())
+        .unwrap_or(snapshot_slot);
+    if tip_slot.saturating_sub(snapshot_slot) <= max_snapshot_age_slots {
+        return Ok(());
+    }
+
+    let err =
+        BlockstoreProcessorError::StaleSnapshot(snapshot_slot, tip_slot, max_snapshot_age_slots);
+    if process_options.reject_stale_snapshot {
+        return Err(err);
+    }
+    warn!("{}, replay from it will take a while", err);
+    datapoint_warn!(
+        "bank_forks_utils-stale_snapshot",
+        ("snapshot_slot", snapshot_slot as i64, i64),
+        ("tip_slot", tip_slot as i64, i64),
+    );
+    Ok(())
+}
+
+// Log the native mint account found in a custom genesis config, if any. The genesis bank keeps a
+// native mint owned by the token program as is, and on development and devnet clusters converts
+// one owned by the system program into a token mint, preserving its lamports.
+fn log_genesis_native_mint(genesis_config: &GenesisConfig) {
+    let native_mint = inline_spl_token_v2_0::native_mint::id();
+    if let Some(account) = genesis_config.accounts.get(&native_mint) {
+        let converted_at_genesis = matches!(
+            genesis_config.cluster_type,
+            ClusterType::Development | ClusterType::Devnet
+        );
+        let action = if account.owner == inline_spl_token_v2_0::id() {
+            "keeping it"
+        } else if account.owner == solana_sdk::system_program::id() && converted_at_genesis {
+            "converting it to a token mint"
+        } else {
+            "leaving it unchanged"
+        };
+        info!(
+            "Genesis includes native mint {}: owner {}, {} lamports, {} bytes of data; {}",
+            native_mint,
+            account.owner,
+            account.lamports,
+            account.data.len(),
+            action
+        );
+    }
+}
+
 pub fn load(
     genesis_config: &GenesisConfig,
     blockstore: &Blockstore,
@@ -39,7 +390,16 @@ pub fn load(
     transaction_status_sender: Option<&TransactionStatusSender>,
     cache_block_meta_sender: Option<&CacheBlockMetaSender>,
 ) -> LoadResult {
+    validate_account_paths(&account_paths)?;
+    check_genesis_hash(genesis_config, blockstore)?;
+
+    let effective_account_indexes = EffectiveAccountIndexes::new(&process_options.account_indexes);
+    info!("Account indexes: {:?}", effective_account_indexes);
+
     if let Some(snapshot_config) = snapshot_config.as_ref() {
+        snapshot_config
+            .validate()
+            .map_err(BlockstoreProcessorError::InvalidSnapshotConfig)?;
         info!(
             "Initializing snapshot path: {:?}",
             snapshot_config.snapshot_path
@@ -47,32 +407,34 @@ pub fn load(
         let _ = fs::remove_dir_all(&snapshot_config.snapshot_path);
         fs::create_dir_all(&snapshot_config.snapshot_path)
             .expect("Couldn't create snapshot directory");
+        info!(
+            "Snapshot archives: {:?}",
+            snapshot_selection(snapshot_config)
+        );
 
-        match snapshot_utils::get_highest_snapshot_archive_path(
+        match highest_snapshot_archive(
             &snapshot_config.snapshot_package_output_path,
+            process_options.preferred_archive_format,
         ) {
-            Some((archive_filename, (archive_slot, archive_snapshot_hash, compression))) => {
-                info!("Loading snapshot package: {:?}", archive_filename);
+            Some(archive) => {
+                check_snapshot_age(blockstore, (archive.1).0, &process_options)?;
+                info!(
+                    "Loading snapshot package: {:?}, estimated restore time {:?}",
+                    archive.0,
+                    estimate_restore_duration(&archive.0, (archive.1).2),
+                );
                 // Fail hard here if snapshot fails to load, don't silently continue
-
-                if account_paths.is_empty() {
-                    error!("Account paths not present when booting from snapshot");
-                    process::exit(1);
-                }
-
-                let deserialized_bank = snapshot_utils::bank_from_archive(
-                    &account_paths,
-                    &process_options.frozen_accounts,
-                    &snapshot_config.snapshot_path,
-                    &archive_filename,
-                    compression,
+                let deserialized_bank = bank_from_snapshot_archive(
                     genesis_config,
-                    process_options.debug_keys.clone(),
-                    Some(&crate::builtins::get(process_options.bpf_jit)),
-                    process_options.account_indexes.clone(),
-                    process_options.accounts_db_caching_enabled,
+                    &account_paths,
+                    snapshot_config,
+                    archive,
+                    &process_options,
                 )
-                .expect("Load from snapshot failed");
+                .unwrap_or_else(|err| {
+                    error!("Failed to load snapshot: {}", err);
+                    process::exit(1);
+                });
                 if let Some(shrink_paths) = shrink_paths {
                     deserialized_bank.set_shrink_paths(shrink_paths);
                 }
@@ -82,12 +444,30 @@ pub fn load(
                     deserialized_bank.get_accounts_hash(),
                 );
 
-                if deserialized_snapshot_hash != (archive_slot, archive_snapshot_hash) {
-                    error!(
-                        "Snapshot has mismatch:\narchive: {:?}\ndeserialized: {:?}",
-                        archive_snapshot_hash, deserialized_snapshot_hash
+                datapoint_info!(
+                    "bank_forks_utils-load",
+                    ("loaded_from", "snapshot".to_string(), String),
+                    ("snapshot_slot", deserialized_snapshot_hash.0 as i64, i64),
+                );
+
+                let account_path_usage = account_path_usage(&account_paths);
+                for (path, usage) in &account_path_usage {
+                    info!(
+                        "Account path {:?}: {} storage files, {} bytes",
+                        path, usage.files, usage.bytes
                     );
-                    process::exit(1);
+                }
+
+                let accounts_by_owner = if process_options.count_accounts_by_owner.is_empty() {
+                    HashMap::new()
+                } else {
+                    count_accounts_by_owner(
+                        &deserialized_bank,
+                        &process_options.count_accounts_by_owner,
+                    )
+                };
+                for (program_id, count) in &accounts_by_owner {
+                    info!("Accounts owned by {}: {}", program_id, count);
                 }
 
                 return to_loadresult(
@@ -100,6 +480,13 @@ pub fn load(
                         cache_block_meta_sender,
                     ),
                     Some(deserialized_snapshot_hash),
+                    LoadStats {
+                        account_indexes: effective_account_indexes,
+                        genesis_bank_hash: None,
+                        account_path_usage,
+                        replay_report: None,
+                        accounts_by_owner,
+                    },
                 );
             }
             None => info!("No snapshot package available"),
@@ -109,14 +496,805 @@ pub fn load(
     }
 
     info!("Processing ledger from genesis");
-    to_loadresult(
-        blockstore_processor::process_blockstore(
+    log_genesis_native_mint(genesis_config);
+    datapoint_info!(
+        "bank_forks_utils-load",
+        ("loaded_from", "genesis".to_string(), String),
+    );
+    let (bank_forks, leader_schedule_cache, genesis_bank_hash, replay_report) =
+        blockstore_processor::process_blockstore_from_genesis(
             &genesis_config,
             &blockstore,
             account_paths,
             process_options,
             cache_block_meta_sender,
-        ),
+        )?;
+    Ok((
+        bank_forks,
+        leader_schedule_cache,
+        None,
+        LoadStats {
+            account_indexes: effective_account_indexes,
+            genesis_bank_hash: Some(genesis_bank_hash),
+            account_path_usage: HashMap::new(),
+            replay_report,
+            accounts_by_owner: HashMap::new(),
+        },
+    ))
+}
+
+/// Deserialize the highest snapshot archive into a root bank, without replaying or even
+/// opening a blockstore
+pub fn load_snapshot_bank(
+    genesis_config: &GenesisConfig,
+    account_paths: &[PathBuf],
+    snapshot_config: &SnapshotConfig,
+    process_options: &ProcessOptions,
+) -> result::Result<Bank, SnapshotLoadError> {
+    let archive = highest_snapshot_archive(
+        &snapshot_config.snapshot_package_output_path,
+        process_options.preferred_archive_format,
+    )
+    .ok_or_else(|| {
+        SnapshotLoadError::NoSnapshotArchive(snapshot_config.snapshot_package_output_path.clone())
+    })?;
+    load_snapshot_archive(
+        genesis_config,
+        account_paths,
+        snapshot_config,
+        archive,
+        process_options,
+    )
+}
+
+/// Package a snapshot of the root bank of `bank_forks` and send it to `accounts_package_sender`
+///
+/// For callers that drive snapshot packaging themselves after `load`, such as tools that produce a
+/// snapshot right after replay. The received package can be archived with
+/// `snapshot_utils::process_accounts_package_pre` and `snapshot_utils::archive_snapshot_package`.
+pub fn package_root_bank_snapshot(
+    bank_forks: &BankForks,
+    snapshot_config: &SnapshotConfig,
+    accounts_package_sender: &AccountsPackageSender,
+) -> snapshot_utils::Result<()> {
+    let root_bank = bank_forks.root_bank();
+    root_bank.force_flush_accounts_cache();
+    root_bank.clean_accounts(true);
+    root_bank.update_accounts_hash();
+    let status_cache_slot_deltas = root_bank.src.slot_deltas(&root_bank.src.roots());
+    snapshot_utils::snapshot_bank(
+        &root_bank,
+        status_cache_slot_deltas,
+        accounts_package_sender,
+        &snapshot_config.snapshot_path,
+        &snapshot_config.snapshot_package_output_path,
+        snapshot_config.snapshot_version,
+        &snapshot_config.archive_format,
         None,
     )
 }
+
+/// Send the slot of each bank later dropped from `bank_forks`, or from any bank forks it grows
+/// into, to the returned receiver, for `drain_pruned_banks` to purge
+///
+/// Banks dropped during `load` purge their own slot, because nothing drains a channel yet. An
+/// embedder that keeps replaying after `load` should call this before pruning any bank, so that
+/// the purge happens on its own thread rather than inside whichever thread drops the last
+/// reference to the bank.
+pub fn send_pruned_banks(bank_forks: &BankForks) -> DroppedSlotsReceiver {
+    let (pruned_banks_sender, pruned_banks_receiver) = unbounded();
+    // Child banks inherit the drop callback of their parent
+    for bank in bank_forks.banks().values() {
+        bank.set_callback(Some(Box::new(SendDroppedBankCallback::new(
+            pruned_banks_sender.clone(),
+        ))));
+    }
+    pruned_banks_receiver
+}
+
+/// Purge the accounts of every pruned slot received so far on `pruned_banks_receiver` through
+/// `bank`, returning the number of slots purged. Does not block.
+///
+/// `bank` must still be alive, typically the current root bank, since a dropped bank can no
+/// longer be used to reach the accounts db. Call this periodically, and without holding the
+/// `BankForks` write lock, since dropping banks may itself wait on it.
+pub fn drain_pruned_banks(pruned_banks_receiver: &DroppedSlotsReceiver, bank: &Bank) -> usize {
+    pruned_banks_receiver
+        .try_iter()
+        .map(|pruned_slot| bank.rc.accounts.purge_slot(pruned_slot))
+        .count()
+}
+
+/// Bank fields that differ between two snapshots at the same slot, see
+/// `compare_snapshot_bank_hashes`
+#[derive(Debug, Default, PartialEq)]
+pub struct SnapshotBankDiff {
+    pub slot: Slot,
+    /// Name of each differing field, with its value in the first and in the second snapshot
+    pub mismatches: Vec<(&'static str, String, String)>,
+}
+
+impl SnapshotBankDiff {
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Deserialize the highest snapshot archives at the same slot in both snapshot configs, each into
+/// its own account paths, and compare the resulting banks
+pub fn compare_snapshot_bank_hashes(
+    genesis_config: &GenesisConfig,
+    (snapshot_config_a, account_paths_a): (&SnapshotConfig, &[PathBuf]),
+    (snapshot_config_b, account_paths_b): (&SnapshotConfig, &[PathBuf]),
+    process_options: &ProcessOptions,
+) -> result::Result<SnapshotBankDiff, SnapshotLoadError> {
+    let archives_a =
+        snapshot_utils::get_snapshot_archives(&snapshot_config_a.snapshot_package_output_path);
+    let archives_b =
+        snapshot_utils::get_snapshot_archives(&snapshot_config_b.snapshot_package_output_path);
+    // Archives are sorted by descending slot
+    let (archive_a, archive_b) = archives_a
+        .into_iter()
+        .find_map(|archive_a| {
+            archives_b
+                .iter()
+                .find(|archive_b| (archive_b.1).0 == (archive_a.1).0)
+                .cloned()
+                .map(|archive_b| (archive_a, archive_b))
+        })
+        .ok_or_else(|| {
+            SnapshotLoadError::NoSharedSnapshotSlot(
+                snapshot_config_a.snapshot_package_output_path.clone(),
+                snapshot_config_b.snapshot_package_output_path.clone(),
+            )
+        })?;
+
+    let bank_a = load_snapshot_archive(
+        genesis_config,
+        account_paths_a,
+        snapshot_config_a,
+        archive_a,
+        process_options,
+    )?;
+    let bank_b = load_snapshot_archive(
+        genesis_config,
+        account_paths_b,
+        snapshot_config_b,
+        archive_b,
+        process_options,
+    )?;
+
+    let fields = vec![
+        (
+            "bank_hash",
+            bank_a.hash().to_string(),
+            bank_b.hash().to_string(),
+        ),
+        (
+            "accounts_hash",
+            bank_a.get_accounts_hash().to_string(),
+            bank_b.get_accounts_hash().to_string(),
+        ),
+        (
+            "parent_hash",
+            bank_a.parent_hash().to_string(),
+            bank_b.parent_hash().to_string(),
+        ),
+        (
+            "capitalization",
+            bank_a.capitalization().to_string(),
+            bank_b.capitalization().to_string(),
+        ),
+        (
+            "transaction_count",
+            bank_a.transaction_count().to_string(),
+            bank_b.transaction_count().to_string(),
+        ),
+    ];
+    Ok(SnapshotBankDiff {
+        slot: bank_a.slot(),
+        mismatches: fields.into_iter().filter(|(_, a, b)| a != b).collect(),
+    })
+}
+
+fn load_snapshot_archive(
+    genesis_config: &GenesisConfig,
+    account_paths: &[PathBuf],
+    snapshot_config: &SnapshotConfig,
+    archive: (PathBuf, (Slot, Hash, ArchiveFormat)),
+    process_options: &ProcessOptions,
+) -> result::Result<Bank, SnapshotLoadError> {
+    fs::create_dir_all(&snapshot_config.snapshot_path).map_err(SnapshotError::from)?;
+    info!("Loading snapshot package: {:?}", archive.0);
+    bank_from_snapshot_archive(
+        genesis_config,
+        account_paths,
+        snapshot_config,
+        archive,
+        process_options,
+    )
+}
+
+fn bank_from_snapshot_archive(
+    genesis_config: &GenesisConfig,
+    account_paths: &[PathBuf],
+    snapshot_config: &SnapshotConfig,
+    (archive_filename, (archive_slot, archive_snapshot_hash, compression)): (
+        PathBuf,
+        (Slot, Hash, ArchiveFormat),
+    ),
+    process_options: &ProcessOptions,
+) -> result::Result<Bank, SnapshotLoadError> {
+    if account_paths.is_empty() {
+        return Err(SnapshotLoadError::MissingAccountPaths);
+    }
+
+    let unpack_path = match &process_options.snapshot_staging_dir {
+        Some(snapshot_staging_dir) => {
+            fs::create_dir_all(snapshot_staging_dir).map_err(SnapshotError::from)?;
+            snapshot_staging_dir
+        }
+        None => &snapshot_config.snapshot_path,
+    };
+    let deserialized_bank = snapshot_utils::bank_from_archive(
+        account_paths,
+        &process_options.frozen_accounts,
+        unpack_path,
+        &archive_filename,
+        compression,
+        genesis_config,
+        process_options.debug_keys.clone(),
+        Some(&crate::builtins::get(process_options.bpf_jit)),
+        process_options.account_indexes.clone(),
+        process_options.accounts_db_caching_enabled,
+        process_options.generate_index_progress.as_deref(),
+    )?;
+
+    let deserialized_snapshot_hash = (
+        deserialized_bank.slot(),
+        deserialized_bank.get_accounts_hash(),
+    );
+    if deserialized_snapshot_hash != (archive_slot, archive_snapshot_hash) {
+        return Err(SnapshotLoadError::MismatchedSnapshotHash(
+            (archive_slot, archive_snapshot_hash),
+            deserialized_snapshot_hash,
+        ));
+    }
+
+    Ok(deserialized_bank)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blockstore::make_many_slot_entries,
+        create_new_tmp_ledger,
+        genesis_utils::{create_genesis_config, GenesisConfigInfo},
+        get_tmp_ledger_path,
+    };
+    use solana_sdk::signature::Keypair;
+    use std::sync::{mpsc::channel, Arc};
+
+    // Archive a snapshot of `bank` into a new directory under `temp_dir`
+    fn archive_bank_snapshot(bank: Bank, temp_dir: &Path, name: &str) -> SnapshotConfig {
+        let output_path = temp_dir.join(name);
+        let snapshot_config = SnapshotConfig {
+            snapshot_interval_slots: 100,
+            snapshot_package_output_path: output_path.clone(),
+            snapshot_path: output_path.join("snapshot"),
+            archive_format: ArchiveFormat::TarBzip2,
+            snapshot_version: snapshot_utils::SnapshotVersion::default(),
+        };
+        fs::create_dir_all(&snapshot_config.snapshot_path).unwrap();
+        bank.freeze();
+        bank.squash();
+        let bank_forks = BankForks::new(bank);
+        let (accounts_package_sender, accounts_package_receiver) = channel();
+        package_root_bank_snapshot(&bank_forks, &snapshot_config, &accounts_package_sender)
+            .unwrap();
+        let accounts_package = accounts_package_receiver.try_recv().unwrap();
+        let accounts_package = snapshot_utils::process_accounts_package_pre(accounts_package, None);
+        snapshot_utils::archive_snapshot_package(&accounts_package).unwrap();
+        // Deserializing unpacks into a fresh snapshot directory
+        fs::remove_dir_all(&snapshot_config.snapshot_path).unwrap();
+        snapshot_config
+    }
+
+    fn new_bank_at_slot_1(genesis_config: &GenesisConfig, transfer_from: Option<&Keypair>) -> Bank {
+        let bank0 = Arc::new(Bank::new(genesis_config));
+        let bank1 = Bank::new_from_parent(&bank0, &Pubkey::default(), 1);
+        if let Some(keypair) = transfer_from {
+            bank1.transfer(1, keypair, &Pubkey::new_unique()).unwrap();
+        }
+        bank1
+    }
+
+    #[test]
+    fn test_validate_account_paths() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let accounts0 = temp_dir.path().join("accounts0");
+        let accounts1 = temp_dir.path().join("accounts1");
+
+        assert!(validate_account_paths(&[]).is_ok());
+        assert!(validate_account_paths(&[accounts0.clone(), accounts1.clone()]).is_ok());
+        assert!(accounts0.is_dir());
+        assert!(accounts1.is_dir());
+
+        // Duplicate paths
+        assert!(matches!(
+            validate_account_paths(&[accounts0.clone(), accounts0.clone()]),
+            Err(BlockstoreProcessorError::InvalidAccountPath(..))
+        ));
+
+        // Nested paths, in either order
+        let nested = accounts0.join("nested");
+        assert!(validate_account_paths(&[accounts0.clone(), nested.clone()]).is_err());
+        assert!(validate_account_paths(&[nested, accounts0]).is_err());
+
+        // A file is not a directory
+        let file = temp_dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        assert!(validate_account_paths(&[file]).is_err());
+    }
+
+    #[test]
+    fn test_account_path_usage() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let accounts0 = temp_dir.path().join("accounts0");
+        let accounts1 = temp_dir.path().join("accounts1");
+        let missing = temp_dir.path().join("missing");
+        fs::create_dir_all(accounts0.join("nested")).unwrap();
+        fs::create_dir_all(&accounts1).unwrap();
+        fs::write(accounts0.join("0.0"), vec![0; 10]).unwrap();
+        fs::write(accounts0.join("nested").join("1.1"), vec![0; 5]).unwrap();
+
+        let usage = account_path_usage(&[accounts0.clone(), accounts1.clone(), missing.clone()]);
+        assert_eq!(usage.len(), 3);
+        assert_eq!(
+            usage[&accounts0],
+            AccountPathUsage {
+                bytes: 15,
+                files: 2
+            }
+        );
+        assert_eq!(usage[&accounts1], AccountPathUsage::default());
+        assert_eq!(usage[&missing], AccountPathUsage::default());
+    }
+
+    #[test]
+    fn test_package_root_bank_snapshot() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let (ledger_path, _blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        let (bank_forks, _leader_schedule_cache) = blockstore_processor::process_blockstore(
+            &genesis_config,
+            &blockstore,
+            Vec::new(),
+            ProcessOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snapshot_config = SnapshotConfig {
+            snapshot_interval_slots: 100,
+            snapshot_package_output_path: temp_dir.path().to_path_buf(),
+            snapshot_path: temp_dir.path().join("snapshot"),
+            archive_format: ArchiveFormat::TarBzip2,
+            snapshot_version: snapshot_utils::SnapshotVersion::default(),
+        };
+        let (accounts_package_sender, accounts_package_receiver) = channel();
+        package_root_bank_snapshot(&bank_forks, &snapshot_config, &accounts_package_sender)
+            .unwrap();
+
+        let accounts_package = accounts_package_receiver.try_recv().unwrap();
+        assert_eq!(accounts_package.slot, 0);
+        let accounts_package = snapshot_utils::process_accounts_package_pre(accounts_package, None);
+        snapshot_utils::archive_snapshot_package(&accounts_package).unwrap();
+        assert_eq!(
+            snapshot_selection(&snapshot_config).chosen,
+            Some((0, bank_forks.root_bank().get_accounts_hash()))
+        );
+    }
+
+    #[test]
+    fn test_drain_pruned_banks() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let bank0 = Bank::new(&genesis_config);
+        bank0.freeze();
+        let mut bank_forks = BankForks::new(bank0);
+        let pruned_banks_receiver = send_pruned_banks(&bank_forks);
+
+        let bank1 = Bank::new_from_parent(&bank_forks[0], &Pubkey::default(), 1);
+        let account_key = Pubkey::new_unique();
+        bank1.store_account(
+            &account_key,
+            &solana_sdk::account::AccountSharedData::new(1, 0, &Pubkey::default()),
+        );
+        bank_forks.insert(bank1);
+        let root_bank = bank_forks.root_bank();
+        assert_eq!(drain_pruned_banks(&pruned_banks_receiver, &root_bank), 0);
+
+        drop(bank_forks.remove(1));
+        assert_eq!(drain_pruned_banks(&pruned_banks_receiver, &root_bank), 1);
+        assert_eq!(drain_pruned_banks(&pruned_banks_receiver, &root_bank), 0);
+    }
+
+    #[test]
+    fn test_count_accounts_by_owner() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let bank = Bank::new(&genesis_config);
+        let legacy_token_program = inline_spl_token_v2_0::id();
+        let new_token_program = inline_spl_token_v2_0::new_token_program::id();
+        let other_program = Pubkey::new_unique();
+        for owner in &[
+            legacy_token_program,
+            legacy_token_program,
+            new_token_program,
+        ] {
+            bank.store_account(
+                &Pubkey::new_unique(),
+                &solana_sdk::account::AccountSharedData::new(1, 0, owner),
+            );
+        }
+        // Zero-lamport accounts are dead and not counted
+        bank.store_account(
+            &Pubkey::new_unique(),
+            &solana_sdk::account::AccountSharedData::new(0, 0, &new_token_program),
+        );
+
+        let accounts_by_owner = count_accounts_by_owner(
+            &bank,
+            &[legacy_token_program, new_token_program, other_program],
+        );
+        assert_eq!(accounts_by_owner.len(), 3);
+        assert_eq!(accounts_by_owner[&legacy_token_program], 2);
+        assert_eq!(accounts_by_owner[&new_token_program], 1);
+        assert_eq!(accounts_by_owner[&other_program], 0);
+        assert!(count_accounts_by_owner(&bank, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_compare_snapshot_bank_hashes() {
+        let GenesisConfigInfo {
+            genesis_config,
+            mint_keypair,
+            ..
+        } = create_genesis_config(10_000);
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let account_paths_a = vec![temp_dir.path().join("accounts_a")];
+        let account_paths_b = vec![temp_dir.path().join("accounts_b")];
+        let account_paths_c = vec![temp_dir.path().join("accounts_c")];
+        let process_options = ProcessOptions::default();
+
+        let snapshot_a = archive_bank_snapshot(
+            new_bank_at_slot_1(&genesis_config, None),
+            temp_dir.path(),
+            "a",
+        );
+        let snapshot_b = archive_bank_snapshot(
+            new_bank_at_slot_1(&genesis_config, None),
+            temp_dir.path(),
+            "b",
+        );
+        let snapshot_c = archive_bank_snapshot(
+            new_bank_at_slot_1(&genesis_config, Some(&mint_keypair)),
+            temp_dir.path(),
+            "c",
+        );
+
+        let diff = compare_snapshot_bank_hashes(
+            &genesis_config,
+            (&snapshot_a, &account_paths_a[..]),
+            (&snapshot_b, &account_paths_b[..]),
+            &process_options,
+        )
+        .unwrap();
+        assert_eq!(diff.slot, 1);
+        assert!(diff.is_match());
+
+        let diff = compare_snapshot_bank_hashes(
+            &genesis_config,
+            (&snapshot_b, &account_paths_b[..]),
+            (&snapshot_c, &account_paths_c[..]),
+            &process_options,
+        )
+        .unwrap();
+        assert!(!diff.is_match());
+        let mismatched_fields: Vec<_> = diff.mismatches.iter().map(|(name, ..)| *name).collect();
+        assert!(mismatched_fields.contains(&"bank_hash"));
+        assert!(mismatched_fields.contains(&"accounts_hash"));
+        assert!(!mismatched_fields.contains(&"parent_hash"));
+
+        let empty_snapshot = SnapshotConfig {
+            snapshot_package_output_path: temp_dir.path().join("empty"),
+            snapshot_path: temp_dir.path().join("empty").join("snapshot"),
+            ..snapshot_a.clone()
+        };
+        assert!(matches!(
+            compare_snapshot_bank_hashes(
+                &genesis_config,
+                (&snapshot_a, &account_paths_a[..]),
+                (&empty_snapshot, &account_paths_b[..]),
+                &process_options,
+            ),
+            Err(SnapshotLoadError::NoSharedSnapshotSlot(..))
+        ));
+    }
+
+    #[test]
+    fn test_load_snapshot_archive_staging_dir() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let account_paths = vec![temp_dir.path().join("accounts")];
+        let snapshot_config = archive_bank_snapshot(
+            new_bank_at_slot_1(&genesis_config, None),
+            temp_dir.path(),
+            "archives",
+        );
+        let staging_dir = temp_dir.path().join("staging");
+        let process_options = ProcessOptions {
+            snapshot_staging_dir: Some(staging_dir.clone()),
+            ..ProcessOptions::default()
+        };
+
+        let archive = snapshot_utils::get_highest_snapshot_archive_path(
+            &snapshot_config.snapshot_package_output_path,
+        )
+        .unwrap();
+        let bank = load_snapshot_archive(
+            &genesis_config,
+            &account_paths,
+            &snapshot_config,
+            archive,
+            &process_options,
+        )
+        .unwrap();
+        assert_eq!(bank.slot(), 1);
+        // The unpacked snapshot is removed once the bank is rebuilt
+        assert!(staging_dir.is_dir());
+        assert_eq!(fs::read_dir(&staging_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_load_rejects_archives_in_snapshot_path() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snapshot_config = archive_bank_snapshot(
+            new_bank_at_slot_1(&genesis_config, None),
+            temp_dir.path(),
+            "archives",
+        );
+        let snapshot_config = SnapshotConfig {
+            snapshot_path: snapshot_config.snapshot_package_output_path.clone(),
+            ..snapshot_config
+        };
+        let ledger_path = get_tmp_ledger_path!();
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        assert!(matches!(
+            load(
+                &genesis_config,
+                &blockstore,
+                vec![temp_dir.path().join("accounts")],
+                None,
+                Some(&snapshot_config),
+                ProcessOptions::default(),
+                None,
+                None,
+            ),
+            Err(BlockstoreProcessorError::InvalidSnapshotConfig(_))
+        ));
+        // The archive directory was not wiped
+        assert!(snapshot_utils::get_highest_snapshot_archive_path(
+            &snapshot_config.snapshot_package_output_path
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn test_check_genesis_hash() {
+        let GenesisConfigInfo {
+            mut genesis_config, ..
+        } = create_genesis_config(10_000);
+        let (ledger_path, _blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        check_genesis_hash(&genesis_config, &blockstore).unwrap();
+
+        let ledger_genesis_hash = genesis_config.hash();
+        genesis_config.ticks_per_slot += 1;
+        assert!(matches!(
+            check_genesis_hash(&genesis_config, &blockstore),
+            Err(BlockstoreProcessorError::GenesisMismatch(genesis_hash, hash))
+                if genesis_hash == genesis_config.hash() && hash == ledger_genesis_hash
+        ));
+
+        // Without a genesis file there is nothing to check against
+        let ledger_path = get_tmp_ledger_path!();
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        check_genesis_hash(&genesis_config, &blockstore).unwrap();
+    }
+
+    #[test]
+    fn test_check_snapshot_age() {
+        let ledger_path = get_tmp_ledger_path!();
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        let (shreds, _entries) = make_many_slot_entries(0, 11, 1);
+        blockstore.insert_shreds(shreds, None, false).unwrap();
+
+        let process_options = ProcessOptions::default();
+        assert!(check_snapshot_age(&blockstore, 0, &process_options).is_ok());
+
+        // Stale snapshots only warn by default
+        let mut process_options = ProcessOptions {
+            max_snapshot_age_slots: Some(5),
+            ..ProcessOptions::default()
+        };
+        assert!(check_snapshot_age(&blockstore, 5, &process_options).is_ok());
+        assert!(check_snapshot_age(&blockstore, 4, &process_options).is_ok());
+
+        process_options.reject_stale_snapshot = true;
+        assert!(check_snapshot_age(&blockstore, 5, &process_options).is_ok());
+        assert!(matches!(
+            check_snapshot_age(&blockstore, 4, &process_options),
+            Err(BlockstoreProcessorError::StaleSnapshot(4, 10, 5))
+        ));
+        // A snapshot past the highest slot in the blockstore is not stale
+        assert!(check_snapshot_age(&blockstore, 20, &process_options).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_selection() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snapshot_config = SnapshotConfig {
+            snapshot_interval_slots: 100,
+            snapshot_package_output_path: temp_dir.path().to_path_buf(),
+            snapshot_path: temp_dir.path().join("snapshot"),
+            archive_format: ArchiveFormat::TarBzip2,
+            snapshot_version: snapshot_utils::SnapshotVersion::default(),
+        };
+        assert_eq!(
+            snapshot_selection(&snapshot_config),
+            SnapshotSelection::default()
+        );
+
+        let snapshot_hashes = vec![
+            (100, Hash::new_unique()),
+            (300, Hash::new_unique()),
+            (200, Hash::new_unique()),
+        ];
+        for snapshot_hash in &snapshot_hashes {
+            let archive_path = snapshot_utils::get_snapshot_archive_path(
+                temp_dir.path().to_path_buf(),
+                snapshot_hash,
+                ArchiveFormat::TarBzip2,
+            );
+            fs::write(archive_path, b"").unwrap();
+        }
+        fs::write(temp_dir.path().join("not-a-snapshot.tar.bz2"), b"").unwrap();
+
+        let selection = snapshot_selection(&snapshot_config);
+        assert_eq!(
+            selection.available,
+            vec![snapshot_hashes[1], snapshot_hashes[2], snapshot_hashes[0]]
+        );
+        assert_eq!(selection.chosen, Some(snapshot_hashes[1]));
+    }
+
+    #[test]
+    fn test_highest_snapshot_archive_preferred_format() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert!(highest_snapshot_archive(temp_dir.path(), Some(ArchiveFormat::TarZstd)).is_none());
+
+        let snapshot_hash = (200, Hash::new_unique());
+        for (snapshot_hash, archive_format) in &[
+            (snapshot_hash, ArchiveFormat::TarGzip),
+            (snapshot_hash, ArchiveFormat::TarZstd),
+            ((100, Hash::new_unique()), ArchiveFormat::TarBzip2),
+        ] {
+            let archive_path = snapshot_utils::get_snapshot_archive_path(
+                temp_dir.path().to_path_buf(),
+                snapshot_hash,
+                *archive_format,
+            );
+            fs::write(archive_path, b"").unwrap();
+        }
+
+        for archive_format in &[ArchiveFormat::TarGzip, ArchiveFormat::TarZstd] {
+            let (_, archive) =
+                highest_snapshot_archive(temp_dir.path(), Some(*archive_format)).unwrap();
+            assert_eq!(archive, (snapshot_hash.0, snapshot_hash.1, *archive_format));
+        }
+
+        // Archives at lower slots are never preferred over the highest slot
+        let (_, (slot, hash, archive_format)) =
+            highest_snapshot_archive(temp_dir.path(), Some(ArchiveFormat::TarBzip2)).unwrap();
+        assert_eq!((slot, hash), snapshot_hash);
+        assert_ne!(archive_format, ArchiveFormat::TarBzip2);
+
+        let (_, (slot, _, _)) = highest_snapshot_archive(temp_dir.path(), None).unwrap();
+        assert_eq!(slot, snapshot_hash.0);
+    }
+
+    #[test]
+    fn test_estimate_restore_duration() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snapshot_hash = (1, Hash::new_unique());
+        let tar_path = snapshot_utils::get_snapshot_archive_path(
+            temp_dir.path().to_path_buf(),
+            &snapshot_hash,
+            ArchiveFormat::Tar,
+        );
+        assert_eq!(
+            estimate_restore_duration(&tar_path, ArchiveFormat::Tar),
+            Duration::default()
+        );
+
+        let archive_size = restore_bytes_per_second(ArchiveFormat::Tar) * 2;
+        fs::File::create(&tar_path)
+            .unwrap()
+            .set_len(archive_size)
+            .unwrap();
+        assert_eq!(
+            estimate_restore_duration(&tar_path, ArchiveFormat::Tar),
+            Duration::from_secs(2)
+        );
+
+        // The estimate follows the archive `load` selects, not the first one listed
+        let bzip2_path = snapshot_utils::get_snapshot_archive_path(
+            temp_dir.path().to_path_buf(),
+            &snapshot_hash,
+            ArchiveFormat::TarBzip2,
+        );
+        fs::File::create(&bzip2_path)
+            .unwrap()
+            .set_len(restore_bytes_per_second(ArchiveFormat::TarBzip2) * 20)
+            .unwrap();
+        let (archive_path, (_, _, archive_format)) =
+            highest_snapshot_archive(temp_dir.path(), Some(ArchiveFormat::TarBzip2)).unwrap();
+        assert_eq!(archive_path, bzip2_path);
+        assert_eq!(
+            estimate_restore_duration(&archive_path, archive_format),
+            Duration::from_secs(20)
+        );
+    }
+
+    #[test]
+    fn test_effective_account_indexes() {
+        let effective = EffectiveAccountIndexes::new(&AccountSecondaryIndexes::default());
+        assert!(effective.is_empty());
+        assert_eq!(effective, EffectiveAccountIndexes::default());
+
+        let keys = Some(AccountSecondaryIndexesIncludeExclude {
+            exclude: true,
+            keys: vec![Pubkey::new_unique()].into_iter().collect(),
+        });
+        let account_indexes = AccountSecondaryIndexes {
+            keys: keys.clone(),
+            indexes: vec![AccountIndex::ProgramId].into_iter().collect(),
+        };
+        let effective = EffectiveAccountIndexes::new(&account_indexes);
+        assert!(effective.program_id);
+        assert!(!effective.token_owner);
+        assert!(!effective.token_mint);
+        assert!(effective.token_program_ids.is_empty());
+        assert_eq!(effective.keys, keys);
+
+        let account_indexes = AccountSecondaryIndexes {
+            keys: None,
+            indexes: vec![AccountIndex::SafeTokenOwner, AccountIndex::SafeTokenMint]
+                .into_iter()
+                .collect(),
+        };
+        let effective = EffectiveAccountIndexes::new(&account_indexes);
+        assert!(!effective.program_id);
+        assert!(effective.token_owner);
+        assert!(effective.token_mint);
+        assert_eq!(
+            effective.token_program_ids,
+            vec![inline_spl_token_v2_0::id()]
+        );
+        assert_eq!(effective.keys, None);
+    }
+}
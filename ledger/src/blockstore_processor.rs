@@ -7,28 +7,32 @@ use crate::{
     leader_schedule_cache::LeaderScheduleCache,
 };
 use chrono_humanize::{Accuracy, HumanTime, Tense};
-use crossbeam_channel::Sender;
+use crossbeam_channel::{unbounded, Sender};
 use itertools::Itertools;
 use log::*;
 use rand::{seq::SliceRandom, thread_rng};
 use rayon::{prelude::*, ThreadPool};
 use safecoin_measure::{measure::Measure, thread_mem_usage};
-use solana_metrics::{datapoint_error, inc_new_counter_debug};
+use solana_metrics::{datapoint_error, datapoint_info, datapoint_warn, inc_new_counter_debug};
 use safecoin_rayon_threadlimit::get_thread_count;
 use solana_runtime::{
+    accounts_db::GenerateIndexProgress,
     accounts_index::AccountSecondaryIndexes,
     bank::{
-        Bank, ExecuteTimings, InnerInstructionsList, RentDebits, TransactionBalancesSet,
-        TransactionExecutionResult, TransactionLogMessages, TransactionResults,
+        Bank, DropCallback, ExecuteTimings, InnerInstructionsList, RentDebits,
+        TransactionBalancesSet, TransactionExecutionResult, TransactionLogMessages,
+        TransactionResults,
     },
-    bank_forks::BankForks,
+    bank_forks::{ArchiveFormat, BankForks},
     bank_utils,
     commitment::VOTE_THRESHOLD_SIZE,
+    inline_spl_token_v2_0::{self, new_token_program},
     transaction_batch::TransactionBatch,
     vote_account::ArcVoteAccount,
     vote_sender_types::ReplayVoteSender,
 };
 use solana_sdk::{
+    account::AccountSharedData,
     clock::{Slot, MAX_PROCESSING_AGE},
     genesis_config::GenesisConfig,
     hash::Hash,
@@ -44,9 +48,14 @@ use safecoin_transaction_status::token_balances::{
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    fmt, fs,
+    path::{Path, PathBuf},
     result,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread::Builder,
     time::{Duration, Instant},
 };
 use thiserror::Error;
@@ -352,25 +361,151 @@ pub enum BlockstoreProcessorError {
 
     #[error("root bank with mismatched capitalization at {0}")]
     RootBankWithMismatchedCapitalization(Slot),
+
+    #[error("invalid account path {0:?}: {1}")]
+    InvalidAccountPath(PathBuf, String),
+
+    #[error("rooted slot {0} failed to replay")]
+    FailedToReplayRootedSlot(Slot),
+
+    #[error("invalid hard forks file {0:?}: {1}")]
+    InvalidHardForksFile(PathBuf, String),
+
+    #[error("snapshot at slot {0} is more than {2} slots behind the blockstore tip at slot {1}")]
+    StaleSnapshot(Slot, Slot, Slot),
+
+    #[error("ledger processing left unfrozen banks at slots {0:?}")]
+    UnfrozenBanks(Vec<Slot>),
+
+    #[error("blockstore is missing shreds for slots {0:?}")]
+    MissingSlots(Vec<Slot>),
+
+    #[error("invalid snapshot config: {0}")]
+    InvalidSnapshotConfig(String),
+
+    #[error("genesis hash {0} does not match the genesis hash {1} of the ledger")]
+    GenesisMismatch(Hash, Hash),
 }
 
 /// Callback for accessing bank state while processing the blockstore
 pub type ProcessCallback = Arc<dyn Fn(&Bank) + Sync + Send>;
 
+/// Callback for observing the slot of each bank dropped while processing the blockstore
+pub type PrunedBankCallback = Arc<dyn Fn(Slot) + Sync + Send>;
+
+#[derive(Clone)]
+struct PrunedBankObserver(PrunedBankCallback);
+
+impl DropCallback for PrunedBankObserver {
+    fn callback(&self, bank: &Bank) {
+        (self.0)(bank.slot());
+        // Same cleanup as a bank without a drop callback, since there's no
+        // AccountsBackgroundService to perform it yet
+        bank.rc.accounts.purge_slot(bank.slot());
+    }
+
+    fn clone_box(&self) -> Box<dyn DropCallback + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+impl fmt::Debug for PrunedBankObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrunedBankObserver({:p})", self)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct ProcessOptions {
     pub bpf_jit: bool,
     pub poh_verify: bool,
     pub full_leader_cache: bool,
+    /// Compute the leader schedules of the root epoch and up to N epochs after it once replay
+    /// is done. Schedules only exist for epochs whose stakes are known, which is about one epoch
+    /// past the root, so larger values are effectively capped there. Also capped to fit in
+    /// `max_leader_schedule_epochs` when that is set.
+    pub warmup_leader_schedule_epochs: Option<u64>,
     pub dev_halt_at_slot: Option<Slot>,
+    pub halt_at_slot: Option<Slot>,
     pub entry_callback: Option<ProcessCallback>,
+    /// Called with the slot of each bank dropped during replay. When `None`, no drop callback
+    /// is installed on the banks and no channel is created, so loaders that only read from the
+    /// resulting banks pay nothing for it. See `bank_forks_utils::send_pruned_banks` for
+    /// routing drops after `load`.
+    pub on_bank_pruned: Option<PrunedBankCallback>,
     pub override_num_threads: Option<usize>,
     pub new_hard_forks: Option<Vec<Slot>>,
+    pub new_hard_forks_path: Option<PathBuf>,
     pub frozen_accounts: Vec<Pubkey>,
     pub debug_keys: Option<Arc<HashSet<Pubkey>>>,
     pub account_indexes: AccountSecondaryIndexes,
     pub accounts_db_caching_enabled: bool,
     pub allow_dead_slots: bool,
+    pub max_snapshot_age_slots: Option<Slot>,
+    pub reject_stale_snapshot: bool,
+    /// Updated while the accounts index is rebuilt from a snapshot, for callers to poll
+    pub generate_index_progress: Option<Arc<GenerateIndexProgress>>,
+    /// Set to stop replay before the next slot; the banks replayed so far still form a
+    /// consistent, if partial, `BankForks`
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Caps the number of epoch leader schedules kept when `full_leader_cache` is set
+    pub max_leader_schedule_epochs: Option<usize>,
+    /// Directory to unpack the snapshot archive in while loading, instead of the snapshot path
+    /// of the `SnapshotConfig`
+    pub snapshot_staging_dir: Option<PathBuf>,
+    /// Check that every slot descending from the starting slot is full before replaying any of
+    /// them, failing with `BlockstoreProcessorError::MissingSlots` otherwise
+    pub verify_blockstore_continuity: bool,
+    /// Move every account owned by the legacy token program to the new token program, in each
+    /// child of the starting root before it is replayed. The resulting banks diverge from any
+    /// cluster that does not apply the same migration at the same slot.
+    pub migrate_token_program_owner: bool,
+    /// Record a `ReplayReport` of every slot replayed
+    pub collect_replay_report: bool,
+    /// When archives of several formats exist at the highest snapshot slot, boot from the one
+    /// in this format. Falls back to any of them if none is in this format.
+    pub preferred_archive_format: Option<ArchiveFormat>,
+    /// Accounts stored into bank 0 on top of the genesis config before it is replayed, when the
+    /// ledger is processed from genesis. Ignored when booting from a snapshot. The resulting
+    /// banks diverge from any cluster that does not start from the same accounts.
+    pub additional_genesis_accounts: Vec<(Pubkey, AccountSharedData)>,
+    /// Count the accounts owned by each of these program ids once a snapshot is restored, see
+    /// `LoadStats::accounts_by_owner`. Each count scans every account, so leave it empty unless
+    /// the numbers are needed.
+    pub count_accounts_by_owner: Vec<Pubkey>,
+    /// Skip the slots that are off the chain of the highest root in blockstore, replaying only
+    /// the rooted path up to that root. Slots past that root are all replayed, as any of them may
+    /// still be rooted.
+    pub replay_rooted_only: bool,
+}
+
+/// Replay cost of a single slot, see `ReplayReport`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotReplayStats {
+    pub slot: Slot,
+    pub num_transactions: usize,
+    /// Time spent executing the slot's transactions. Compute units are not metered per slot, so
+    /// this stands in for the slot's execution cost.
+    pub execute_us: u64,
+    /// Wall-clock time to replay and freeze the slot, including fetching and verifying entries
+    pub replay_duration: Duration,
+}
+
+/// Per-slot replay costs of the slots replayed by a load, in replay order, collected when
+/// `ProcessOptions::collect_replay_report` is set. Slots that failed to replay are left out.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplayReport {
+    pub slots: Vec<SlotReplayStats>,
+}
+
+impl ReplayReport {
+    pub fn num_transactions(&self) -> usize {
+        self.slots.iter().map(|slot| slot.num_transactions).sum()
+    }
+
+    pub fn replay_duration(&self) -> Duration {
+        self.slots.iter().map(|slot| slot.replay_duration).sum()
+    }
 }
 
 pub fn process_blockstore(
@@ -380,6 +515,28 @@ pub fn process_blockstore(
     opts: ProcessOptions,
     cache_block_meta_sender: Option<&CacheBlockMetaSender>,
 ) -> BlockstoreProcessorResult {
+    process_blockstore_from_genesis(
+        genesis_config,
+        blockstore,
+        account_paths,
+        opts,
+        cache_block_meta_sender,
+    )
+    .map(|(bank_forks, leader_schedule_cache, ..)| (bank_forks, leader_schedule_cache))
+}
+
+/// Same as `process_blockstore`, also returning the hash of bank 0 as computed from
+/// `genesis_config` and the replay report if `opts.collect_replay_report` is set
+pub(crate) fn process_blockstore_from_genesis(
+    genesis_config: &GenesisConfig,
+    blockstore: &Blockstore,
+    account_paths: Vec<PathBuf>,
+    opts: ProcessOptions,
+    cache_block_meta_sender: Option<&CacheBlockMetaSender>,
+) -> result::Result<
+    (BankForks, LeaderScheduleCache, Hash, Option<ReplayReport>),
+    BlockstoreProcessorError,
+> {
     if let Some(num_threads) = opts.override_num_threads {
         PAR_THREAD_POOL.with(|pool| {
             *pool.borrow_mut() = rayon::ThreadPoolBuilder::new()
@@ -400,6 +557,9 @@ pub fn process_blockstore(
         opts.accounts_db_caching_enabled,
     );
     let bank0 = Arc::new(bank0);
+    if !opts.additional_genesis_accounts.is_empty() {
+        add_genesis_accounts(&bank0, &opts.additional_genesis_accounts);
+    }
     info!("processing ledger for slot 0...");
     let recyclers = VerifyRecyclers::default();
     process_bank_0(
@@ -409,16 +569,26 @@ pub fn process_blockstore(
         &recyclers,
         cache_block_meta_sender,
     );
-    do_process_blockstore_from_root(
+    let bank0_hash = bank0.hash();
+    info!("bank 0 hash: {}", bank0_hash);
+    let (bank_forks, leader_schedule_cache, replay_report) = do_process_blockstore_from_root(
         blockstore,
         bank0,
         &opts,
         &recyclers,
         None,
         cache_block_meta_sender,
-    )
+    )?;
+    Ok((bank_forks, leader_schedule_cache, bank0_hash, replay_report))
 }
 
+/// Result of replaying the blockstore from a root bank, with the replay report if
+/// `ProcessOptions::collect_replay_report` is set
+pub(crate) type ProcessFromRootResult = result::Result<
+    (BankForks, LeaderScheduleCache, Option<ReplayReport>),
+    BlockstoreProcessorError,
+>;
+
 // Process blockstore from a known root bank
 pub(crate) fn process_blockstore_from_root(
     blockstore: &Blockstore,
@@ -427,7 +597,7 @@ pub(crate) fn process_blockstore_from_root(
     recyclers: &VerifyRecyclers,
     transaction_status_sender: Option<&TransactionStatusSender>,
     cache_block_meta_sender: Option<&CacheBlockMetaSender>,
-) -> BlockstoreProcessorResult {
+) -> ProcessFromRootResult {
     do_process_blockstore_from_root(
         blockstore,
         Arc::new(bank),
@@ -438,6 +608,49 @@ pub(crate) fn process_blockstore_from_root(
     )
 }
 
+/// Read hard fork slots from a file with one slot per line, ignoring blank lines
+pub fn read_hard_forks_file(path: &Path) -> result::Result<Vec<Slot>, BlockstoreProcessorError> {
+    let invalid_file =
+        |reason: String| BlockstoreProcessorError::InvalidHardForksFile(path.to_path_buf(), reason);
+    let contents = fs::read_to_string(path).map_err(|err| invalid_file(err.to_string()))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            line.parse::<Slot>().map_err(|err| {
+                invalid_file(format!(
+                    "line {}: {:?} is not a slot: {}",
+                    index + 1,
+                    line,
+                    err
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Find the slots descending from the slot of `start_meta` whose shreds are not all in the
+/// blockstore yet, in ascending order. Replay stops at the first such slot of each fork.
+///
+/// The tip of a fork still being received is reported too.
+fn find_missing_slots(
+    blockstore: &Blockstore,
+    start_meta: &SlotMeta,
+) -> result::Result<Vec<Slot>, BlockstoreProcessorError> {
+    let mut missing_slots = vec![];
+    let mut pending_slots = start_meta.next_slots.clone();
+    while let Some(slot) = pending_slots.pop() {
+        match blockstore.meta(slot)? {
+            Some(meta) if meta.is_full() => pending_slots.extend(meta.next_slots),
+            _ => missing_slots.push(slot),
+        }
+    }
+    missing_slots.sort_unstable();
+    Ok(missing_slots)
+}
+
 fn do_process_blockstore_from_root(
     blockstore: &Blockstore,
     bank: Arc<Bank>,
@@ -445,18 +658,30 @@ fn do_process_blockstore_from_root(
     recyclers: &VerifyRecyclers,
     transaction_status_sender: Option<&TransactionStatusSender>,
     cache_block_meta_sender: Option<&CacheBlockMetaSender>,
-) -> BlockstoreProcessorResult {
+) -> ProcessFromRootResult {
     info!("processing ledger from slot {}...", bank.slot());
     let allocated = thread_mem_usage::Allocatedp::default();
     let initial_allocation = allocated.get();
 
     // Starting slot must be a root, and thus has no parents
     assert!(bank.parent().is_none());
+
+    // Child banks inherit the drop callback of their parent
+    if let Some(on_bank_pruned) = &opts.on_bank_pruned {
+        bank.set_callback(Some(Box::new(PrunedBankObserver(on_bank_pruned.clone()))));
+    }
     let start_slot = bank.slot();
     let now = Instant::now();
     let mut root = start_slot;
 
-    if let Some(ref new_hard_forks) = opts.new_hard_forks {
+    let mut new_hard_forks = opts.new_hard_forks.clone().unwrap_or_default();
+    if let Some(new_hard_forks_path) = &opts.new_hard_forks_path {
+        new_hard_forks.extend(read_hard_forks_file(new_hard_forks_path)?);
+    }
+    // Registering a slot more than once would count it as multiple hard forks
+    new_hard_forks.sort_unstable();
+    new_hard_forks.dedup();
+    if !new_hard_forks.is_empty() {
         let hard_forks = bank.hard_forks();
 
         for hard_fork_slot in new_hard_forks.iter() {
@@ -487,16 +712,28 @@ fn do_process_blockstore_from_root(
     }
 
     let mut timing = ExecuteTimings::default();
+    let mut replay_report = if opts.collect_replay_report {
+        Some(ReplayReport::default())
+    } else {
+        None
+    };
     // Iterate and replay slots from blockstore starting from `start_slot`
-    let (initial_forks, leader_schedule_cache) = {
+    let (initial_forks, mut leader_schedule_cache) = {
         if let Some(meta) = blockstore
             .meta(start_slot)
             .unwrap_or_else(|_| panic!("Failed to get meta for slot {}", start_slot))
         {
+            if opts.verify_blockstore_continuity {
+                let missing_slots = find_missing_slots(blockstore, &meta)?;
+                if !missing_slots.is_empty() {
+                    return Err(BlockstoreProcessorError::MissingSlots(missing_slots));
+                }
+            }
             let epoch_schedule = bank.epoch_schedule();
             let mut leader_schedule_cache = LeaderScheduleCache::new(*epoch_schedule, &bank);
             if opts.full_leader_cache {
-                leader_schedule_cache.set_max_schedules(std::usize::MAX);
+                leader_schedule_cache
+                    .set_max_schedules(opts.max_leader_schedule_epochs.unwrap_or(std::usize::MAX));
             }
             let mut initial_forks = load_frozen_forks(
                 &bank,
@@ -509,6 +746,7 @@ fn do_process_blockstore_from_root(
                 transaction_status_sender,
                 cache_block_meta_sender,
                 &mut timing,
+                &mut replay_report,
             )?;
             initial_forks.sort_by_key(|bank| bank.slot());
 
@@ -526,6 +764,44 @@ fn do_process_blockstore_from_root(
     }
     let bank_forks = BankForks::new_from_banks(&initial_forks, root);
 
+    if let Some(mut num_epochs) = opts.warmup_leader_schedule_epochs {
+        if let Some(max_epochs) = opts.max_leader_schedule_epochs.filter(|max| *max > 0) {
+            let max_warmup_epochs = (max_epochs - 1) as u64;
+            if num_epochs > max_warmup_epochs {
+                warn!(
+                    "warming up {} leader schedule epochs instead of {} to fit {} cached epochs",
+                    max_warmup_epochs, num_epochs, max_epochs,
+                );
+                num_epochs = max_warmup_epochs;
+            }
+        }
+        let max_schedules = (num_epochs as usize).saturating_add(1);
+        if max_schedules > leader_schedule_cache.max_schedules() {
+            leader_schedule_cache.set_max_schedules(max_schedules);
+        }
+        let num_schedules =
+            leader_schedule_cache.warmup_epochs(&bank_forks.root_bank(), num_epochs);
+        info!(
+            "leader schedule cache warmed up with {} epoch schedule{}",
+            num_schedules,
+            if num_schedules > 1 { "s" } else { "" },
+        );
+    }
+
+    let num_cached_schedules = leader_schedule_cache.num_cached_schedules();
+    let cached_schedules_bytes = leader_schedule_cache.cached_schedules_memory_usage();
+    info!(
+        "leader schedule cache: {} epoch schedules, ~{} bytes, max {}",
+        num_cached_schedules,
+        cached_schedules_bytes,
+        leader_schedule_cache.max_schedules(),
+    );
+    datapoint_info!(
+        "leader-schedule-cache",
+        ("num_schedules", num_cached_schedules, i64),
+        ("bytes", cached_schedules_bytes, i64),
+    );
+
     info!("ledger processing timing: {:?}", timing);
     info!(
         "ledger processed in {}. {} MB allocated. root slot is {}, {} fork{} at {}, with {} frozen bank{}",
@@ -545,7 +821,11 @@ fn do_process_blockstore_from_root(
             ""
         },
     );
-    assert!(bank_forks.active_banks().is_empty());
+    let mut active_banks = bank_forks.active_banks();
+    if !active_banks.is_empty() {
+        active_banks.sort_unstable();
+        return Err(BlockstoreProcessorError::UnfrozenBanks(active_banks));
+    }
 
     // We might be promptly restarted after bad capitalization was detected while creating newer snapshot.
     // In that case, we're most likely restored from the last good snapshot and replayed up to this root.
@@ -554,7 +834,7 @@ fn do_process_blockstore_from_root(
         return Err(BlockstoreProcessorError::RootBankWithMismatchedCapitalization(root));
     }
 
-    Ok((bank_forks, leader_schedule_cache))
+    Ok((bank_forks, leader_schedule_cache, replay_report))
 }
 
 /// Verify that a segment of entries has the correct number of ticks and hashes
@@ -883,6 +1163,99 @@ fn process_next_slots(
     Ok(())
 }
 
+/// Store `accounts` into `bank0` on top of the genesis config, logging every account added
+fn add_genesis_accounts(bank0: &Bank, accounts: &[(Pubkey, AccountSharedData)]) {
+    warn!(
+        "Adding {} accounts to bank 0 beyond the genesis config. \
+        This bank will not match the cluster unless every node adds the same accounts.",
+        accounts.len(),
+    );
+    for (pubkey, account) in accounts {
+        info!(
+            "Adding genesis account {}: owner {}, {} lamports",
+            pubkey, account.owner, account.lamports
+        );
+        bank0.store_account_and_update_capitalization(pubkey, account);
+    }
+}
+
+/// Reassign the accounts of the legacy token program to the new token program in `bank`, logging
+/// every account moved
+fn migrate_token_program_owner(bank: &Bank) {
+    warn!(
+        "Migrating the accounts owned by token program {} to {} in slot {}. \
+        This bank will not match the cluster unless every node applies the same migration.",
+        inline_spl_token_v2_0::id(),
+        new_token_program::id(),
+        bank.slot(),
+    );
+    let migrated_accounts =
+        bank.reassign_program_accounts(&inline_spl_token_v2_0::id(), &new_token_program::id());
+    for pubkey in &migrated_accounts {
+        info!(
+            "Migrated token account {} to {} in slot {}",
+            pubkey,
+            new_token_program::id(),
+            bank.slot()
+        );
+    }
+    warn!(
+        "Migrated {} token accounts in slot {}",
+        migrated_accounts.len(),
+        bank.slot()
+    );
+    datapoint_warn!(
+        "migrate-token-program-owner",
+        ("slot", bank.slot(), i64),
+        ("accounts", migrated_accounts.len(), i64),
+    );
+}
+
+/// Slots on the chain from `root` up to `max_root`, found by walking the parents of `max_root`
+/// in blockstore, see `ProcessOptions::replay_rooted_only`. Returns `None` if that walk doesn't
+/// lead back to `root`, in which case no slot can safely be skipped.
+fn rooted_path(blockstore: &Blockstore, root: Slot, max_root: Slot) -> Option<HashSet<Slot>> {
+    let mut rooted_path = HashSet::new();
+    let mut slot = max_root;
+    while slot > root {
+        rooted_path.insert(slot);
+        slot = match blockstore.meta(slot) {
+            Ok(Some(meta)) => meta.parent_slot,
+            _ => break,
+        };
+    }
+    if slot != root {
+        warn!(
+            "Slot {} does not descend from root {}, replaying every fork",
+            max_root, root
+        );
+        return None;
+    }
+    info!(
+        "Replaying only the {} rooted slots up to slot {}",
+        rooted_path.len(),
+        max_root
+    );
+    Some(rooted_path)
+}
+
+// Drop the pending slots at or below `max_root` that are not on the `rooted_path`. Such slots
+// can never be rooted, since roots only move forward.
+fn skip_unrooted_slots(
+    pending_slots: &mut Vec<(SlotMeta, Arc<Bank>, Hash)>,
+    rooted_path: &HashSet<Slot>,
+    max_root: Slot,
+) {
+    pending_slots.retain(|(_, bank, _)| {
+        let slot = bank.slot();
+        let keep = slot > max_root || rooted_path.contains(&slot);
+        if !keep {
+            debug!("Skipping unrooted slot {}", slot);
+        }
+        keep
+    });
+}
+
 // Iterate through blockstore processing slots starting from the root slot pointed to by the
 // given `meta` and return a vector of frozen bank forks
 #[allow(clippy::too_many_arguments)]
@@ -897,6 +1270,7 @@ fn load_frozen_forks(
     transaction_status_sender: Option<&TransactionStatusSender>,
     cache_block_meta_sender: Option<&CacheBlockMetaSender>,
     timing: &mut ExecuteTimings,
+    replay_report: &mut Option<ReplayReport>,
 ) -> result::Result<Vec<Arc<Bank>>, BlockstoreProcessorError> {
     let mut initial_forks = HashMap::new();
     let mut all_banks = HashMap::new();
@@ -912,6 +1286,11 @@ fn load_frozen_forks(
         "load_frozen_forks() latest root from blockstore: {}, max_root: {}",
         blockstore_max_root, max_root,
     );
+    let rooted_path = if opts.replay_rooted_only {
+        rooted_path(blockstore, root_bank.slot(), blockstore_max_root)
+    } else {
+        None
+    };
     process_next_slots(
         root_bank,
         root_meta,
@@ -920,10 +1299,34 @@ fn load_frozen_forks(
         &mut pending_slots,
         &mut initial_forks,
     )?;
+    if let Some(rooted_path) = &rooted_path {
+        skip_unrooted_slots(&mut pending_slots, rooted_path, blockstore_max_root);
+    }
+    if opts.migrate_token_program_owner {
+        if pending_slots.is_empty() {
+            warn!(
+                "Token program owner migration skipped: root slot {} has no child slot to \
+                apply it to",
+                root_bank.slot()
+            );
+        }
+        for (_meta, bank, _last_entry_hash) in &pending_slots {
+            migrate_token_program_owner(bank);
+        }
+    }
 
     let dev_halt_at_slot = opts.dev_halt_at_slot.unwrap_or(std::u64::MAX);
-    if root_bank.slot() != dev_halt_at_slot {
+    let halt_at_slot = opts.halt_at_slot.unwrap_or(std::u64::MAX);
+    if root_bank.slot() != dev_halt_at_slot && root_bank.slot() < halt_at_slot {
         while !pending_slots.is_empty() {
+            if opts
+                .cancel
+                .as_ref()
+                .map_or(false, |cancel| cancel.load(Ordering::Relaxed))
+            {
+                info!("ledger processing cancelled, last root slot={}", last_root);
+                break;
+            }
             let (meta, bank, last_entry_hash) = pending_slots.pop().unwrap();
             let slot = bank.slot();
             if last_status_report.elapsed() > Duration::from_secs(2) {
@@ -945,8 +1348,10 @@ fn load_frozen_forks(
             let initial_allocation = allocated.get();
 
             let mut progress = ConfirmationProgress::new(last_entry_hash);
+            let replay_start = Instant::now();
+            let mut slot_timing = ExecuteTimings::default();
 
-            if process_single_slot(
+            let process_result = process_single_slot(
                 blockstore,
                 &bank,
                 opts,
@@ -955,13 +1360,27 @@ fn load_frozen_forks(
                 transaction_status_sender,
                 cache_block_meta_sender,
                 None,
-                timing,
-            )
-            .is_err()
-            {
+                &mut slot_timing,
+            );
+            timing.accumulate(&slot_timing);
+            if let Err(err) = process_result {
+                // A failed slot on an unrooted fork is skipped along with its descendants, but
+                // skipping a rooted slot would silently boot from an older root
+                if blockstore.is_root(slot) {
+                    error!("rooted slot {} failed to replay: {}", slot, err);
+                    return Err(BlockstoreProcessorError::FailedToReplayRootedSlot(slot));
+                }
                 continue;
             }
             txs += progress.num_txs;
+            if let Some(replay_report) = replay_report {
+                replay_report.slots.push(SlotReplayStats {
+                    slot,
+                    num_transactions: progress.num_txs,
+                    execute_us: slot_timing.execute_us,
+                    replay_duration: replay_start.elapsed(),
+                });
+            }
 
             // Block must be frozen by this point, otherwise `process_single_slot` would
             // have errored above
@@ -1053,10 +1472,21 @@ fn load_frozen_forks(
                 &mut pending_slots,
                 &mut initial_forks,
             )?;
+            if let Some(rooted_path) = &rooted_path {
+                skip_unrooted_slots(&mut pending_slots, rooted_path, blockstore_max_root);
+            }
 
             if slot >= dev_halt_at_slot {
                 break;
             }
+
+            if *root >= halt_at_slot {
+                info!(
+                    "load_frozen_forks() halting, root {} reached halt_at_slot {}",
+                    root, halt_at_slot
+                );
+                break;
+            }
         }
     }
 
@@ -1176,6 +1606,38 @@ pub struct TransactionStatusSender {
 }
 
 impl TransactionStatusSender {
+    /// Create a sender whose messages are dropped until a consumer is attached to the returned
+    /// `TransactionStatusSubscriber`, so that transaction statuses can start being recorded after
+    /// the ledger is loaded and the validator is running
+    ///
+    /// This does not make the sender free before a consumer is attached: replay during `load`
+    /// still collects balances and builds and sends a full status batch for every batch of
+    /// transactions it executes, which the relay then drops.
+    ///
+    /// Messages are relayed by a thread that exits once every clone of the sender is dropped.
+    pub fn new_deferred(enable_cpi_and_log_storage: bool) -> (Self, TransactionStatusSubscriber) {
+        let (sender, receiver) = unbounded();
+        let subscriber = TransactionStatusSubscriber::default();
+        let relay_subscriber = subscriber.clone();
+        Builder::new()
+            .name("solana-transaction-status-relay".to_string())
+            .spawn(move || {
+                for message in receiver.iter() {
+                    if let Some(sender) = &*relay_subscriber.sender.read().unwrap() {
+                        let _ = sender.send(message);
+                    }
+                }
+            })
+            .unwrap();
+        (
+            Self {
+                sender,
+                enable_cpi_and_log_storage,
+            },
+            subscriber,
+        )
+    }
+
     pub fn send_transaction_status_batch(
         &self,
         bank: Arc<Bank>,
@@ -1226,6 +1688,25 @@ impl TransactionStatusSender {
     }
 }
 
+/// Attaches a transaction status consumer to a sender created by
+/// `TransactionStatusSender::new_deferred()`
+#[derive(Clone, Default)]
+pub struct TransactionStatusSubscriber {
+    sender: Arc<RwLock<Option<Sender<TransactionStatusMessage>>>>,
+}
+
+impl TransactionStatusSubscriber {
+    /// Forward the messages sent from now on to `sender`, replacing any previous consumer
+    pub fn attach(&self, sender: Sender<TransactionStatusMessage>) {
+        *self.sender.write().unwrap() = Some(sender);
+    }
+
+    /// Drop the messages sent from now on
+    pub fn detach(&self) {
+        *self.sender.write().unwrap() = None;
+    }
+}
+
 pub type CacheBlockMetaSender = Sender<Arc<Bank>>;
 
 pub fn cache_block_meta(bank: &Arc<Bank>, cache_block_meta_sender: Option<&CacheBlockMetaSender>) {
@@ -1276,7 +1757,6 @@ pub mod tests {
             create_genesis_config, create_genesis_config_with_leader, GenesisConfigInfo,
         },
     };
-    use crossbeam_channel::unbounded;
     use matches::assert_matches;
     use rand::{thread_rng, Rng};
     use solana_runtime::genesis_utils::{
@@ -1413,6 +1893,47 @@ pub mod tests {
         assert_eq!(bank_forks.root(), 0);
     }
 
+    #[test]
+    fn test_process_blockstore_with_invalid_rooted_slot() {
+        solana_logger::setup();
+
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        // Write slot 1 with one tick missing, and root it
+        let entries = create_ticks(ticks_per_slot - 1, 0, blockhash);
+        blockstore
+            .write_entries(
+                1,
+                0,
+                0,
+                ticks_per_slot,
+                Some(0),
+                true,
+                &Arc::new(Keypair::new()),
+                entries,
+                0,
+            )
+            .unwrap();
+        blockstore.set_roots(&[0, 1]).unwrap();
+
+        assert_matches!(
+            process_blockstore(
+                &genesis_config,
+                &blockstore,
+                Vec::new(),
+                ProcessOptions {
+                    poh_verify: true,
+                    ..ProcessOptions::default()
+                },
+                None,
+            ),
+            Err(BlockstoreProcessorError::FailedToReplayRootedSlot(1))
+        );
+    }
+
     #[test]
     fn test_process_blockstore_with_slot_with_trailing_entry() {
         solana_logger::setup();
@@ -1463,6 +1984,196 @@ pub mod tests {
         assert_eq!(frozen_bank_slots(&bank_forks), vec![0]);
     }
 
+    #[test]
+    fn test_process_blockstore_verify_continuity() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        /*
+          Build a blockstore in the ledger with the following fork structure:
+
+               slot 0 (all ticks)
+                 |
+               slot 1 (all ticks)
+                 |
+               slot 2 (all ticks but one)
+                 |
+               slot 3 (all ticks)
+        */
+        let last_hash =
+            fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 1, 0, blockhash);
+        let mut entries = create_ticks(ticks_per_slot, 0, last_hash);
+        let last_hash = entries.last().unwrap().hash;
+        entries.pop();
+        blockstore
+            .write_entries(
+                2,
+                0,
+                0,
+                ticks_per_slot,
+                Some(1),
+                false,
+                &Arc::new(Keypair::new()),
+                entries,
+                0,
+            )
+            .unwrap();
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 3, 2, last_hash);
+
+        let opts = ProcessOptions {
+            verify_blockstore_continuity: true,
+            ..ProcessOptions::default()
+        };
+        assert_matches!(
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None),
+            Err(BlockstoreProcessorError::MissingSlots(missing_slots)) if missing_slots == vec![2]
+        );
+
+        // Without the check, replay stops quietly before the incomplete slot
+        let (bank_forks, _leader_schedule) = process_blockstore(
+            &genesis_config,
+            &blockstore,
+            Vec::new(),
+            ProcessOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_process_blockstore_migrate_token_program_owner() {
+        let GenesisConfigInfo {
+            mut genesis_config, ..
+        } = create_genesis_config(10_000);
+        let token_account = Pubkey::new_unique();
+        genesis_config.add_account(
+            token_account,
+            AccountSharedData::new(1, 165, &inline_spl_token_v2_0::id()),
+        );
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 1, 0, blockhash);
+
+        let opts = ProcessOptions {
+            migrate_token_program_owner: true,
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![0, 1]);
+        assert_eq!(
+            bank_forks[0].get_account(&token_account).unwrap().owner,
+            inline_spl_token_v2_0::id()
+        );
+        assert_eq!(
+            bank_forks[1].get_account(&token_account).unwrap().owner,
+            new_token_program::id()
+        );
+    }
+
+    #[test]
+    fn test_process_blockstore_additional_genesis_accounts() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 1, 0, blockhash);
+
+        let (bank_forks, _leader_schedule) = process_blockstore(
+            &genesis_config,
+            &blockstore,
+            Vec::new(),
+            ProcessOptions::default(),
+            None,
+        )
+        .unwrap();
+        let capitalization = bank_forks[1].capitalization();
+
+        let pubkey = Pubkey::new_unique();
+        let account = AccountSharedData::new(42, 10, &Pubkey::new_unique());
+        let opts = ProcessOptions {
+            additional_genesis_accounts: vec![(pubkey, account.clone())],
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![0, 1]);
+        assert_eq!(bank_forks[0].get_account(&pubkey), Some(account.clone()));
+        assert_eq!(bank_forks[1].get_account(&pubkey), Some(account));
+        assert_eq!(bank_forks[1].capitalization(), capitalization + 42);
+    }
+
+    #[test]
+    fn test_process_blockstore_replay_report() {
+        let GenesisConfigInfo {
+            genesis_config,
+            mint_keypair,
+            ..
+        } = create_genesis_config(10_000);
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, mut last_entry_hash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        // Slot 1 holds a single transfer, slot 2 only ticks
+        let tx = system_transaction::transfer(
+            &mint_keypair,
+            &Pubkey::new_unique(),
+            1,
+            genesis_config.hash(),
+        );
+        let mut entries = vec![next_entry_mut(&mut last_entry_hash, 1, vec![tx])];
+        entries.extend(create_ticks(ticks_per_slot, 0, last_entry_hash));
+        let last_blockhash = entries.last().unwrap().hash;
+        blockstore
+            .write_entries(
+                1,
+                0,
+                0,
+                ticks_per_slot,
+                None,
+                true,
+                &Arc::new(Keypair::new()),
+                entries,
+                0,
+            )
+            .unwrap();
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 2, 1, last_blockhash);
+
+        let opts = ProcessOptions {
+            collect_replay_report: true,
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule, _bank0_hash, replay_report) =
+            process_blockstore_from_genesis(&genesis_config, &blockstore, Vec::new(), opts, None)
+                .unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![0, 1, 2]);
+        let replay_report = replay_report.unwrap();
+        assert_eq!(
+            replay_report
+                .slots
+                .iter()
+                .map(|stats| (stats.slot, stats.num_transactions))
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (2, 0)]
+        );
+        assert_eq!(replay_report.num_transactions(), 1);
+
+        let (_bank_forks, _leader_schedule, _bank0_hash, replay_report) =
+            process_blockstore_from_genesis(
+                &genesis_config,
+                &blockstore,
+                Vec::new(),
+                ProcessOptions::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(replay_report, None);
+    }
+
     #[test]
     fn test_process_blockstore_with_incomplete_slot() {
         solana_logger::setup();
@@ -1549,6 +2260,73 @@ pub mod tests {
         assert_eq!(frozen_bank_slots(&bank_forks), vec![0, 3]);
     }
 
+    #[test]
+    fn test_process_blockstore_replay_rooted_only() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        /*
+            Build a blockstore in the ledger with the following fork structure:
+
+                 slot 0
+                   |
+                 slot 1
+                 /   \
+            slot 2   slot 3 <-- set_root(true)
+                       |
+                     slot 4
+
+        */
+        let last_slot1_entry_hash =
+            fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 1, 0, blockhash);
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 2, 1, last_slot1_entry_hash);
+        let last_slot3_entry_hash = fill_blockstore_slot_with_ticks(
+            &blockstore,
+            ticks_per_slot,
+            3,
+            1,
+            last_slot1_entry_hash,
+        );
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 4, 3, last_slot3_entry_hash);
+        blockstore.set_roots(&[0, 1, 3]).unwrap();
+
+        let replayed_slots = |replay_rooted_only| {
+            let opts = ProcessOptions {
+                replay_rooted_only,
+                collect_replay_report: true,
+                ..ProcessOptions::default()
+            };
+            let (bank_forks, _leader_schedule, _bank0_hash, replay_report) =
+                process_blockstore_from_genesis(
+                    &genesis_config,
+                    &blockstore,
+                    Vec::new(),
+                    opts,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(frozen_bank_slots(&bank_forks), vec![3, 4]);
+            replay_report
+                .unwrap()
+                .slots
+                .iter()
+                .map(|stats| stats.slot)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(replayed_slots(false), vec![1, 2, 3, 4]);
+        // Slot 2 is off the rooted path, slot 4 is past the highest root and still replayed
+        assert_eq!(replayed_slots(true), vec![1, 3, 4]);
+
+        // No slot is skipped when the highest root isn't on a chain from the starting root
+        assert_eq!(
+            rooted_path(&blockstore, 0, 3),
+            Some(vec![1, 3].into_iter().collect())
+        );
+        assert_eq!(rooted_path(&blockstore, 2, 3), None);
+    }
+
     #[test]
     fn test_process_blockstore_with_two_forks_and_squash() {
         solana_logger::setup();
@@ -1628,6 +2406,101 @@ pub mod tests {
         assert_eq!(bank_forks.root(), 4);
     }
 
+    #[test]
+    fn test_process_blockstore_on_bank_pruned() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let ticks_per_slot = genesis_config.ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        /*
+            Build a blockstore in the ledger with the following fork structure:
+
+                 slot 0
+                   |
+                 slot 1
+                 /   \
+            slot 2   |
+                     |
+                   slot 3 <-- set_root(true)
+
+        */
+        let last_slot1_entry_hash =
+            fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 1, 0, blockhash);
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 2, 1, last_slot1_entry_hash);
+        fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, 3, 1, last_slot1_entry_hash);
+        blockstore.set_roots(&[0, 1, 3]).unwrap();
+
+        let pruned_slots = Arc::new(RwLock::new(vec![]));
+        let on_bank_pruned: PrunedBankCallback = {
+            let pruned_slots = pruned_slots.clone();
+            Arc::new(move |slot| pruned_slots.write().unwrap().push(slot))
+        };
+        let opts = ProcessOptions {
+            poh_verify: true,
+            on_bank_pruned: Some(on_bank_pruned),
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![3]);
+
+        // Slot 2 isn't a descendant of the root, so it was pruned during load
+        let pruned_slots = pruned_slots.read().unwrap();
+        assert!(pruned_slots.contains(&2));
+        assert!(!pruned_slots.contains(&3));
+    }
+
+    #[test]
+    fn test_process_blockstore_new_hard_forks_path() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
+        let (ledger_path, _blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+
+        let new_hard_forks_path = temp_dir.path().join("hard_forks");
+        fs::write(&new_hard_forks_path, "5\n\n3\n 5\n").unwrap();
+        assert_eq!(
+            read_hard_forks_file(&new_hard_forks_path).unwrap(),
+            vec![5, 3, 5]
+        );
+
+        // Slots from the file are merged with the in-memory list, and each is registered once
+        let opts = ProcessOptions {
+            new_hard_forks: Some(vec![7, 3]),
+            new_hard_forks_path: Some(new_hard_forks_path),
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(
+            bank_forks
+                .root_bank()
+                .hard_forks()
+                .read()
+                .unwrap()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![(3, 1), (5, 1), (7, 1)]
+        );
+
+        let malformed_path = temp_dir.path().join("malformed");
+        fs::write(&malformed_path, "5\nsix\n").unwrap();
+        assert_matches!(
+            read_hard_forks_file(&malformed_path),
+            Err(BlockstoreProcessorError::InvalidHardForksFile(..))
+        );
+        let opts = ProcessOptions {
+            new_hard_forks_path: Some(malformed_path),
+            ..ProcessOptions::default()
+        };
+        assert_matches!(
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None),
+            Err(BlockstoreProcessorError::InvalidHardForksFile(..))
+        );
+    }
+
     #[test]
     fn test_process_blockstore_with_two_forks() {
         solana_logger::setup();
@@ -2099,6 +2972,45 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn test_deferred_transaction_status_sender() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(123);
+        let bank = Arc::new(Bank::new(&genesis_config));
+        let (transaction_status_sender, subscriber) = TransactionStatusSender::new_deferred(false);
+
+        let (sender, receiver) = unbounded();
+        subscriber.attach(sender);
+        transaction_status_sender.send_transaction_status_freeze_message(&bank);
+        assert_matches!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok(TransactionStatusMessage::Freeze(0))
+        );
+        assert!(receiver.try_recv().is_err());
+
+        // Once detached, messages are dropped
+        subscriber.detach();
+        transaction_status_sender.send_transaction_status_freeze_message(&bank);
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_process_blockstore_from_genesis_bank0_hash() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(123);
+        let (ledger_path, _blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        let (bank_forks, _leader_schedule, bank0_hash, _replay_report) =
+            process_blockstore_from_genesis(
+                &genesis_config,
+                &blockstore,
+                Vec::new(),
+                ProcessOptions::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(bank_forks.get(0).unwrap().hash(), bank0_hash);
+    }
+
     #[test]
     fn test_process_ledger_options_full_leader_cache() {
         let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(123);
@@ -2112,6 +3024,16 @@ pub mod tests {
         let (_bank_forks, leader_schedule) =
             process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
         assert_eq!(leader_schedule.max_schedules(), std::usize::MAX);
+
+        let opts = ProcessOptions {
+            full_leader_cache: true,
+            max_leader_schedule_epochs: Some(4),
+            ..ProcessOptions::default()
+        };
+        let (_bank_forks, leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(leader_schedule.max_schedules(), 4);
+        assert!(leader_schedule.num_cached_schedules() <= 4);
     }
 
     #[test]
@@ -2831,6 +3753,98 @@ pub mod tests {
         assert!(bank_forks.get(0).is_some());
     }
 
+    #[test]
+    fn test_halt_at_slot_root() {
+        let GenesisConfigInfo {
+            mut genesis_config, ..
+        } = create_genesis_config(123);
+
+        let ticks_per_slot = 1;
+        genesis_config.ticks_per_slot = ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        /*
+          Build a blockstore in the ledger with the following fork structure:
+
+               slot 0 (all ticks)
+                 |
+               slot 1 (all ticks)
+                 |
+               slot 2 (all ticks)
+                 |
+               slot 3 (all ticks) -> root
+                 |
+               slot 4 (all ticks)
+                 |
+               slot 5 (all ticks) -> root
+        */
+
+        let mut last_hash = blockhash;
+        for i in 0..5 {
+            last_hash =
+                fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, i + 1, i, last_hash);
+        }
+        blockstore.set_roots(&[3, 5]).unwrap();
+
+        // Replay should stop once the root reaches slot 3, leaving slot 5 unprocessed
+        let opts = ProcessOptions {
+            poh_verify: true,
+            halt_at_slot: Some(2),
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![3]);
+        assert_eq!(bank_forks.root(), 3);
+        verify_fork_infos(&bank_forks);
+    }
+
+    #[test]
+    fn test_process_blockstore_cancel() {
+        let GenesisConfigInfo {
+            mut genesis_config, ..
+        } = create_genesis_config(123);
+
+        let ticks_per_slot = 1;
+        genesis_config.ticks_per_slot = ticks_per_slot;
+        let (ledger_path, blockhash) = create_new_tmp_ledger!(&genesis_config);
+        let blockstore = Blockstore::open(&ledger_path).unwrap();
+
+        let mut last_hash = blockhash;
+        for i in 0..3 {
+            last_hash =
+                fill_blockstore_slot_with_ticks(&blockstore, ticks_per_slot, i + 1, i, last_hash);
+        }
+        blockstore.set_roots(&[1]).unwrap();
+
+        // Cancelling before replay starts leaves only the genesis bank
+        let cancel = Arc::new(AtomicBool::new(true));
+        let opts = ProcessOptions {
+            poh_verify: true,
+            cancel: Some(cancel.clone()),
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![0]);
+        assert_eq!(bank_forks.root(), 0);
+        verify_fork_infos(&bank_forks);
+
+        // An unset flag replays everything
+        cancel.store(false, Ordering::Relaxed);
+        let opts = ProcessOptions {
+            poh_verify: true,
+            cancel: Some(cancel),
+            ..ProcessOptions::default()
+        };
+        let (bank_forks, _leader_schedule) =
+            process_blockstore(&genesis_config, &blockstore, Vec::new(), opts, None).unwrap();
+        assert_eq!(frozen_bank_slots(&bank_forks), vec![1, 2, 3]);
+        assert_eq!(bank_forks.root(), 1);
+    }
+
     #[test]
     fn test_process_blockstore_from_root() {
         let GenesisConfigInfo {
@@ -2890,9 +3904,10 @@ pub mod tests {
         bank1.squash();
 
         // Test process_blockstore_from_root() from slot 1 onwards
-        let (bank_forks, _leader_schedule) =
+        let (bank_forks, _leader_schedule, replay_report) =
             do_process_blockstore_from_root(&blockstore, bank1, &opts, &recyclers, None, None)
                 .unwrap();
+        assert_eq!(replay_report, None);
 
         assert_eq!(frozen_bank_slots(&bank_forks), vec![5, 6]);
         assert_eq!(bank_forks.working_bank().slot(), 6);
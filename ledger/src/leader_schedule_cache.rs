@@ -13,10 +13,12 @@ use solana_sdk::{
 };
 use std::{
     collections::{hash_map::Entry, HashMap, VecDeque},
+    mem,
     sync::{Arc, RwLock},
 };
 
 type CachedSchedules = (HashMap<Epoch, Arc<LeaderSchedule>>, VecDeque<u64>);
+type LeaderSlots = HashMap<Pubkey, Vec<Slot>>;
 const MAX_SCHEDULES: usize = 10;
 
 struct CacheCapacity(usize);
@@ -34,6 +36,9 @@ pub struct LeaderScheduleCache {
     max_epoch: RwLock<Epoch>,
     max_schedules: CacheCapacity,
     fixed_schedule: Option<Arc<FixedSchedule>>,
+    // Map from an epoch to the slots of each leader in that epoch, filled in on demand by
+    // `slots_for_leader()`
+    leader_slots: RwLock<HashMap<Epoch, Arc<LeaderSlots>>>,
 }
 
 impl LeaderScheduleCache {
@@ -48,6 +53,7 @@ impl LeaderScheduleCache {
             max_epoch: RwLock::new(0),
             max_schedules: CacheCapacity::default(),
             fixed_schedule: None,
+            leader_slots: RwLock::default(),
         };
 
         // This sets the root and calculates the schedule at leader_schedule_epoch(root)
@@ -72,6 +78,25 @@ impl LeaderScheduleCache {
         self.max_schedules.0
     }
 
+    /// Number of epoch leader schedules currently cached
+    pub fn num_cached_schedules(&self) -> usize {
+        self.cached_schedules.read().unwrap().0.len()
+    }
+
+    /// Approximate number of bytes held by the cached leader schedules: one leader and one
+    /// inverted index entry per slot
+    pub fn cached_schedules_memory_usage(&self) -> usize {
+        self.cached_schedules
+            .read()
+            .unwrap()
+            .0
+            .values()
+            .map(|schedule| {
+                schedule.num_slots() * (mem::size_of::<Pubkey>() + mem::size_of::<usize>())
+            })
+            .sum()
+    }
+
     pub fn set_root(&self, root_bank: &Bank) {
         let new_max_epoch = self
             .epoch_schedule
@@ -156,6 +181,49 @@ impl LeaderScheduleCache {
         Some((first_slot, last_slot))
     }
 
+    /// Compute and cache the leader schedules for the epoch of `root_bank` and the
+    /// `num_epochs` epochs after it, stopping at the first epoch whose stakes are not yet
+    /// known. Returns the number of epochs with a cached schedule.
+    pub fn warmup_epochs(&self, root_bank: &Bank, num_epochs: u64) -> usize {
+        let epoch = root_bank.epoch();
+        (epoch..=epoch.saturating_add(num_epochs))
+            .map(|epoch| self.get_epoch_schedule_else_compute(epoch, root_bank))
+            .while_some()
+            .count()
+    }
+
+    /// Returns the slots of `epoch` in which `pubkey` is scheduled to be leader, in ascending
+    /// order. Empty if the schedule for `epoch` has not been computed yet.
+    pub fn slots_for_leader(&self, pubkey: &Pubkey, epoch: Epoch) -> Vec<Slot> {
+        self.epoch_leader_slots(epoch)
+            .and_then(|leader_slots| leader_slots.get(pubkey).cloned())
+            .unwrap_or_default()
+    }
+
+    fn epoch_leader_slots(&self, epoch: Epoch) -> Option<Arc<LeaderSlots>> {
+        if let Some(leader_slots) = self.leader_slots.read().unwrap().get(&epoch) {
+            return Some(leader_slots.clone());
+        }
+        let leader_schedule = self.get_epoch_schedule_no_compute(epoch)?;
+        let first_slot = self.epoch_schedule.get_first_slot_in_epoch(epoch);
+        let mut leader_slots = LeaderSlots::new();
+        for slot_index in 0..self.epoch_schedule.get_slots_in_epoch(epoch) {
+            leader_slots
+                .entry(leader_schedule[slot_index])
+                .or_default()
+                .push(first_slot + slot_index);
+        }
+        let leader_slots = Arc::new(leader_slots);
+
+        let mut cached_leader_slots = self.leader_slots.write().unwrap();
+        cached_leader_slots.insert(epoch, leader_slots.clone());
+        while cached_leader_slots.len() > self.max_schedules() {
+            let oldest_epoch = *cached_leader_slots.keys().min().unwrap();
+            cached_leader_slots.remove(&oldest_epoch);
+        }
+        Some(leader_slots)
+    }
+
     pub fn set_fixed_leader_schedule(&mut self, fixed_schedule: Option<FixedSchedule>) {
         self.fixed_schedule = fixed_schedule.map(Arc::new);
     }
@@ -199,17 +267,21 @@ impl LeaderScheduleCache {
         self.cached_schedules.read().unwrap().0.get(&epoch).cloned()
     }
 
-    fn get_epoch_schedule_else_compute(
-        &self,
-        epoch: Epoch,
-        bank: &Bank,
-    ) -> Option<Arc<LeaderSchedule>> {
+    fn get_epoch_schedule_no_compute(&self, epoch: Epoch) -> Option<Arc<LeaderSchedule>> {
         if let Some(ref fixed_schedule) = self.fixed_schedule {
             if epoch >= fixed_schedule.start_epoch {
                 return Some(fixed_schedule.leader_schedule.clone());
             }
         }
-        let epoch_schedule = self.get_epoch_leader_schedule(epoch);
+        self.get_epoch_leader_schedule(epoch)
+    }
+
+    fn get_epoch_schedule_else_compute(
+        &self,
+        epoch: Epoch,
+        bank: &Bank,
+    ) -> Option<Arc<LeaderSchedule>> {
+        let epoch_schedule = self.get_epoch_schedule_no_compute(epoch);
         if epoch_schedule.is_some() {
             epoch_schedule
         } else {
@@ -617,4 +689,54 @@ mod tests {
         cache.set_max_schedules(std::usize::MAX);
         assert_eq!(cache.max_schedules(), std::usize::MAX);
     }
+
+    #[test]
+    fn test_warmup_epochs() {
+        let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(2);
+        let bank = Bank::new(&genesis_config);
+        let cache = LeaderScheduleCache::new_from_bank(&bank);
+        let leader_schedule_epoch = bank.get_leader_schedule_epoch(bank.slot());
+
+        assert_eq!(cache.warmup_epochs(&bank, 0), 1);
+
+        // Schedules can't be computed past the leader schedule epoch of the bank
+        assert_eq!(
+            cache.warmup_epochs(&bank, leader_schedule_epoch + 5),
+            leader_schedule_epoch as usize + 1
+        );
+        assert!(cache
+            .get_epoch_leader_schedule(leader_schedule_epoch)
+            .is_some());
+    }
+
+    #[test]
+    fn test_slots_for_leader() {
+        let pubkey = solana_sdk::pubkey::new_rand();
+        let mut genesis_config =
+            create_genesis_config_with_leader(42, &pubkey, bootstrap_validator_stake_lamports())
+                .genesis_config;
+        genesis_config.epoch_schedule =
+            EpochSchedule::custom(MINIMUM_SLOTS_PER_EPOCH, MINIMUM_SLOTS_PER_EPOCH, false);
+
+        let bank = Bank::new(&genesis_config);
+        let cache = LeaderScheduleCache::new_from_bank(&bank);
+
+        // The bootstrap validator leads every slot of the epochs with a schedule
+        for epoch in 0..=1 {
+            let first_slot = genesis_config.epoch_schedule.get_first_slot_in_epoch(epoch);
+            let expected: Vec<_> = (first_slot..first_slot + MINIMUM_SLOTS_PER_EPOCH).collect();
+            assert_eq!(cache.slots_for_leader(&pubkey, epoch), expected);
+            for slot in expected {
+                assert_eq!(cache.slot_leader_at(slot, None), Some(pubkey));
+            }
+        }
+        assert_eq!(cache.leader_slots.read().unwrap().len(), 2);
+
+        // Unknown leader, and an epoch without a computed schedule
+        assert!(cache
+            .slots_for_leader(&solana_sdk::pubkey::new_rand(), 0)
+            .is_empty());
+        assert!(cache.slots_for_leader(&pubkey, 2).is_empty());
+        assert_eq!(cache.leader_slots.read().unwrap().len(), 2);
+    }
 }
@@ -24,7 +24,7 @@ use solana_sdk::{
     fee_calculator::FeeCalculator,
     genesis_config::ClusterType,
     hash::Hash,
-    message::Message,
+    message::{Message, SerializeInstructionsOptions},
     native_loader, nonce,
     nonce::NONCED_TX_MARKER_IX_INDEX,
     pubkey::Pubkey,
@@ -172,9 +172,9 @@ impl Accounts {
 
     fn construct_instructions_account(
         message: &Message,
-        demote_sysvar_write_locks: bool,
+        options: SerializeInstructionsOptions,
     ) -> AccountSharedData {
-        let mut data = message.serialize_instructions(demote_sysvar_write_locks);
+        let mut data = message.serialize_instructions_with_options(options);
         // add room for current instruction index.
         data.resize(data.len() + 2, 0);
         AccountSharedData::from(Account {
@@ -219,7 +219,17 @@ impl Accounts {
                         if message.is_writable(i, demote_sysvar_write_locks) {
                             return Err(TransactionError::InvalidAccountIndex);
                         }
-                        Self::construct_instructions_account(message, demote_sysvar_write_locks)
+                        Self::construct_instructions_account(
+                            message,
+                            SerializeInstructionsOptions {
+                                demote_sysvar_write_locks,
+                                demote_program_write_locks: feature_set
+                                    .is_active(&feature_set::demote_program_write_locks::id()),
+                                mark_layout_version: feature_set.is_active(
+                                    &feature_set::instructions_sysvar_layout_version::id(),
+                                ),
+                            },
+                        )
                     } else {
                         let (account, rent) = self
                             .accounts_db
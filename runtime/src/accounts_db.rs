@@ -681,6 +681,15 @@ impl RecycleStores {
     }
 }
 
+/// Progress of `AccountsDb::generate_index()`, to poll while a snapshot is loading
+#[derive(Debug, Default)]
+pub struct GenerateIndexProgress {
+    /// Approximate number of stored accounts to index, set once indexing starts
+    pub total_accounts: AtomicU64,
+    /// Stored accounts indexed so far
+    pub accounts_processed: AtomicU64,
+}
+
 // This structure handles the load/store of the accounts
 #[derive(Debug)]
 pub struct AccountsDb {
@@ -4431,11 +4440,25 @@ impl AccountsDb {
             .collect()
     }
 
-    pub fn generate_index(&self) {
+    pub fn generate_index(&self, progress: Option<&GenerateIndexProgress>) {
         type AccountsMap<'a> = HashMap<Pubkey, BTreeMap<u64, (AppendVecId, StoredAccountMeta<'a>)>>;
         let mut slots = self.storage.all_slots();
         #[allow(clippy::stable_sort_primitive)]
         slots.sort();
+        if let Some(progress) = progress {
+            let total_accounts: usize = slots
+                .iter()
+                .flat_map(|slot| {
+                    self.storage
+                        .get_slot_storage_entries(*slot)
+                        .unwrap_or_default()
+                })
+                .map(|storage| storage.approx_stored_count())
+                .sum();
+            progress
+                .total_accounts
+                .store(total_accounts as u64, Ordering::Relaxed);
+        }
 
         let mut last_log_update = Instant::now();
         for (index, slot) in slots.iter().enumerate() {
@@ -4499,6 +4522,11 @@ impl AccountsDb {
                     }
                 }
             }
+            if let Some(progress) = progress {
+                progress
+                    .accounts_processed
+                    .fetch_add(num_accounts as u64, Ordering::Relaxed);
+            }
         }
 
         // Need to add these last, otherwise older updates will be cleaned
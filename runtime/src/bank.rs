@@ -8,12 +8,12 @@ use crate::{
         TransactionLoadResult, TransactionLoaders,
     },
     accounts_db::{ErrorCounters, SnapshotStorages},
-    accounts_index::{AccountSecondaryIndexes, Ancestors, IndexKey},
+    accounts_index::{AccountIndex, AccountSecondaryIndexes, Ancestors, IndexKey},
     blockhash_queue::BlockhashQueue,
     builtins::{self, ActivationType},
     epoch_stakes::{EpochStakes, NodeVoteAccounts},
     hashed_transaction::{HashedTransaction, HashedTransactionSlice},
-    inline_spl_token_v2_0,
+    inline_spl_token_v2_0::{self, SPL_TOKEN_ACCOUNT_AMOUNT_OFFSET, SPL_TOKEN_ACCOUNT_MINT_OFFSET},
     instruction_recorder::InstructionRecorder,
     log_collector::LogCollector,
     message_processor::{ExecuteDetailsTimings, Executors, MessageProcessor},
@@ -62,7 +62,7 @@ use solana_sdk::{
     nonce, nonce_account,
     process_instruction::{BpfComputeBudget, Executor, ProcessInstructionWithContext},
     program_utils::limited_deserialize,
-    pubkey::Pubkey,
+    pubkey::{Pubkey, PUBKEY_BYTES},
     recent_blockhashes_account,
     sanitize::Sanitize,
     signature::{Keypair, Signature},
@@ -4057,7 +4057,7 @@ impl Bank {
 
     /// Technically this issues (or even burns!) new lamports,
     /// so be extra careful for its usage
-    fn store_account_and_update_capitalization(
+    pub fn store_account_and_update_capitalization(
         &self,
         pubkey: &Pubkey,
         new_account: &AccountSharedData,
@@ -4191,6 +4191,19 @@ impl Bank {
             .load_by_program(&self.ancestors, program_id)
     }
 
+    /// Change the owner of every account owned by `old_owner` to `new_owner`, returning the
+    /// addresses of the accounts changed
+    pub fn reassign_program_accounts(&self, old_owner: &Pubkey, new_owner: &Pubkey) -> Vec<Pubkey> {
+        self.get_program_accounts(old_owner)
+            .into_iter()
+            .map(|(pubkey, mut account)| {
+                account.owner = *new_owner;
+                self.store_account(&pubkey, &account);
+                pubkey
+            })
+            .collect()
+    }
+
     pub fn get_filtered_program_accounts<F: Fn(&AccountSharedData) -> bool>(
         &self,
         program_id: &Pubkey,
@@ -4211,6 +4224,45 @@ impl Bank {
             .load_by_index_key_with_filter(&self.ancestors, index_key, filter)
     }
 
+    /// Every token account of `mint` with its token amount. Uses the token mint secondary index
+    /// if it covers `mint`, and scans all token program accounts otherwise.
+    pub fn get_token_account_balances_by_mint(&self, mint: &Pubkey) -> Vec<(Pubkey, u64)> {
+        // The index may still hold accounts that were closed or reinitialized, so the owner,
+        // size and mint of every account are checked again
+        let is_token_account_of_mint = |account: &AccountSharedData| {
+            account.owner == inline_spl_token_v2_0::id()
+                && account.data().len() == inline_spl_token_v2_0::state::Account::get_packed_len()
+                && account.data()
+                    [SPL_TOKEN_ACCOUNT_MINT_OFFSET..SPL_TOKEN_ACCOUNT_MINT_OFFSET + PUBKEY_BYTES]
+                    == mint.as_ref()[..]
+        };
+        let account_indexes = &self.rc.accounts.accounts_db.account_indexes;
+        let token_accounts = if account_indexes.contains(&AccountIndex::SafeTokenMint)
+            && account_indexes.include_key(mint)
+        {
+            self.get_filtered_indexed_accounts(
+                &IndexKey::SafeTokenMint(*mint),
+                is_token_account_of_mint,
+            )
+        } else {
+            self.get_filtered_program_accounts(
+                &inline_spl_token_v2_0::id(),
+                is_token_account_of_mint,
+            )
+        };
+        token_accounts
+            .into_iter()
+            .map(|(pubkey, account)| {
+                let amount = account.data()
+                    [SPL_TOKEN_ACCOUNT_AMOUNT_OFFSET..SPL_TOKEN_ACCOUNT_AMOUNT_OFFSET + 8]
+                    .try_into()
+                    .map(u64::from_le_bytes)
+                    .unwrap();
+                (pubkey, amount)
+            })
+            .collect()
+    }
+
     pub fn account_indexes_include_key(&self, key: &Pubkey) -> bool {
         self.rc.accounts.account_indexes_include_key(key)
     }
@@ -9028,6 +9080,86 @@ pub(crate) mod tests {
         assert_eq!(bank3.get_program_accounts(&program_id).len(), 2);
     }
 
+    #[test]
+    fn test_bank_reassign_program_accounts() {
+        let (genesis_config, _mint_keypair) = create_genesis_config(500);
+        let bank0 = Arc::new(Bank::new(&genesis_config));
+        let old_owner = Pubkey::new_unique();
+        let new_owner = Pubkey::new_unique();
+        let pubkey0 = Pubkey::new_unique();
+        let pubkey1 = Pubkey::new_unique();
+        bank0.store_account(&pubkey0, &AccountSharedData::new(1, 3, &old_owner));
+        bank0.store_account(
+            &pubkey1,
+            &AccountSharedData::new(2, 0, &Pubkey::new_unique()),
+        );
+
+        let bank1 = new_from_parent(&bank0);
+        assert_eq!(
+            bank1.reassign_program_accounts(&old_owner, &new_owner),
+            vec![pubkey0]
+        );
+        assert!(bank1.get_program_accounts(&old_owner).is_empty());
+        assert_eq!(
+            bank1.get_account(&pubkey0).unwrap(),
+            AccountSharedData::new(1, 3, &new_owner)
+        );
+        // The parent is unchanged
+        assert_eq!(bank0.get_account(&pubkey0).unwrap().owner, old_owner);
+
+        assert!(bank1
+            .reassign_program_accounts(&old_owner, &new_owner)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_token_account_balances_by_mint() {
+        let token_account_owned_by = |mint: &Pubkey, amount: u64, owner: Pubkey| {
+            let mut data = vec![0; inline_spl_token_v2_0::state::Account::get_packed_len()];
+            data[SPL_TOKEN_ACCOUNT_MINT_OFFSET..SPL_TOKEN_ACCOUNT_MINT_OFFSET + PUBKEY_BYTES]
+                .copy_from_slice(mint.as_ref());
+            data[SPL_TOKEN_ACCOUNT_AMOUNT_OFFSET..SPL_TOKEN_ACCOUNT_AMOUNT_OFFSET + 8]
+                .copy_from_slice(&amount.to_le_bytes());
+            AccountSharedData::from(Account {
+                lamports: 1,
+                data,
+                owner,
+                ..Account::default()
+            })
+        };
+        let token_account = |mint: &Pubkey, amount: u64| {
+            token_account_owned_by(mint, amount, inline_spl_token_v2_0::id())
+        };
+        let mint = Pubkey::new_unique();
+        let other_mint = Pubkey::new_unique();
+        let address0 = Pubkey::new_unique();
+        let address1 = Pubkey::new_unique();
+
+        let (genesis_config, _mint_keypair) = create_genesis_config(500);
+        let mut with_mint_index = AccountSecondaryIndexes::default();
+        with_mint_index.indexes.insert(AccountIndex::SafeTokenMint);
+        for account_indexes in [AccountSecondaryIndexes::default(), with_mint_index].iter() {
+            let bank = Bank::new_with_config(&genesis_config, account_indexes.clone(), false);
+            bank.store_account(&address0, &token_account(&mint, 42));
+            bank.store_account(&address1, &token_account(&mint, 7));
+            bank.store_account(&Pubkey::new_unique(), &token_account(&other_mint, 1));
+            // Right layout, but not owned by the token program
+            bank.store_account(
+                &Pubkey::new_unique(),
+                &token_account_owned_by(&mint, 1, Pubkey::new_unique()),
+            );
+
+            let mut balances = bank.get_token_account_balances_by_mint(&mint);
+            balances.sort();
+            let mut expected = vec![(address0, 42), (address1, 7)];
+            expected.sort();
+            assert_eq!(balances, expected);
+            assert!(bank
+                .get_token_account_balances_by_mint(&Pubkey::new_unique())
+                .is_empty());
+        }
+    }
+
     #[test]
     fn test_get_filtered_indexed_accounts() {
         let (genesis_config, _mint_keypair) = create_genesis_config(500);
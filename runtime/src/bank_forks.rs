@@ -9,8 +9,9 @@ use solana_metrics::inc_new_counter_info;
 use solana_sdk::{clock::Slot, hash::Hash, timing};
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
+    fs,
     ops::Index,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
     time::Instant,
 };
@@ -42,6 +43,27 @@ pub struct SnapshotConfig {
     pub snapshot_version: SnapshotVersion,
 }
 
+impl SnapshotConfig {
+    /// Check that wiping `snapshot_path`, as is done when loading from a snapshot, cannot delete
+    /// the snapshot archives. `snapshot_path` may live inside `snapshot_package_output_path`, but
+    /// the archives may not be in `snapshot_path` or one of its subdirectories.
+    pub fn validate(&self) -> Result<(), String> {
+        // Directories that don't exist yet are compared as given
+        let canonicalize =
+            |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let snapshot_path = canonicalize(&self.snapshot_path);
+        let snapshot_package_output_path = canonicalize(&self.snapshot_package_output_path);
+        if snapshot_package_output_path.starts_with(&snapshot_path) {
+            return Err(format!(
+                "snapshot archive directory {:?} is within the bank snapshot directory {:?}, \
+                which is cleared on startup",
+                self.snapshot_package_output_path, self.snapshot_path
+            ));
+        }
+        Ok(())
+    }
+}
+
 pub struct BankForks {
     banks: HashMap<Slot, Arc<Bank>>,
     descendants: HashMap<Slot, HashSet<Slot>>,
@@ -342,6 +364,53 @@ mod tests {
     };
     use solana_vote_program::vote_state::BlockTimestamp;
 
+    #[test]
+    fn test_snapshot_config_validate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let snapshot_config =
+            |snapshot_path: PathBuf, snapshot_package_output_path: PathBuf| SnapshotConfig {
+                snapshot_interval_slots: 100,
+                snapshot_package_output_path,
+                snapshot_path,
+                archive_format: ArchiveFormat::Tar,
+                snapshot_version: SnapshotVersion::default(),
+            };
+
+        // The usual layout, bank snapshots inside the archive directory
+        assert!(snapshot_config(
+            temp_dir.path().join("snapshot"),
+            temp_dir.path().to_path_buf()
+        )
+        .validate()
+        .is_ok());
+        assert!(
+            snapshot_config(temp_dir.path().join("a"), temp_dir.path().join("b"))
+                .validate()
+                .is_ok()
+        );
+
+        assert!(
+            snapshot_config(temp_dir.path().to_path_buf(), temp_dir.path().to_path_buf())
+                .validate()
+                .is_err()
+        );
+        assert!(snapshot_config(
+            temp_dir.path().to_path_buf(),
+            temp_dir.path().join("archives")
+        )
+        .validate()
+        .is_err());
+
+        // Equal once canonicalized
+        fs::create_dir_all(temp_dir.path().join("snapshot")).unwrap();
+        assert!(snapshot_config(
+            temp_dir.path().join("snapshot"),
+            temp_dir.path().join("snapshot").join("..").join("snapshot")
+        )
+        .validate()
+        .is_err());
+    }
+
     #[test]
     fn test_bank_forks_new() {
         let GenesisConfigInfo { genesis_config, .. } = create_genesis_config(10_000);
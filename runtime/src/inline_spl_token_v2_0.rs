@@ -1,7 +1,7 @@
 // Partial SPL Token v2.0.x declarations inlined to avoid an external dependency on the safe-token crate
 solana_sdk::declare_id!("7v5TwK92hUSqduoL3R8NtzTNfNzMA48nJL4mzPYMdDrD");
 
-pub(crate) mod new_token_program {
+pub mod new_token_program {
     solana_sdk::declare_id!("t31zsgDmRntje65uXV3LrnWaJtJJpMd4LyJxq2R2VrU");
 }
 
@@ -19,6 +19,7 @@ pub(crate) mod new_token_program {
 */
 pub const SPL_TOKEN_ACCOUNT_MINT_OFFSET: usize = 0;
 pub const SPL_TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+pub const SPL_TOKEN_ACCOUNT_AMOUNT_OFFSET: usize = 64;
 
 pub mod state {
     const LEN: usize = 165;
@@ -1,7 +1,9 @@
 use {
     crate::{
         accounts::Accounts,
-        accounts_db::{AccountStorageEntry, AccountsDb, AppendVecId, BankHashInfo},
+        accounts_db::{
+            AccountStorageEntry, AccountsDb, AppendVecId, BankHashInfo, GenerateIndexProgress,
+        },
         accounts_index::{AccountSecondaryIndexes, Ancestors},
         append_vec::AppendVec,
         bank::{Bank, BankFieldsToDeserialize, BankRc, Builtins},
@@ -129,6 +131,7 @@ pub(crate) fn bank_from_stream<R>(
     additional_builtins: Option<&Builtins>,
     account_indexes: AccountSecondaryIndexes,
     caching_enabled: bool,
+    generate_index_progress: Option<&GenerateIndexProgress>,
 ) -> std::result::Result<Bank, Error>
 where
     R: Read,
@@ -148,6 +151,7 @@ where
                 additional_builtins,
                 account_indexes,
                 caching_enabled,
+                generate_index_progress,
             )?;
             Ok(bank)
         }};
@@ -237,6 +241,7 @@ fn reconstruct_bank_from_fields<E>(
     additional_builtins: Option<&Builtins>,
     account_indexes: AccountSecondaryIndexes,
     caching_enabled: bool,
+    generate_index_progress: Option<&GenerateIndexProgress>,
 ) -> Result<Bank, Error>
 where
     E: SerializableStorage,
@@ -248,6 +253,7 @@ where
         &genesis_config.cluster_type,
         account_indexes,
         caching_enabled,
+        generate_index_progress,
     )?;
     accounts_db.freeze_accounts(&bank_fields.ancestors, frozen_account_pubkeys);
 
@@ -270,6 +276,7 @@ fn reconstruct_accountsdb_from_fields<E>(
     cluster_type: &ClusterType,
     account_indexes: AccountSecondaryIndexes,
     caching_enabled: bool,
+    generate_index_progress: Option<&GenerateIndexProgress>,
 ) -> Result<AccountsDb, Error>
 where
     E: SerializableStorage,
@@ -362,6 +369,6 @@ where
     accounts_db
         .write_version
         .fetch_add(version, Ordering::Relaxed);
-    accounts_db.generate_index();
+    accounts_db.generate_index(generate_index_progress);
     Ok(accounts_db)
 }
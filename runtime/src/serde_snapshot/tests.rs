@@ -73,6 +73,7 @@ where
         &ClusterType::Development,
         AccountSecondaryIndexes::default(),
         false,
+        None,
     )
 }
 
@@ -215,6 +216,7 @@ fn test_bank_serialize_style(serde_style: SerdeStyle) {
     let copied_accounts = TempDir::new().unwrap();
     let unpacked_append_vec_map =
         copy_append_vecs(&bank2.rc.accounts.accounts_db, copied_accounts.path()).unwrap();
+    let generate_index_progress = GenerateIndexProgress::default();
     let mut dbank = crate::serde_snapshot::bank_from_stream(
         serde_style,
         &mut reader,
@@ -226,8 +228,19 @@ fn test_bank_serialize_style(serde_style: SerdeStyle) {
         None,
         AccountSecondaryIndexes::default(),
         false,
+        Some(&generate_index_progress),
     )
     .unwrap();
+    let total_accounts = generate_index_progress
+        .total_accounts
+        .load(Ordering::Relaxed);
+    assert!(total_accounts > 0);
+    assert_eq!(
+        generate_index_progress
+            .accounts_processed
+            .load(Ordering::Relaxed),
+        total_accounts
+    );
     dbank.src = ref_sc;
     assert_eq!(dbank.get_balance(&key1.pubkey()), 0);
     assert_eq!(dbank.get_balance(&key2.pubkey()), 10);
@@ -183,6 +183,18 @@ pub struct MessageHeader {
     pub num_readonly_unsigned_accounts: u8,
 }
 
+/// Feature-dependent choices of how `Message::serialize_instructions_with_options` lays out the
+/// instructions sysvar data
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SerializeInstructionsOptions {
+    /// Report builtin programs and sysvars readonly
+    pub demote_sysvar_write_locks: bool,
+    /// Report keys the message invokes as programs readonly
+    pub demote_program_write_locks: bool,
+    /// Write the layout version byte, see `sysvar::instructions::LAYOUT_VERSION`
+    pub mark_layout_version: bool,
+}
+
 #[frozen_abi(digest = "BPBJZxpRQ4JS7LGJtsgoyctg4BXyBbbY4uc7FjowtxLV")]
 #[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq, Clone, AbiExample)]
 #[serde(rename_all = "camelCase")]
@@ -348,6 +360,16 @@ impl Message {
         false
     }
 
+    pub fn is_key_called_as_program(&self, key_index: usize) -> bool {
+        if let Ok(key_index) = u8::try_from(key_index) {
+            self.instructions
+                .iter()
+                .any(|ix| ix.program_id_index == key_index)
+        } else {
+            false
+        }
+    }
+
     pub fn is_non_loader_key(&self, key: &Pubkey, key_index: usize) -> bool {
         !self.program_ids().contains(&key) || self.is_key_passed_to_program(key_index)
     }
@@ -380,6 +402,23 @@ impl Message {
         i < self.header.num_required_signatures as usize
     }
 
+    /// Writability of each account key as seen by the programs in this message
+    ///
+    /// With `demote_program_write_locks`, keys invoked as programs by the message are reported
+    /// readonly, since the runtime never stores changes to the executable accounts behind them.
+    pub fn writable_accounts(
+        &self,
+        demote_sysvar_write_locks: bool,
+        demote_program_write_locks: bool,
+    ) -> Vec<bool> {
+        (0..self.account_keys.len())
+            .map(|i| {
+                self.is_writable(i, demote_sysvar_write_locks)
+                    && !(demote_program_write_locks && self.is_key_called_as_program(i))
+            })
+            .collect()
+    }
+
     pub fn get_account_keys_by_lock_type(
         &self,
         demote_sysvar_write_locks: bool,
@@ -402,6 +441,9 @@ impl Message {
     // Then a table of offsets of where to find them in the data
     //  3..2 * num_instructions table of instruction offsets
     //
+    // Then, if there are any instructions and `options.mark_layout_version` is set, the layout
+    // version byte, see `sysvar::instructions::LAYOUT_VERSION`
+    //
     // Each instruction is then encoded as:
     //   0..2 - num_accounts
     //   2 - meta_byte -> (bit 0 signer, bit 1 is_writable)
@@ -410,12 +452,33 @@ impl Message {
     //   67..69 - data len - u16
     //   69..data_len - data
     pub fn serialize_instructions(&self, demote_sysvar_write_locks: bool) -> Vec<u8> {
+        self.serialize_instructions_with_options(SerializeInstructionsOptions {
+            demote_sysvar_write_locks,
+            ..SerializeInstructionsOptions::default()
+        })
+    }
+
+    /// Serialize the instructions as `serialize_instructions` does, with the layout choices of
+    /// `options`
+    pub fn serialize_instructions_with_options(
+        &self,
+        options: SerializeInstructionsOptions,
+    ) -> Vec<u8> {
+        let writable_accounts = self.writable_accounts(
+            options.demote_sysvar_write_locks,
+            options.demote_program_write_locks,
+        );
         // 64 bytes is a reasonable guess, calculating exactly is slower in benchmarks
         let mut data = Vec::with_capacity(self.instructions.len() * (32 * 2));
         append_u16(&mut data, self.instructions.len() as u16);
         for _ in 0..self.instructions.len() {
             append_u16(&mut data, 0);
         }
+        // Readers locate instructions through the offset table, so the marker between it and
+        // the first instruction is invisible to those unaware of it
+        if options.mark_layout_version && !self.instructions.is_empty() {
+            append_u8(&mut data, sysvar::instructions::LAYOUT_VERSION);
+        }
         for (i, instruction) in self.instructions.iter().enumerate() {
             let start_instruction_offset = data.len() as u16;
             let start = 2 + (2 * i);
@@ -424,7 +487,7 @@ impl Message {
             for account_index in &instruction.accounts {
                 let account_index = *account_index as usize;
                 let is_signer = self.is_signer(account_index);
-                let is_writable = self.is_writable(account_index, demote_sysvar_write_locks);
+                let is_writable = writable_accounts[account_index];
                 let mut meta_byte = 0;
                 if is_signer {
                     meta_byte |= 1 << Self::IS_SIGNER_BIT;
@@ -961,6 +1024,226 @@ mod tests {
         );
     }
 
+    // Serialize `instructions` and read every one of them back, checking that each account meta
+    // carries the signer and writable flags the compiled message gives its key
+    fn assert_instructions_roundtrip(instructions: &[Instruction]) {
+        let demotions = [(false, false), (true, false), (false, true), (true, true)];
+        for (demote_sysvar_write_locks, demote_program_write_locks) in demotions.iter().copied() {
+            let message = Message::new(instructions, None);
+            let serialized =
+                message.serialize_instructions_with_options(SerializeInstructionsOptions {
+                    demote_sysvar_write_locks,
+                    demote_program_write_locks,
+                    mark_layout_version: true,
+                });
+            assert_eq!(
+                read_u16(&mut 0, &serialized).unwrap() as usize,
+                instructions.len()
+            );
+            for (i, instruction) in instructions.iter().enumerate() {
+                let loaded = sysvar::instructions::load_instruction_at(i, &serialized).unwrap();
+                assert_eq!(loaded.program_id, instruction.program_id);
+                assert_eq!(loaded.data, instruction.data);
+                assert_eq!(loaded.accounts.len(), instruction.accounts.len());
+                for (loaded_meta, meta) in loaded.accounts.iter().zip(&instruction.accounts) {
+                    let metas = instructions
+                        .iter()
+                        .flat_map(|instruction| &instruction.accounts)
+                        .filter(|other| other.pubkey == meta.pubkey);
+                    let is_signer = metas.clone().any(|other| other.is_signer);
+                    let is_demoted = (demote_sysvar_write_locks
+                        && (sysvar::is_sysvar_id(&meta.pubkey)
+                            || BUILTIN_PROGRAMS_KEYS.contains(&meta.pubkey)))
+                        || (demote_program_write_locks
+                            && instructions
+                                .iter()
+                                .any(|instruction| instruction.program_id == meta.pubkey));
+                    let is_writable = metas.clone().any(|other| other.is_writable) && !is_demoted;
+                    assert_eq!(
+                        *loaded_meta,
+                        AccountMeta {
+                            pubkey: meta.pubkey,
+                            is_signer,
+                            is_writable,
+                        },
+                        "instruction {}, demote_sysvar_write_locks: {}, demote_program_write_locks: {}",
+                        i,
+                        demote_sysvar_write_locks,
+                        demote_program_write_locks
+                    );
+                }
+            }
+            assert_eq!(
+                sysvar::instructions::load_instruction_at(instructions.len(), &serialized)
+                    .unwrap_err(),
+                SanitizeError::IndexOutOfBounds
+            );
+        }
+    }
+
+    #[test]
+    fn test_serialize_instructions_roundtrip() {
+        assert_instructions_roundtrip(&[]);
+
+        let program_id0 = Pubkey::new_unique();
+        let program_id1 = Pubkey::new_unique();
+        let id0 = Pubkey::new_unique();
+        let id1 = Pubkey::new_unique();
+        let id2 = Pubkey::new_unique();
+        assert_instructions_roundtrip(&[
+            Instruction::new_with_bincode(
+                program_id0,
+                &0,
+                vec![
+                    AccountMeta::new(id0, true),
+                    AccountMeta::new_readonly(id1, false),
+                    AccountMeta::new(sysvar::clock::id(), false),
+                ],
+            ),
+            Instruction::new_with_bincode(
+                program_id1,
+                &[1u8; 3],
+                vec![
+                    AccountMeta::new_readonly(id0, false),
+                    AccountMeta::new(id1, true),
+                    AccountMeta::new(system_program::id(), false),
+                    AccountMeta::new_readonly(id2, false),
+                ],
+            ),
+            Instruction::new_with_bytes(
+                program_id0,
+                &[],
+                vec![
+                    AccountMeta::new(program_id1, false),
+                    AccountMeta::new_readonly(sysvar::clock::id(), false),
+                ],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_writable_accounts() {
+        let program_id0 = Pubkey::new_unique();
+        let program_id1 = Pubkey::new_unique();
+        let id0 = Pubkey::new_unique();
+        let message = Message::new(
+            &[
+                Instruction::new_with_bincode(
+                    program_id0,
+                    &0,
+                    vec![
+                        AccountMeta::new(id0, true),
+                        AccountMeta::new(program_id1, false),
+                        AccountMeta::new(sysvar::clock::id(), false),
+                    ],
+                ),
+                Instruction::new_with_bincode(program_id1, &0, vec![]),
+            ],
+            None,
+        );
+        assert_eq!(
+            message.account_keys,
+            vec![id0, program_id1, sysvar::clock::id(), program_id0]
+        );
+        assert!(!message.is_key_called_as_program(0));
+        assert!(message.is_key_called_as_program(1));
+        assert!(!message.is_key_called_as_program(2));
+        assert!(message.is_key_called_as_program(3));
+        assert!(!message.is_key_called_as_program(4));
+
+        assert_eq!(
+            message.writable_accounts(false, false),
+            vec![true, true, true, false]
+        );
+        assert_eq!(
+            message.writable_accounts(true, false),
+            vec![true, true, false, false]
+        );
+        assert_eq!(
+            message.writable_accounts(false, true),
+            vec![true, false, true, false]
+        );
+        assert_eq!(
+            message.writable_accounts(true, true),
+            vec![true, false, false, false]
+        );
+    }
+
+    // Check that each account meta of each instruction in `serialized`, the instructions sysvar
+    // data of `message` with program write locks demoted, is writable exactly when `message`
+    // makes its key writable and does not invoke it as a program
+    fn assert_writability_matches(
+        message: &Message,
+        serialized: &[u8],
+        demote_sysvar_write_locks: bool,
+    ) {
+        for (i, instruction) in message.instructions.iter().enumerate() {
+            let loaded = sysvar::instructions::load_instruction_at(i, serialized).unwrap();
+            assert_eq!(loaded.accounts.len(), instruction.accounts.len());
+            for (loaded_meta, account_index) in loaded.accounts.iter().zip(&instruction.accounts) {
+                let account_index = *account_index as usize;
+                assert_eq!(loaded_meta.pubkey, message.account_keys[account_index]);
+                let is_writable = message.is_writable(account_index, demote_sysvar_write_locks)
+                    && !message.is_key_called_as_program(account_index);
+                assert_eq!(
+                    loaded_meta.is_writable, is_writable,
+                    "instruction {}, account {}, demote_sysvar_write_locks: {}",
+                    i, loaded_meta.pubkey, demote_sysvar_write_locks
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize_instructions_demotes_programs() {
+        let program_id0 = Pubkey::new_unique();
+        let program_id1 = Pubkey::new_unique();
+        let id0 = Pubkey::new_unique();
+        let message = Message::new(
+            &[
+                Instruction::new_with_bincode(
+                    program_id0,
+                    &0,
+                    vec![
+                        AccountMeta::new(id0, true),
+                        AccountMeta::new(program_id1, false),
+                        AccountMeta::new(sysvar::clock::id(), false),
+                        AccountMeta::new(program_id0, false),
+                    ],
+                ),
+                Instruction::new_with_bincode(
+                    program_id1,
+                    &0,
+                    vec![
+                        AccountMeta::new_readonly(program_id1, false),
+                        AccountMeta::new(system_program::id(), false),
+                    ],
+                ),
+            ],
+            None,
+        );
+        for demote_sysvar_write_locks in [false, true].iter().copied() {
+            let serialized =
+                message.serialize_instructions_with_options(SerializeInstructionsOptions {
+                    demote_sysvar_write_locks,
+                    demote_program_write_locks: true,
+                    mark_layout_version: true,
+                });
+            assert_writability_matches(&message, &serialized, demote_sysvar_write_locks);
+        }
+
+        // Without the demotion, programs passed as writable accounts are reported writable
+        let serialized =
+            message.serialize_instructions_with_options(SerializeInstructionsOptions {
+                demote_sysvar_write_locks: true,
+                demote_program_write_locks: false,
+                mark_layout_version: true,
+            });
+        let loaded = sysvar::instructions::load_instruction_at(0, &serialized).unwrap();
+        assert!(loaded.accounts[1].is_writable);
+        assert!(loaded.accounts[3].is_writable);
+    }
+
     #[test]
     fn test_program_ids() {
         let key0 = Pubkey::new_unique();
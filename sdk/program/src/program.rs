@@ -1,4 +1,7 @@
-use crate::{account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction};
+use crate::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction, msg,
+    program_error::ProgramError, pubkey::Pubkey,
+};
 
 /// Invoke a cross-program instruction
 ///
@@ -33,6 +36,62 @@ pub fn invoke_signed(
         }
     }
 
+    invoke_signed_syscall(instruction, account_infos, signers_seeds)
+}
+
+/// Invoke a cross-program instruction with program signatures, first checking that every
+/// signer the instruction requires is either a signer among `account_infos` or a program
+/// address derived from `program_id` and one of `signers_seeds`
+///
+/// Returns `ProgramError::MissingRequiredSignature`, and logs the offending pubkey, instead of
+/// leaving the runtime to reject the invocation. `program_id` is the id of the calling program.
+pub fn invoke_signed_checked(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    program_id: &Pubkey,
+) -> ProgramResult {
+    check_required_signers(instruction, account_infos, signers_seeds, program_id)?;
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+fn check_required_signers(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    program_id: &Pubkey,
+) -> ProgramResult {
+    let mut signer_addresses = None;
+    for account_meta in instruction.accounts.iter().filter(|meta| meta.is_signer) {
+        if account_infos
+            .iter()
+            .any(|account_info| account_info.is_signer && account_meta.pubkey == *account_info.key)
+        {
+            continue;
+        }
+        let signer_addresses = signer_addresses.get_or_insert_with(|| {
+            signers_seeds
+                .iter()
+                .filter_map(|seeds| Pubkey::create_program_address(seeds, program_id).ok())
+                .collect::<Vec<_>>()
+        });
+        if !signer_addresses.contains(&account_meta.pubkey) {
+            msg!(
+                "Missing required signature for {} in invoke of program {}",
+                account_meta.pubkey,
+                instruction.program_id
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+    }
+    Ok(())
+}
+
+fn invoke_signed_syscall(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
     #[cfg(target_arch = "bpf")]
     {
         let result = unsafe {
@@ -54,6 +113,157 @@ pub fn invoke_signed(
     crate::program_stubs::sol_invoke_signed(instruction, account_infos, signers_seeds)
 }
 
+/// A cross-program instruction laid out together with the account infos it is invoked with,
+/// built once and then issued any number of times by `invoke_signed_unchecked_slice()`
+///
+/// `new()` resolves the account infos the instruction references, each once and in the order
+/// the instruction first references them, so every invocation hands the syscall the instruction
+/// and that slice as they are. Only the instruction data can change between invocations.
+pub struct PreparedInvoke<'a> {
+    instruction: Instruction,
+    account_infos: Vec<AccountInfo<'a>>,
+}
+
+impl<'a> PreparedInvoke<'a> {
+    /// Returns `ProgramError::NotEnoughAccountKeys` if an account of `instruction` is missing
+    /// from `account_infos`
+    pub fn new(
+        instruction: Instruction,
+        account_infos: &[AccountInfo<'a>],
+    ) -> Result<Self, ProgramError> {
+        let mut prepared_infos: Vec<AccountInfo<'a>> =
+            Vec::with_capacity(instruction.accounts.len() + 1);
+        for account_meta in instruction.accounts.iter() {
+            if prepared_infos
+                .iter()
+                .any(|account_info| *account_info.key == account_meta.pubkey)
+            {
+                continue;
+            }
+            let account_info = account_infos
+                .iter()
+                .find(|account_info| *account_info.key == account_meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            prepared_infos.push(account_info.clone());
+        }
+        if !prepared_infos
+            .iter()
+            .any(|account_info| *account_info.key == instruction.program_id)
+        {
+            if let Some(program_info) = account_infos
+                .iter()
+                .find(|account_info| *account_info.key == instruction.program_id)
+            {
+                prepared_infos.push(program_info.clone());
+            }
+        }
+        Ok(Self {
+            instruction,
+            account_infos: prepared_infos,
+        })
+    }
+
+    pub fn instruction(&self) -> &Instruction {
+        &self.instruction
+    }
+
+    /// The account infos passed to every invocation
+    pub fn account_infos(&self) -> &[AccountInfo<'a>] {
+        &self.account_infos
+    }
+
+    /// The instruction data, to update in place between invocations
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.instruction.data
+    }
+}
+
+/// Invoke a prepared cross-program instruction with program signatures, without checking the
+/// account `RefCell`s first
+///
+/// For programs issuing many identically shaped cross-program instructions in a loop, where the
+/// per-call checks of `invoke_signed()` add up. Use `invoke_signed()` everywhere else.
+///
+/// # Safety
+///
+/// The caller must ensure that none of the prepared account infos has an outstanding borrow of
+/// its lamports or data when this is called: the runtime writes the invoked program's changes
+/// to those accounts, which would otherwise alias a live reference.
+pub unsafe fn invoke_signed_unchecked_slice(
+    prepared: &PreparedInvoke,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    invoke_signed_syscall(
+        &prepared.instruction,
+        &prepared.account_infos,
+        signers_seeds,
+    )
+}
+
+/// Invoke a cross-program instruction with program signatures, passing the subset of
+/// `all_account_infos` selected by `indices`
+///
+/// The selected account infos are cloned into a new `Vec` on every call, so this saves
+/// assembling the slice by hand rather than work; for invoking the same accounts repeatedly
+/// see `PreparedInvoke`. Returns `ProgramError::NotEnoughAccountKeys` if any index is out of
+/// bounds.
+pub fn invoke_signed_with_indices(
+    instruction: &Instruction,
+    all_account_infos: &[AccountInfo],
+    indices: &[usize],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let account_infos = indices
+        .iter()
+        .map(|index| {
+            all_account_infos
+                .get(*index)
+                .cloned()
+                .ok_or(ProgramError::NotEnoughAccountKeys)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    invoke_signed(instruction, &account_infos, signers_seeds)
+}
+
+/// Start or stop recording the messages logged by programs invoked off-chain through the
+/// syscall stubs, see `take_captured_logs()`
+///
+/// Does nothing on-chain.
+pub fn capture_invoke_logs(enable: bool) {
+    #[cfg(not(target_arch = "bpf"))]
+    crate::program_stubs::capture_invoke_logs(enable);
+    #[cfg(target_arch = "bpf")]
+    let _ = enable;
+}
+
+/// Limit the compute units available to cross-program invocations made off-chain through the
+/// syscall stubs on the current thread; once exhausted, `invoke()` returns
+/// `ProgramError::Custom(program_stubs::SIMULATED_COMPUTE_BUDGET_EXCEEDED)`. `None` removes the
+/// limit.
+///
+/// Does nothing on-chain, where the runtime meters compute units.
+pub fn set_simulated_compute_budget(units: Option<u64>) {
+    #[cfg(not(target_arch = "bpf"))]
+    crate::program_stubs::set_simulated_compute_budget(units);
+    #[cfg(target_arch = "bpf")]
+    let _ = units;
+}
+
+/// Take the messages logged by programs invoked off-chain on the current thread since
+/// `capture_invoke_logs(true)`
+///
+/// Always empty on-chain.
+pub fn take_captured_logs() -> Vec<String> {
+    #[cfg(not(target_arch = "bpf"))]
+    {
+        crate::program_stubs::take_captured_logs()
+    }
+    #[cfg(target_arch = "bpf")]
+    {
+        Vec::new()
+    }
+}
+
 #[cfg(target_arch = "bpf")]
 extern "C" {
     fn sol_invoke_signed_rust(
@@ -64,3 +274,126 @@ extern "C" {
         signers_seeds_len: u64,
     ) -> u64;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::AccountMeta;
+
+    #[test]
+    fn test_check_required_signers() {
+        let program_id = Pubkey::new_unique();
+        let invoked_program_id = Pubkey::new_unique();
+        let signer = Pubkey::new_unique();
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"vault"], &program_id);
+        let owner = Pubkey::default();
+        let (mut signer_lamports, mut pda_lamports) = (0, 0);
+        let (mut signer_data, mut pda_data) = (Vec::<u8>::new(), Vec::<u8>::new());
+        let signer_info = AccountInfo::new(
+            &signer,
+            true,
+            false,
+            &mut signer_lamports,
+            &mut signer_data,
+            &owner,
+            false,
+            0,
+        );
+        let pda_info = AccountInfo::new(
+            &pda,
+            false,
+            true,
+            &mut pda_lamports,
+            &mut pda_data,
+            &owner,
+            false,
+            0,
+        );
+        let account_infos = [signer_info.clone(), pda_info];
+        let instruction = Instruction::new_with_bytes(
+            invoked_program_id,
+            &[],
+            vec![AccountMeta::new(signer, true), AccountMeta::new(pda, true)],
+        );
+        let signer_seeds: &[&[u8]] = &[b"vault", &[bump_seed]];
+
+        assert_eq!(
+            check_required_signers(&instruction, &account_infos, &[signer_seeds], &program_id),
+            Ok(())
+        );
+        // Without the seeds nothing signs for the program address
+        assert_eq!(
+            check_required_signers(&instruction, &account_infos, &[], &program_id),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        // Seeds only sign for addresses of the calling program
+        assert_eq!(
+            check_required_signers(
+                &instruction,
+                &account_infos,
+                &[signer_seeds],
+                &invoked_program_id
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        // A required signer passed without its signature
+        let mut unsigned_info = signer_info;
+        unsigned_info.is_signer = false;
+        assert_eq!(
+            check_required_signers(
+                &instruction,
+                &[unsigned_info, account_infos[1].clone()],
+                &[signer_seeds],
+                &program_id
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn test_prepared_invoke_account_infos() {
+        let program_id = Pubkey::new_unique();
+        let keys: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let owner = Pubkey::default();
+        let mut lamports = [0u64; 4];
+        let mut data = vec![Vec::<u8>::new(); 4];
+        let all_keys = [keys[0], keys[1], keys[2], program_id];
+        let account_infos: Vec<_> = all_keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        let instruction = Instruction::new_with_bytes(
+            program_id,
+            &[1],
+            vec![
+                AccountMeta::new(keys[2], false),
+                AccountMeta::new_readonly(keys[0], false),
+                AccountMeta::new(keys[2], false),
+            ],
+        );
+
+        // Only the referenced accounts are kept, once each, followed by the program
+        let mut prepared = PreparedInvoke::new(instruction.clone(), &account_infos).unwrap();
+        assert_eq!(
+            prepared
+                .account_infos()
+                .iter()
+                .map(|account_info| *account_info.key)
+                .collect::<Vec<_>>(),
+            vec![keys[2], keys[0], program_id]
+        );
+        prepared.data_mut()[0] = 2;
+        assert_eq!(prepared.instruction().data, vec![2]);
+
+        assert_eq!(
+            PreparedInvoke::new(instruction, &account_infos[1..])
+                .err()
+                .unwrap(),
+            ProgramError::NotEnoughAccountKeys
+        );
+    }
+}
@@ -3,15 +3,37 @@
 #![cfg(not(target_arch = "bpf"))]
 
 use crate::{
-    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
-    program_error::UNSUPPORTED_SYSVAR,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program_error::{ProgramError, UNSUPPORTED_SYSVAR},
+};
+use std::{
+    cell::{Cell, RefCell},
+    sync::{Arc, RwLock},
 };
-use std::sync::{Arc, RwLock};
 
 lazy_static::lazy_static! {
     static ref SYSCALL_STUBS: Arc<RwLock<Box<dyn SyscallStubs>>> = Arc::new(RwLock::new(Box::new(DefaultSyscallStubs {})));
 }
 
+thread_local! {
+    // Messages logged by invoked programs while capture is enabled on this thread
+    static CAPTURED_LOGS: RefCell<Option<Vec<String>>> = RefCell::new(None);
+    // Depth of the `sol_invoke_signed()` calls in progress on this thread
+    static INVOKE_DEPTH: Cell<usize> = Cell::new(0);
+    // Compute units left for `sol_invoke_signed()` calls on this thread, unlimited if `None`
+    static SIMULATED_COMPUTE_BUDGET: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Compute units charged per simulated `sol_invoke_signed()`, the runtime's cost of a
+/// cross-program invocation
+pub const SIMULATED_INVOKE_UNITS: u64 = 1_000;
+
+/// `ProgramError::Custom` code returned by `sol_invoke_signed()` once the simulated compute
+/// budget is exhausted
+pub const SIMULATED_COMPUTE_BUDGET_EXCEEDED: u32 = u32::MAX;
+
 // The default syscall stubs may not do much, but `set_syscalls()` can be used
 // to swap in alternatives
 pub fn set_syscall_stubs(syscall_stubs: Box<dyn SyscallStubs>) -> Box<dyn SyscallStubs> {
@@ -85,7 +107,59 @@ pub trait SyscallStubs: Sync + Send {
 struct DefaultSyscallStubs {}
 impl SyscallStubs for DefaultSyscallStubs {}
 
+/// Start or stop recording the messages logged by programs invoked through
+/// `sol_invoke_signed()` on the current thread
+///
+/// Stopping discards any messages not yet taken with `take_captured_logs()`.
+pub fn capture_invoke_logs(enable: bool) {
+    CAPTURED_LOGS.with(|logs| {
+        *logs.borrow_mut() = if enable { Some(vec![]) } else { None };
+    });
+}
+
+/// Take the messages recorded on the current thread since capture was enabled or last taken
+pub fn take_captured_logs() -> Vec<String> {
+    CAPTURED_LOGS.with(|logs| {
+        logs.borrow_mut()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    })
+}
+
+/// Limit the `sol_invoke_signed()` calls on the current thread to `units` compute units, each
+/// call consuming `SIMULATED_INVOKE_UNITS`. `None` removes the limit.
+pub fn set_simulated_compute_budget(units: Option<u64>) {
+    SIMULATED_COMPUTE_BUDGET.with(|budget| budget.set(units));
+}
+
+/// Compute units left on the current thread, `None` if unlimited
+pub fn simulated_compute_budget() -> Option<u64> {
+    SIMULATED_COMPUTE_BUDGET.with(|budget| budget.get())
+}
+
+fn consume_simulated_compute_units(units: u64) -> ProgramResult {
+    SIMULATED_COMPUTE_BUDGET.with(|budget| match budget.get() {
+        Some(remaining) if remaining < units => {
+            budget.set(Some(0));
+            Err(ProgramError::Custom(SIMULATED_COMPUTE_BUDGET_EXCEEDED))
+        }
+        Some(remaining) => {
+            budget.set(Some(remaining - units));
+            Ok(())
+        }
+        None => Ok(()),
+    })
+}
+
 pub(crate) fn sol_log(message: &str) {
+    if INVOKE_DEPTH.with(|depth| depth.get()) > 0 {
+        CAPTURED_LOGS.with(|logs| {
+            if let Some(logs) = logs.borrow_mut().as_mut() {
+                logs.push(message.to_string());
+            }
+        });
+    }
     SYSCALL_STUBS.read().unwrap().sol_log(message);
 }
 
@@ -105,10 +179,15 @@ pub(crate) fn sol_invoke_signed(
     account_infos: &[AccountInfo],
     signers_seeds: &[&[&[u8]]],
 ) -> ProgramResult {
-    SYSCALL_STUBS
-        .read()
-        .unwrap()
-        .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    consume_simulated_compute_units(SIMULATED_INVOKE_UNITS)?;
+    INVOKE_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let result =
+        SYSCALL_STUBS
+            .read()
+            .unwrap()
+            .sol_invoke_signed(instruction, account_infos, signers_seeds);
+    INVOKE_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    result
 }
 
 pub(crate) fn sol_get_clock_sysvar(var_addr: *mut u8) -> u64 {
@@ -153,3 +232,51 @@ pub(crate) fn sol_memset(s: *mut u8, c: u8, n: usize) {
         SYSCALL_STUBS.read().unwrap().sol_memset(s, c, n);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubkey::Pubkey;
+
+    #[test]
+    fn test_capture_invoke_logs() {
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+
+        // Not capturing
+        crate::program::invoke(&instruction, &[]).unwrap();
+        assert!(take_captured_logs().is_empty());
+
+        capture_invoke_logs(true);
+        // Only logs from within an invoke are captured
+        sol_log("outside invoke");
+        crate::program::invoke(&instruction, &[]).unwrap();
+        assert_eq!(
+            take_captured_logs(),
+            vec!["SyscallStubs: sol_invoke_signed() not available".to_string()]
+        );
+        assert!(take_captured_logs().is_empty());
+
+        crate::program::invoke(&instruction, &[]).unwrap();
+        capture_invoke_logs(false);
+        assert!(take_captured_logs().is_empty());
+    }
+
+    #[test]
+    fn test_simulated_compute_budget() {
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+        assert_eq!(simulated_compute_budget(), None);
+
+        set_simulated_compute_budget(Some(SIMULATED_INVOKE_UNITS * 2 + 1));
+        crate::program::invoke(&instruction, &[]).unwrap();
+        crate::program::invoke(&instruction, &[]).unwrap();
+        assert_eq!(simulated_compute_budget(), Some(1));
+        assert_eq!(
+            crate::program::invoke(&instruction, &[]),
+            Err(ProgramError::Custom(SIMULATED_COMPUTE_BUDGET_EXCEEDED))
+        );
+        assert_eq!(simulated_compute_budget(), Some(0));
+
+        set_simulated_compute_budget(None);
+        crate::program::invoke(&instruction, &[]).unwrap();
+    }
+}
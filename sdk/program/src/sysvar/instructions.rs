@@ -1,12 +1,36 @@
 //! This account contains the serialized transaction instructions
 
-use crate::{instruction::Instruction, sanitize::SanitizeError};
+use crate::{
+    instruction::Instruction,
+    message::{Message, SerializeInstructionsOptions},
+    sanitize::SanitizeError,
+    serialize_utils::{read_u16, read_u8},
+};
 
 // Instructions Sysvar, dummy type, use the associated helpers instead of the Sysvar trait
 pub struct Instructions();
 
 crate::declare_sysvar_id!("Sysvar1nstructions1111111111111111111111111", Instructions);
 
+/// Version of the layout `Message::serialize_instructions_with_options` produces, stored in the byte between
+/// the instruction offset table and the first instruction
+///
+/// Data written before the marker was introduced has no such byte and reads as version 0, so
+/// versions start at 1.
+pub const LAYOUT_VERSION: u8 = 1;
+
+/// Serialize `instructions` in the same layout `Message::serialize_instructions_with_options`
+/// produces for a message compiled from them
+///
+/// The runtime appends two more bytes for the current instruction index, see
+/// `store_current_index`.
+pub fn serialize_instructions_subset(
+    instructions: &[Instruction],
+    options: SerializeInstructionsOptions,
+) -> Vec<u8> {
+    Message::new(instructions, None).serialize_instructions_with_options(options)
+}
+
 /// Load the current instruction's index from the Instructions Sysvar data
 pub fn load_current_index(data: &[u8]) -> u16 {
     let mut instr_fixed_data = [0u8; 2];
@@ -21,14 +45,53 @@ pub fn store_current_index(data: &mut [u8], instruction_index: u16) {
     data[last_index..last_index + 2].copy_from_slice(&instruction_index.to_le_bytes());
 }
 
+/// Read the layout version of the Instructions Sysvar data, see `LAYOUT_VERSION`
+///
+/// Data without instructions, or serialized without the version marker, reads as version 0.
+pub fn layout_version(data: &[u8]) -> Result<u8, SanitizeError> {
+    let mut current = 0;
+    let num_instructions = read_u16(&mut current, data)? as usize;
+    if num_instructions == 0 {
+        return Ok(0);
+    }
+    let offset_table_end = 2 + 2 * num_instructions;
+    let first_instruction_offset = read_u16(&mut current, data)? as usize;
+    if first_instruction_offset <= offset_table_end {
+        return Ok(0);
+    }
+    current = offset_table_end;
+    read_u8(&mut current, data)
+}
+
 /// Load an instruction at the specified index
+///
+/// Fails with `SanitizeError::InvalidValue` if the data is in a layout version this crate
+/// does not know
 pub fn load_instruction_at(index: usize, data: &[u8]) -> Result<Instruction, SanitizeError> {
-    crate::message::Message::deserialize_instruction(index, data)
+    match layout_version(data)? {
+        0 | LAYOUT_VERSION => Message::deserialize_instruction(index, data),
+        _ => Err(SanitizeError::InvalidValue),
+    }
+}
+
+/// Load every instruction from the Instructions Sysvar data
+pub fn load_instructions(data: &[u8]) -> Result<Vec<Instruction>, SanitizeError> {
+    let num_instructions = read_u16(&mut 0, data)?;
+    (0..num_instructions as usize)
+        .map(|index| load_instruction_at(index, data))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{instruction::AccountMeta, pubkey::Pubkey};
+
+    const OPTIONS: SerializeInstructionsOptions = SerializeInstructionsOptions {
+        demote_sysvar_write_locks: true,
+        demote_program_write_locks: true,
+        mark_layout_version: true,
+    };
 
     #[test]
     fn test_load_store_instruction() {
@@ -37,4 +100,103 @@ mod tests {
         assert_eq!(load_current_index(&data), 3);
         assert_eq!([4u8; 8], data[0..8]);
     }
+
+    #[test]
+    fn test_serialize_instructions_subset() {
+        let program_id0 = Pubkey::new_unique();
+        let program_id1 = Pubkey::new_unique();
+        let id0 = Pubkey::new_unique();
+        let id1 = Pubkey::new_unique();
+        let instructions = vec![
+            Instruction::new_with_bincode(program_id0, &0, vec![AccountMeta::new(id0, true)]),
+            Instruction::new_with_bincode(
+                program_id1,
+                &1,
+                vec![AccountMeta::new_readonly(id1, false)],
+            ),
+        ];
+
+        let message = Message::new(&instructions, None);
+        let serialized = serialize_instructions_subset(&instructions, OPTIONS);
+        assert_eq!(
+            serialized,
+            message.serialize_instructions_with_options(OPTIONS)
+        );
+        for (i, instruction) in instructions.iter().enumerate() {
+            assert_eq!(load_instruction_at(i, &serialized).unwrap(), *instruction);
+        }
+    }
+
+    #[test]
+    fn test_layout_version() {
+        let program_id = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(program_id, &[7], vec![]);
+        let mut data = serialize_instructions_subset(&[instruction.clone()], OPTIONS);
+        assert_eq!(layout_version(&data), Ok(LAYOUT_VERSION));
+        assert_eq!(load_instruction_at(0, &data), Ok(instruction.clone()));
+        assert_eq!(
+            layout_version(&serialize_instructions_subset(&[], OPTIONS)),
+            Ok(0)
+        );
+        assert_eq!(layout_version(&[1]), Err(SanitizeError::IndexOutOfBounds));
+
+        // Without a marker the first instruction starts right after the offset table
+        let mut legacy_data = vec![1, 0, 4, 0, 0, 0];
+        legacy_data.extend_from_slice(program_id.as_ref());
+        legacy_data.extend_from_slice(&[1, 0, 7]);
+        assert_eq!(
+            serialize_instructions_subset(
+                &[instruction.clone()],
+                SerializeInstructionsOptions {
+                    mark_layout_version: false,
+                    ..OPTIONS
+                }
+            ),
+            legacy_data
+        );
+        assert_eq!(layout_version(&legacy_data), Ok(0));
+        assert_eq!(load_instruction_at(0, &legacy_data), Ok(instruction));
+
+        // Unknown versions are rejected rather than misread
+        data[4] = LAYOUT_VERSION + 1;
+        assert_eq!(layout_version(&data), Ok(LAYOUT_VERSION + 1));
+        assert_eq!(
+            load_instruction_at(0, &data),
+            Err(SanitizeError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn test_load_instructions() {
+        let program_id = Pubkey::new_unique();
+        let id0 = Pubkey::new_unique();
+        let id1 = Pubkey::new_unique();
+        let instructions = vec![
+            Instruction::new_with_bincode(program_id, &0, vec![AccountMeta::new(id0, true)]),
+            Instruction::new_with_bincode(
+                program_id,
+                &1,
+                vec![
+                    AccountMeta::new_readonly(id0, true),
+                    AccountMeta::new(id1, false),
+                ],
+            ),
+        ];
+
+        let mut data = serialize_instructions_subset(&instructions, OPTIONS);
+        data.resize(data.len() + 2, 0);
+        let loaded = load_instructions(&data).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0], instructions[0]);
+        assert_eq!(loaded[1].program_id, program_id);
+        // id0 is writable in the compiled message, so it is reported writable in both
+        assert_eq!(loaded[1].accounts[0], AccountMeta::new(id0, true));
+        assert_eq!(loaded[1].accounts[1], AccountMeta::new(id1, false));
+
+        assert_eq!(load_instructions(&[]), Err(SanitizeError::IndexOutOfBounds));
+        assert_eq!(
+            load_instructions(&data[..4]),
+            Err(SanitizeError::IndexOutOfBounds)
+        );
+    }
 }
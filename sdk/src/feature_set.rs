@@ -166,6 +166,14 @@ pub mod merge_nonce_error_into_system_error {
     solana_sdk::declare_id!("4n5Ko6ax8yLi21CXoBMFbCy52QydH7jpy42W5df7GZqT");
 }
 
+pub mod demote_program_write_locks {
+    solana_sdk::declare_id!("2fHkmYXa2pQ9ecAUo3kVGTpckRA4Ve2WFsgQcnsbWx9B");
+}
+
+pub mod instructions_sysvar_layout_version {
+    solana_sdk::declare_id!("5CCmU8zrrhzTGcBYX9FmEgyyph1cdhGMd6eRP24gDkk1");
+}
+
 lazy_static! {
     /// Map of feature identifiers to user-visible description
     pub static ref FEATURE_NAMES: HashMap<Pubkey, &'static str> = [
@@ -206,6 +214,8 @@ lazy_static! {
         (updated_verify_policy::id(), "Update verify policy"),
         (libsecp256k1_0_5_upgrade_enabled::id(), "upgrade libsecp256k1 to v0.5.0"),
         (merge_nonce_error_into_system_error::id(), "merge NonceError into SystemError"),
+        (demote_program_write_locks::id(), "report invoked program accounts as readonly in the instructions sysvar"),
+        (instructions_sysvar_layout_version::id(), "mark the instructions sysvar layout version"),
         /*************** ADD NEW FEATURES HERE ***************/
     ]
     .iter()
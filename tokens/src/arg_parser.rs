@@ -1,19 +1,47 @@
-use crate::args::{
-    Args, BalancesArgs, Command, DistributeTokensArgs, SafeTokenArgs, StakeArgs, TransactionLogArgs,
+use crate::{
+    args::{
+        Args, BalancesArgs, Command, DiffAllocationsArgs, DisplayOptions, DistributeTokensArgs,
+        InputFormat, ReconcileArgs, SafeTokenArgs, StakeArgs, TransactionLogArgs,
+    },
+    spl_token::{wrapped_sol_address, TokenProgram},
 };
 use clap::{
     crate_description, crate_name, value_t, value_t_or_exit, App, Arg, ArgMatches, SubCommand,
 };
 use safecoin_clap_utils::{
     input_parsers::{pubkey_of_signer, value_of},
-    input_validators::{is_amount, is_valid_pubkey, is_valid_signer},
+    input_validators::{is_amount, is_parsable, is_valid_pubkey, is_valid_signer},
     keypair::{pubkey_from_path, signer_from_path},
 };
 use safecoin_cli_config::CONFIG_FILE;
 use safecoin_remote_wallet::remote_wallet::maybe_wallet_manager;
-use solana_sdk::native_token::sol_to_lamports;
+use solana_sdk::{commitment_config::CommitmentConfig, native_token::sol_to_lamports};
 use std::{error::Error, ffi::OsString, process::exit};
 
+fn input_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("input_format")
+        .long("input-format")
+        .takes_value(true)
+        .value_name("FORMAT")
+        .possible_values(&["csv", "ndjson"])
+        .help(
+            "Format of the allocations file. \
+            Detected from the file extension if not specified",
+        )
+}
+
+fn token_program_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("token_program")
+        .long("token-program")
+        .takes_value(true)
+        .value_name("PROGRAM")
+        .possible_values(&["legacy", "new"])
+        .help(
+            "Token program that owns the mint, under which the recipients' associated token \
+            accounts are derived [default: legacy]",
+        )
+}
+
 fn get_matches<'a, I, T>(args: I) -> ArgMatches<'a>
 where
     I: IntoIterator<Item = T>,
@@ -62,6 +90,7 @@ where
                         .value_name("FILE")
                         .help("Input CSV file"),
                 )
+                .arg(input_format_arg())
                 .arg(
                     Arg::with_name("transfer_amount")
                         .long("transfer-amount")
@@ -75,6 +104,33 @@ where
                         .long("dry-run")
                         .help("Do not execute any transfers"),
                 )
+                .arg(
+                    Arg::with_name("confirm_commitment")
+                        .long("confirm-commitment")
+                        .takes_value(true)
+                        .value_name("COMMITMENT_LEVEL")
+                        .possible_values(&["confirmed", "finalized"])
+                        .help(
+                            "Confirm the sent transactions in bulk at this commitment level, \
+                            reporting any that fail [default: wait for each to be finalized]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("isolate_failures")
+                        .long("isolate-failures")
+                        .help(
+                            "Keep going when an allocation fails to send, and write the failed \
+                            allocations next to the output file, with a .failed.csv extension",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("merge_duplicates")
+                        .long("merge-duplicates")
+                        .help(
+                            "Sum the amounts of allocations to the same recipient (and lockup \
+                            date) into one allocation [default: reject duplicate recipients]",
+                        ),
+                )
                 .arg(
                     Arg::with_name("output_path")
                         .long("output-path")
@@ -83,6 +139,35 @@ where
                         .takes_value(true)
                         .help("Write the transaction log to this file"),
                 )
+                .arg(
+                    Arg::with_name("summary_path")
+                        .long("summary-path")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Write a JSON summary of the distribution to this file"),
+                )
+                .arg(
+                    Arg::with_name("max_in_flight")
+                        .long("max-in-flight")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "Wait for sent transactions to be confirmed whenever this many are \
+                            outstanding [default: no limit]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("max_resubmits")
+                        .long("max-resubmits")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "Re-sign with a fresh blockhash and resend a transaction whose \
+                            blockhash expires before it lands, up to this many times [default: 0]",
+                        ),
+                )
                 .arg(
                     Arg::with_name("sender_keypair")
                         .long("from")
@@ -125,11 +210,39 @@ where
                         .value_name("FILE")
                         .help("Allocations CSV file"),
                 )
+                .arg(input_format_arg())
                 .arg(
                     Arg::with_name("dry_run")
                         .long("dry-run")
                         .help("Do not execute any transfers"),
                 )
+                .arg(
+                    Arg::with_name("confirm_commitment")
+                        .long("confirm-commitment")
+                        .takes_value(true)
+                        .value_name("COMMITMENT_LEVEL")
+                        .possible_values(&["confirmed", "finalized"])
+                        .help(
+                            "Confirm the sent transactions in bulk at this commitment level, \
+                            reporting any that fail [default: wait for each to be finalized]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("isolate_failures")
+                        .long("isolate-failures")
+                        .help(
+                            "Keep going when an allocation fails to send, and write the failed \
+                            allocations next to the output file, with a .failed.csv extension",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("merge_duplicates")
+                        .long("merge-duplicates")
+                        .help(
+                            "Sum the amounts of allocations to the same recipient (and lockup \
+                            date) into one allocation [default: reject duplicate recipients]",
+                        ),
+                )
                 .arg(
                     Arg::with_name("output_path")
                         .long("output-path")
@@ -138,6 +251,24 @@ where
                         .takes_value(true)
                         .help("Write the transaction log to this file"),
                 )
+                .arg(
+                    Arg::with_name("summary_path")
+                        .long("summary-path")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Write a JSON summary of the distribution to this file"),
+                )
+                .arg(
+                    Arg::with_name("max_in_flight")
+                        .long("max-in-flight")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "Wait for sent transactions to be confirmed whenever this many are \
+                            outstanding [default: no limit]",
+                        ),
+                )
                 .arg(
                     Arg::with_name("sender_keypair")
                         .long("from")
@@ -223,11 +354,39 @@ where
                         .value_name("FILE")
                         .help("Allocations CSV file"),
                 )
+                .arg(input_format_arg())
                 .arg(
                     Arg::with_name("dry_run")
                         .long("dry-run")
                         .help("Do not execute any transfers"),
                 )
+                .arg(
+                    Arg::with_name("confirm_commitment")
+                        .long("confirm-commitment")
+                        .takes_value(true)
+                        .value_name("COMMITMENT_LEVEL")
+                        .possible_values(&["confirmed", "finalized"])
+                        .help(
+                            "Confirm the sent transactions in bulk at this commitment level, \
+                            reporting any that fail [default: wait for each to be finalized]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("isolate_failures")
+                        .long("isolate-failures")
+                        .help(
+                            "Keep going when an allocation fails to send, and write the failed \
+                            allocations next to the output file, with a .failed.csv extension",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("merge_duplicates")
+                        .long("merge-duplicates")
+                        .help(
+                            "Sum the amounts of allocations to the same recipient (and lockup \
+                            date) into one allocation [default: reject duplicate recipients]",
+                        ),
+                )
                 .arg(
                     Arg::with_name("transfer_amount")
                         .long("transfer-amount")
@@ -244,15 +403,56 @@ where
                         .takes_value(true)
                         .help("Write the transaction log to this file"),
                 )
+                .arg(
+                    Arg::with_name("summary_path")
+                        .long("summary-path")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Write a JSON summary of the distribution to this file"),
+                )
+                .arg(
+                    Arg::with_name("max_in_flight")
+                        .long("max-in-flight")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "Wait for sent transactions to be confirmed whenever this many are \
+                            outstanding [default: no limit]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("max_resubmits")
+                        .long("max-resubmits")
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .validator(is_parsable::<usize>)
+                        .help(
+                            "Re-sign with a fresh blockhash and resend a transaction whose \
+                            blockhash expires before it lands, up to this many times [default: 0]",
+                        ),
+                )
                 .arg(
                     Arg::with_name("token_account_address")
                         .long("from")
-                        .required(true)
+                        .required_unless("wrap_sol")
                         .takes_value(true)
                         .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .validator(is_valid_pubkey)
                         .help("SPL token account to send from"),
                 )
+                .arg(
+                    Arg::with_name("wrap_sol")
+                        .long("wrap-sol")
+                        .takes_value(false)
+                        .conflicts_with("token_account_address")
+                        .help(
+                            "Distribute native SAFE: wrap the owner's lamports into a temporary \
+                            wrapped SAFE account, and close it when the distribution ends. \
+                            The account is derived from the owner, so an owner can only run \
+                            one such distribution at a time.",
+                        ),
+                )
                 .arg(
                     Arg::with_name("token_owner")
                         .long("owner")
@@ -270,7 +470,38 @@ where
                         .value_name("KEYPAIR")
                         .validator(is_valid_signer)
                         .help("Fee payer"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("rent_payer")
+                        .long("rent-payer")
+                        .takes_value(true)
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .help(
+                            "Funds the rent of new associated token accounts \
+                            [default: the fee payer]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("allow_unchecked_transfer")
+                        .long("allow-unchecked-transfer")
+                        .takes_value(false)
+                        .help(
+                            "If the mint account cannot be fetched, send unchecked transfers \
+                            instead of failing. The token program then does not verify the \
+                            mint or decimals, and amounts are read as base units.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("check_recipients")
+                        .long("check-recipients")
+                        .takes_value(false)
+                        .help(
+                            "Before distributing, fail if any recipient address is a token \
+                            account rather than a wallet",
+                        ),
+                )
+                .arg(token_program_arg()),
         )
         .subcommand(
             SubCommand::with_name("balances")
@@ -282,6 +513,30 @@ where
                         .takes_value(true)
                         .value_name("FILE")
                         .help("Allocations CSV file"),
+                )
+                .arg(input_format_arg())
+                .arg(
+                    Arg::with_name("group_digits")
+                        .long("group-digits")
+                        .takes_value(false)
+                        .help("Separate the integer digits of amounts in groups of three"),
+                )
+                .arg(
+                    Arg::with_name("max_fraction_digits")
+                        .long("max-fraction-digits")
+                        .takes_value(true)
+                        .value_name("DIGITS")
+                        .validator(is_parsable::<u8>)
+                        .help("Round amounts to at most this many fractional digits"),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .takes_value(false)
+                        .help(
+                            "Exit with an error if any recipient's account is missing \
+                            or holds less than its allocation",
+                        ),
                 ),
         )
         .subcommand(
@@ -295,6 +550,7 @@ where
                         .value_name("FILE")
                         .help("Allocations CSV file"),
                 )
+                .arg(input_format_arg())
                 .arg(
                     Arg::with_name("mint_address")
                         .long("mint")
@@ -303,6 +559,111 @@ where
                         .value_name("MINT_ADDRESS")
                         .validator(is_valid_pubkey)
                         .help("SPL token mint of distribution"),
+                )
+                .arg(token_program_arg())
+                .arg(
+                    Arg::with_name("group_digits")
+                        .long("group-digits")
+                        .takes_value(false)
+                        .help("Separate the integer digits of amounts in groups of three"),
+                )
+                .arg(
+                    Arg::with_name("max_fraction_digits")
+                        .long("max-fraction-digits")
+                        .takes_value(true)
+                        .value_name("DIGITS")
+                        .validator(is_parsable::<u8>)
+                        .help("Round amounts to at most this many fractional digits"),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .takes_value(false)
+                        .help(
+                            "Exit with an error if any recipient's account is missing \
+                            or holds less than its allocation",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reconcile")
+                .about("Classify each allocation by the recipient's current balance")
+                .arg(
+                    Arg::with_name("input_csv")
+                        .long("input-csv")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Allocations CSV file"),
+                )
+                .arg(input_format_arg())
+                .arg(
+                    Arg::with_name("mint_address")
+                        .long("mint")
+                        .takes_value(true)
+                        .value_name("MINT_ADDRESS")
+                        .validator(is_valid_pubkey)
+                        .help(
+                            "SPL token mint of distribution. \
+                            Compares SAFE balances if not specified",
+                        ),
+                )
+                .arg(token_program_arg().requires("mint_address"))
+                .arg(
+                    Arg::with_name("output_path")
+                        .long("output-path")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write the reconciliation to this CSV file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff-allocations")
+                .about(
+                    "List the recipients added, removed, or changed between two allocation files",
+                )
+                .arg(
+                    Arg::with_name("old_input_csv")
+                        .long("old-input-csv")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Allocations file already distributed"),
+                )
+                .arg(
+                    Arg::with_name("new_input_csv")
+                        .long("new-input-csv")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Updated allocations file"),
+                )
+                .arg(input_format_arg().help(
+                    "Format of the allocations files. \
+                    Detected from the file extensions if not specified",
+                ))
+                .arg(
+                    Arg::with_name("mint_address")
+                        .long("mint")
+                        .takes_value(true)
+                        .value_name("MINT_ADDRESS")
+                        .validator(is_valid_pubkey)
+                        .help(
+                            "SPL token mint of distribution, whose amounts are in base units. \
+                            Amounts are in SAFE if not specified",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("output_path")
+                        .long("output-path")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help(
+                            "Write the allocations that top up the old distribution to the new \
+                            one to this CSV file",
+                        ),
                 ),
         )
         .subcommand(
@@ -352,11 +713,22 @@ fn parse_distribute_tokens_args(
 
     Ok(DistributeTokensArgs {
         input_csv: value_t_or_exit!(matches, "input_csv", String),
+        input_format: value_t!(matches, "input_format", InputFormat).ok(),
         transaction_db: value_t_or_exit!(matches, "db_path", String),
         output_path: matches.value_of("output_path").map(|path| path.to_string()),
         dry_run: matches.is_present("dry_run"),
         sender_keypair,
         fee_payer,
+        rent_payer: None,
+        confirm_commitment: value_t!(matches, "confirm_commitment", CommitmentConfig).ok(),
+        isolate_failures: matches.is_present("isolate_failures"),
+        merge_duplicates: matches.is_present("merge_duplicates"),
+        summary_path: matches
+            .value_of("summary_path")
+            .map(|path| path.to_string()),
+        max_in_flight: value_t!(matches, "max_in_flight", usize).unwrap_or(0),
+        max_resubmits: value_t!(matches, "max_resubmits", usize).unwrap_or(0),
+        blockhash: None,
         stake_args: None,
         spl_token_args: None,
         transfer_amount: value_of(matches, "transfer_amount").map(sol_to_lamports),
@@ -430,11 +802,22 @@ fn parse_distribute_stake_args(
     };
     Ok(DistributeTokensArgs {
         input_csv: value_t_or_exit!(matches, "input_csv", String),
+        input_format: value_t!(matches, "input_format", InputFormat).ok(),
         transaction_db: value_t_or_exit!(matches, "db_path", String),
         output_path: matches.value_of("output_path").map(|path| path.to_string()),
         dry_run: matches.is_present("dry_run"),
         sender_keypair,
         fee_payer,
+        rent_payer: None,
+        confirm_commitment: value_t!(matches, "confirm_commitment", CommitmentConfig).ok(),
+        isolate_failures: matches.is_present("isolate_failures"),
+        merge_duplicates: matches.is_present("merge_duplicates"),
+        summary_path: matches
+            .value_of("summary_path")
+            .map(|path| path.to_string()),
+        max_in_flight: value_t!(matches, "max_in_flight", usize).unwrap_or(0),
+        max_resubmits: 0,
+        blockhash: None,
         stake_args: Some(stake_args),
         spl_token_args: None,
         transfer_amount: None,
@@ -463,24 +846,50 @@ fn parse_distribute_spl_tokens_args(
         &mut wallet_manager,
     )?;
 
-    let token_account_address_str = value_t_or_exit!(matches, "token_account_address", String);
-    let token_account_address = pubkey_from_path(
-        &signer_matches,
-        &token_account_address_str,
-        "token account address",
-        &mut wallet_manager,
-    )?;
+    let rent_payer_str = value_t!(matches, "rent_payer", String).ok();
+    let rent_payer = rent_payer_str
+        .map(|path| signer_from_path(&signer_matches, &path, "rent-payer", &mut wallet_manager))
+        .transpose()?;
+
+    let token_program = value_t!(matches, "token_program", TokenProgram).unwrap_or_default();
+    let wrap_sol = matches.is_present("wrap_sol");
+    let token_account_address = if wrap_sol {
+        wrapped_sol_address(&token_owner.pubkey(), token_program)
+    } else {
+        let token_account_address_str = value_t_or_exit!(matches, "token_account_address", String);
+        pubkey_from_path(
+            &signer_matches,
+            &token_account_address_str,
+            "token account address",
+            &mut wallet_manager,
+        )?
+    };
 
     Ok(DistributeTokensArgs {
         input_csv: value_t_or_exit!(matches, "input_csv", String),
+        input_format: value_t!(matches, "input_format", InputFormat).ok(),
         transaction_db: value_t_or_exit!(matches, "db_path", String),
         output_path: matches.value_of("output_path").map(|path| path.to_string()),
         dry_run: matches.is_present("dry_run"),
         sender_keypair: token_owner,
         fee_payer,
+        rent_payer,
+        confirm_commitment: value_t!(matches, "confirm_commitment", CommitmentConfig).ok(),
+        isolate_failures: matches.is_present("isolate_failures"),
+        merge_duplicates: matches.is_present("merge_duplicates"),
+        summary_path: matches
+            .value_of("summary_path")
+            .map(|path| path.to_string()),
+        max_in_flight: value_t!(matches, "max_in_flight", usize).unwrap_or(0),
+        max_resubmits: value_t!(matches, "max_resubmits", usize).unwrap_or(0),
+        blockhash: None,
         stake_args: None,
         spl_token_args: Some(SafeTokenArgs {
             token_account_address,
+            allow_unchecked_transfer: matches.is_present("allow_unchecked_transfer"),
+            check_recipients: matches.is_present("check_recipients"),
+            wrap_sol,
+            token_program,
             ..SafeTokenArgs::default()
         }),
         transfer_amount: value_of(matches, "transfer_amount"),
@@ -492,11 +901,52 @@ fn parse_balances_args(matches: &ArgMatches<'_>) -> Result<BalancesArgs, Box<dyn
     let spl_token_args =
         pubkey_of_signer(matches, "mint_address", &mut wallet_manager)?.map(|mint| SafeTokenArgs {
             mint,
+            token_program: value_t!(matches, "token_program", TokenProgram).unwrap_or_default(),
             ..SafeTokenArgs::default()
         });
     Ok(BalancesArgs {
         input_csv: value_t_or_exit!(matches, "input_csv", String),
+        input_format: value_t!(matches, "input_format", InputFormat).ok(),
+        spl_token_args,
+        display_options: DisplayOptions {
+            group_separators: matches.is_present("group_digits"),
+            max_fraction_digits: value_t!(matches, "max_fraction_digits", u8).ok(),
+        },
+        strict: matches.is_present("strict"),
+    })
+}
+
+fn parse_reconcile_args(matches: &ArgMatches<'_>) -> Result<ReconcileArgs, Box<dyn Error>> {
+    let mut wallet_manager = maybe_wallet_manager()?;
+    let spl_token_args =
+        pubkey_of_signer(matches, "mint_address", &mut wallet_manager)?.map(|mint| SafeTokenArgs {
+            mint,
+            token_program: value_t!(matches, "token_program", TokenProgram).unwrap_or_default(),
+            ..SafeTokenArgs::default()
+        });
+    Ok(ReconcileArgs {
+        input_csv: value_t_or_exit!(matches, "input_csv", String),
+        input_format: value_t!(matches, "input_format", InputFormat).ok(),
+        spl_token_args,
+        output_path: matches.value_of("output_path").map(|path| path.to_string()),
+    })
+}
+
+fn parse_diff_allocations_args(
+    matches: &ArgMatches<'_>,
+) -> Result<DiffAllocationsArgs, Box<dyn Error>> {
+    let mut wallet_manager = maybe_wallet_manager()?;
+    let spl_token_args =
+        pubkey_of_signer(matches, "mint_address", &mut wallet_manager)?.map(|mint| SafeTokenArgs {
+            mint,
+            ..SafeTokenArgs::default()
+        });
+    Ok(DiffAllocationsArgs {
+        old_input_csv: value_t_or_exit!(matches, "old_input_csv", String),
+        new_input_csv: value_t_or_exit!(matches, "new_input_csv", String),
+        input_format: value_t!(matches, "input_format", InputFormat).ok(),
         spl_token_args,
+        output_path: matches.value_of("output_path").map(|path| path.to_string()),
     })
 }
 
@@ -528,6 +978,10 @@ where
         }
         ("balances", Some(matches)) => Command::Balances(parse_balances_args(matches)?),
         ("safe-token-balances", Some(matches)) => Command::Balances(parse_balances_args(matches)?),
+        ("reconcile", Some(matches)) => Command::Reconcile(parse_reconcile_args(matches)?),
+        ("diff-allocations", Some(matches)) => {
+            Command::DiffAllocations(parse_diff_allocations_args(matches)?)
+        }
         ("transaction-log", Some(matches)) => {
             Command::TransactionLog(parse_transaction_log_args(matches))
         }
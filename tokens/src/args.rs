@@ -1,17 +1,99 @@
-use solana_sdk::{pubkey::Pubkey, signature::Signer};
+use crate::spl_token::TokenProgram;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, hash::Hash, pubkey::Pubkey, signature::Signer,
+};
+use std::{path::Path, str::FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    Ndjson,
+}
+
+impl InputFormat {
+    /// Guess the input format from the file extension, defaulting to CSV
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") | Some("jsonl") | Some("ndjson") => InputFormat::Ndjson,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            _ => Err(format!("unknown input format: {}", s)),
+        }
+    }
+}
 
 pub struct DistributeTokensArgs {
     pub input_csv: String,
+    pub input_format: Option<InputFormat>,
     pub transaction_db: String,
     pub output_path: Option<String>,
     pub dry_run: bool,
     pub sender_keypair: Box<dyn Signer>,
     pub fee_payer: Box<dyn Signer>,
+    /// Funds the rent of new associated token accounts, the fee payer if `None`
+    pub rent_payer: Option<Box<dyn Signer>>,
+    /// Confirm sent transactions in bulk at this commitment instead of waiting for each to be
+    /// finalized
+    pub confirm_commitment: Option<CommitmentConfig>,
+    /// Record allocations that fail to send and carry on with the rest, instead of stopping at
+    /// the first error
+    pub isolate_failures: bool,
+    /// Sum allocations to the same recipient and lockup date into one, instead of rejecting the
+    /// duplicates
+    pub merge_duplicates: bool,
+    /// Write a JSON summary of the distribution to this file once it completes
+    pub summary_path: Option<String>,
+    /// Most transactions sent but not yet confirmed at any time, 0 for no limit
+    pub max_in_flight: usize,
+    /// Times to re-sign and resend a transaction whose blockhash expires before it lands, 0 to
+    /// never resubmit. Ignored for stake distributions.
+    pub max_resubmits: usize,
+    /// Sign the distribution's transactions with this blockhash, and check fees against it,
+    /// instead of fetching a recent blockhash for each transaction
+    pub blockhash: Option<Hash>,
     pub stake_args: Option<StakeArgs>,
     pub spl_token_args: Option<SafeTokenArgs>,
     pub transfer_amount: Option<u64>,
 }
 
+#[cfg(test)]
+impl DistributeTokensArgs {
+    /// Arguments that distribute SAFE from `sender_keypair` with every option off, for tests to
+    /// override the fields they exercise
+    pub(crate) fn for_test(sender_keypair: Box<dyn Signer>, fee_payer: Box<dyn Signer>) -> Self {
+        Self {
+            input_csv: "".to_string(),
+            input_format: None,
+            transaction_db: "".to_string(),
+            output_path: None,
+            dry_run: false,
+            sender_keypair,
+            fee_payer,
+            rent_payer: None,
+            confirm_commitment: None,
+            isolate_failures: false,
+            merge_duplicates: false,
+            summary_path: None,
+            max_in_flight: 0,
+            max_resubmits: 0,
+            blockhash: None,
+            stake_args: None,
+            spl_token_args: None,
+            transfer_amount: None,
+        }
+    }
+}
+
 pub struct StakeArgs {
     pub unlocked_sol: u64,
     pub stake_account_address: Pubkey,
@@ -25,11 +107,58 @@ pub struct SafeTokenArgs {
     pub token_account_address: Pubkey,
     pub mint: Pubkey,
     pub decimals: u8,
+    /// Token program that owns the mint; the recipients' associated token accounts are derived
+    /// under it and every token instruction is sent to it
+    pub token_program: TokenProgram,
+    /// Fall back to an unchecked `transfer` if the mint account cannot be fetched
+    pub allow_unchecked_transfer: bool,
+    /// Set when falling back to `transfer`. The mint and decimals are then not verified by the
+    /// token program, so a wrong token account or amount scale goes undetected.
+    pub unchecked_transfer: bool,
+    /// Look up each recipient before distributing and reject those that are token accounts
+    /// rather than wallets
+    pub check_recipients: bool,
+    /// Distribute native SAFE through a temporary wrapped SAFE account at
+    /// `token_account_address`, see `wrapped_sol_address()`. The account is created and funded
+    /// from the sender's lamports once the funding checks pass, and closed back to the sender
+    /// when the distribution ends, whether or not it succeeds.
+    pub wrap_sol: bool,
+}
+
+/// How amounts are rendered in balance reports
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DisplayOptions {
+    /// Separate the integer digits in groups of three, e.g. `1,234.5`
+    pub group_separators: bool,
+    /// Round to at most this many fractional digits
+    pub max_fraction_digits: Option<u8>,
 }
 
 pub struct BalancesArgs {
     pub input_csv: String,
+    pub input_format: Option<InputFormat>,
+    pub spl_token_args: Option<SafeTokenArgs>,
+    pub display_options: DisplayOptions,
+    /// Fail if any recipient has no token account or holds less than its allocation, for
+    /// verifying that a distribution completed
+    pub strict: bool,
+}
+
+pub struct ReconcileArgs {
+    pub input_csv: String,
+    pub input_format: Option<InputFormat>,
     pub spl_token_args: Option<SafeTokenArgs>,
+    pub output_path: Option<String>,
+}
+
+pub struct DiffAllocationsArgs {
+    pub old_input_csv: String,
+    pub new_input_csv: String,
+    pub input_format: Option<InputFormat>,
+    /// Read amounts as token base units of this mint instead of SAFE
+    pub spl_token_args: Option<SafeTokenArgs>,
+    /// Write the allocations that top up the old distribution to the new one to this CSV file
+    pub output_path: Option<String>,
 }
 
 pub struct TransactionLogArgs {
@@ -40,6 +169,8 @@ pub struct TransactionLogArgs {
 pub enum Command {
     DistributeTokens(DistributeTokensArgs),
     Balances(BalancesArgs),
+    Reconcile(ReconcileArgs),
+    DiffAllocations(DiffAllocationsArgs),
     TransactionLog(TransactionLogArgs),
 }
 
@@ -1,6 +1,9 @@
 use crate::{
-    args::{BalancesArgs, DistributeTokensArgs, StakeArgs, TransactionLogArgs},
-    db::{self, TransactionInfo},
+    args::{
+        BalancesArgs, DiffAllocationsArgs, DistributeTokensArgs, InputFormat, ReconcileArgs,
+        StakeArgs, TransactionLogArgs,
+    },
+    db::{self, FailedAllocation, TransactionInfo},
     spl_token::*,
     token_display::Token,
 };
@@ -11,43 +14,47 @@ use indexmap::IndexMap;
 use indicatif::{ProgressBar, ProgressStyle};
 use pickledb::PickleDb;
 use serde::{Deserialize, Serialize};
-use safecoin_account_decoder::parse_token::{
-    pubkey_from_spl_token_v2_0, real_number_string, spl_token_v2_0_pubkey,
-};
 use safecoin_client::{
-    client_error::{ClientError, Result as ClientResult},
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
     rpc_client::RpcClient,
     rpc_config::RpcSendTransactionConfig,
-    rpc_request::MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS,
+    rpc_request::{MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS, MAX_MULTIPLE_ACCOUNTS},
 };
 use solana_sdk::{
-    clock::Slot,
+    clock::{Slot, MAX_RECENT_BLOCKHASHES},
     commitment_config::CommitmentConfig,
+    fee_calculator::FeeCalculator,
+    hash::Hash,
     instruction::Instruction,
     message::Message,
     native_token::{lamports_to_sol, sol_to_lamports},
+    packet::PACKET_DATA_SIZE,
     signature::{unique_signers, Signature, Signer},
     system_instruction,
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
 };
 use solana_stake_program::{
     stake_instruction::{self, LockupArgs},
     stake_state::{Authorized, Lockup, StakeAuthorize},
 };
 use safecoin_transaction_status::TransactionStatus;
-use safe_associated_token_account_v1_0::get_associated_token_address;
 use spl_token_v2_0::solana_program::program_error::ProgramError;
 use std::{
     cmp::{self},
-    io,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+/// How long `--confirm-commitment` waits for the distribution's transactions
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(300);
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Allocation {
     pub recipient: String,
@@ -55,9 +62,10 @@ pub struct Allocation {
     pub lockup_date: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum FundingSource {
     FeePayer,
+    RentPayer,
     SafeTokenAccount,
     StakeAccount,
     SystemAccount,
@@ -89,6 +97,72 @@ impl From<Vec<FundingSource>> for FundingSources {
     }
 }
 
+/// Amounts a distribution draws from each of its funding sources. Token amounts are in base
+/// units of the distributed token, everything else in lamports.
+#[derive(Debug, Default, PartialEq)]
+pub struct FundingRequirements {
+    pub total_tokens: u64,
+    pub total_lamports_fees: u64,
+    pub total_rent: u64,
+    pub by_source: HashMap<FundingSource, u64>,
+}
+
+impl FundingRequirements {
+    pub fn required(&self, source: &FundingSource) -> u64 {
+        self.by_source.get(source).copied().unwrap_or_default()
+    }
+}
+
+/// Compute what each funding source must cover for `allocations`, given the transaction `fees`
+/// and the `total_rent` of any token accounts the distribution creates. Fails rather than wrap
+/// if a requirement does not fit in a u64.
+pub fn compute_funding_requirements(
+    allocations: &[Allocation],
+    args: &DistributeTokensArgs,
+    fees: u64,
+    total_rent: u64,
+) -> Result<FundingRequirements, Error> {
+    let total_tokens = allocations
+        .iter()
+        .try_fold(0u64, |total, x| total.checked_add(x.amount))
+        .ok_or(Error::FundingOverflow("total allocation amount"))?;
+    let mut by_source = HashMap::new();
+    let separate_rent_payer = args
+        .rent_payer
+        .as_ref()
+        .map(|rent_payer| rent_payer.pubkey() != args.fee_payer.pubkey())
+        .unwrap_or(false);
+    if separate_rent_payer {
+        by_source.insert(FundingSource::FeePayer, fees);
+        by_source.insert(FundingSource::RentPayer, total_rent);
+    } else {
+        let fees_and_rent = fees
+            .checked_add(total_rent)
+            .ok_or(Error::FundingOverflow("fees plus rent"))?;
+        by_source.insert(FundingSource::FeePayer, fees_and_rent);
+    }
+    if args.spl_token_args.is_some() {
+        by_source.insert(FundingSource::SafeTokenAccount, total_tokens);
+    } else if let Some(stake_args) = &args.stake_args {
+        let total_unlocked_sol = (allocations.len() as u64)
+            .checked_mul(stake_args.unlocked_sol)
+            .ok_or(Error::FundingOverflow("total unlocked SAFE"))?;
+        let total_staked = total_tokens
+            .checked_sub(total_unlocked_sol)
+            .ok_or(Error::FundingOverflow("total staked amount"))?;
+        by_source.insert(FundingSource::StakeAccount, total_staked);
+        by_source.insert(FundingSource::SystemAccount, total_unlocked_sol);
+    } else {
+        by_source.insert(FundingSource::SystemAccount, total_tokens);
+    }
+    Ok(FundingRequirements {
+        total_tokens,
+        total_lamports_fees: fees,
+        total_rent,
+        by_source,
+    })
+}
+
 type StakeExtras = Vec<(Keypair, Option<DateTime<Utc>>)>;
 
 #[derive(thiserror::Error, Debug)]
@@ -109,6 +183,127 @@ pub enum Error {
     ProgramError(#[from] ProgramError),
     #[error("Exit signal received")]
     ExitSignal,
+    #[error("{} invalid allocation(s)", .0.len())]
+    InvalidAllocations(Vec<AllocationError>),
+    #[error("invalid amount {0}: {1}")]
+    InvalidUiAmount(String, String),
+    #[error("invalid recipient token account {0}: {1}")]
+    InvalidRecipientTokenAccount(Pubkey, String),
+    #[error("{0} allocation(s) failed to send")]
+    FailedAllocations(usize),
+    #[error("{0} recipient(s) missing or holding less than their allocation")]
+    IncompleteDistribution(usize),
+    #[error("{0} cannot be built for offline signing")]
+    UnsupportedOfflineDistribution(&'static str),
+    #[error("{0} overflows")]
+    FundingOverflow(&'static str),
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AllocationError {
+    #[error("allocation {0}: invalid recipient address {1}")]
+    InvalidRecipient(usize, String),
+    #[error("allocation {0}: amount must be greater than zero")]
+    ZeroAmount(usize),
+    #[error("allocation {0}: invalid lockup date {1}")]
+    InvalidLockupDate(usize, String),
+    #[error("allocation {0}: duplicate of allocation {1} for recipient {2}")]
+    DuplicateRecipient(usize, usize, String),
+    #[error("allocation {0}: merged amount for recipient {1} overflows")]
+    MergedAmountOverflow(usize, String),
+    #[error("allocation {0}: recipient {1} is a token account of mint {2}, not a wallet")]
+    TokenAccountRecipient(usize, String, Pubkey),
+    #[error("allocation {0}: lockup date {1} is not supported here")]
+    UnsupportedLockupDate(usize, String),
+}
+
+/// Check every allocation locally, returning all problems found rather than just the first
+pub fn validate_allocations(allocations: &[Allocation]) -> Result<(), Vec<AllocationError>> {
+    let mut errors = vec![];
+    let mut seen = HashMap::new();
+    for (i, allocation) in allocations.iter().enumerate() {
+        if allocation.recipient.parse::<Pubkey>().is_err() {
+            errors.push(AllocationError::InvalidRecipient(
+                i,
+                allocation.recipient.clone(),
+            ));
+        }
+        if allocation.amount == 0 {
+            errors.push(AllocationError::ZeroAmount(i));
+        }
+        if !allocation.lockup_date.is_empty()
+            && allocation.lockup_date.parse::<DateTime<Utc>>().is_err()
+        {
+            errors.push(AllocationError::InvalidLockupDate(
+                i,
+                allocation.lockup_date.clone(),
+            ));
+        }
+        let key = (&allocation.recipient, &allocation.lockup_date);
+        if let Some(first) = seen.get(&key) {
+            errors.push(AllocationError::DuplicateRecipient(
+                i,
+                *first,
+                allocation.recipient.clone(),
+            ));
+        } else {
+            seen.insert(key, i);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse the recipient address of every allocation, returning all invalid addresses rather than
+/// just the first
+pub fn parse_recipients(allocations: &[Allocation]) -> Result<Vec<Pubkey>, Vec<AllocationError>> {
+    let mut recipients = Vec::with_capacity(allocations.len());
+    let mut errors = vec![];
+    for (i, allocation) in allocations.iter().enumerate() {
+        match allocation.recipient.parse() {
+            Ok(recipient) => recipients.push(recipient),
+            Err(_) => errors.push(AllocationError::InvalidRecipient(
+                i,
+                allocation.recipient.clone(),
+            )),
+        }
+    }
+    if errors.is_empty() {
+        Ok(recipients)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Sum the allocations to the same recipient and lockup date into one allocation, in the order
+/// each recipient first appears
+pub fn merge_duplicate_allocations(
+    allocations: Vec<Allocation>,
+) -> Result<Vec<Allocation>, AllocationError> {
+    let mut allocation_map: IndexMap<(String, String), Allocation> = IndexMap::new();
+    for (i, allocation) in allocations.into_iter().enumerate() {
+        let key = (allocation.recipient.clone(), allocation.lockup_date.clone());
+        match allocation_map.get_mut(&key) {
+            Some(merged) => {
+                merged.amount = merged
+                    .amount
+                    .checked_add(allocation.amount)
+                    .ok_or_else(|| {
+                        AllocationError::MergedAmountOverflow(i, allocation.recipient.clone())
+                    })?;
+            }
+            None => {
+                allocation_map.insert(key, allocation);
+            }
+        }
+    }
+    Ok(allocation_map
+        .into_iter()
+        .map(|(_, allocation)| allocation)
+        .collect())
 }
 
 fn merge_allocations(allocations: &[Allocation]) -> Vec<Allocation> {
@@ -251,6 +446,107 @@ fn distribution_instructions(
     instructions
 }
 
+// Size of a transaction carrying `message` once signed
+fn signed_transaction_size(message: &Message) -> usize {
+    let num_signatures = message.header.num_required_signatures as usize;
+    // The signatures are prefixed with their count, encoded as a compact-u16
+    let signatures_len_size = match num_signatures {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    };
+    signatures_len_size
+        + num_signatures * std::mem::size_of::<Signature>()
+        + message.serialize().len()
+}
+
+/// Split `instructions` into consecutive batches, each filling a transaction paid by `fee_payer`
+/// with as many instructions as fit in `max_size` bytes once signed
+///
+/// An instruction too large to fit in `max_size` on its own is returned as a batch of its own.
+pub fn pack_instructions(
+    instructions: Vec<Instruction>,
+    fee_payer: &Pubkey,
+    max_size: usize,
+) -> Vec<Vec<Instruction>> {
+    let mut batches = vec![];
+    let mut batch: Vec<Instruction> = vec![];
+    for instruction in instructions {
+        batch.push(instruction);
+        let message = Message::new(&batch, Some(fee_payer));
+        if batch.len() > 1 && signed_transaction_size(&message) > max_size {
+            let instruction = batch.pop().unwrap();
+            batches.push(std::mem::replace(&mut batch, vec![instruction]));
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Build the unsigned messages of a distribution of `allocations`, for signing on another
+/// machine and submitting later
+///
+/// The allocations are packed into as few transactions as fit in a packet, each paid by
+/// `args.fee_payer` and referencing `recent_blockhash`, which must still be valid when the signed
+/// transactions are submitted, or be the blockhash of a durable nonce. For SPL token
+/// distributions, an associated token account is created for each recipient whose account is
+/// not in `existing_associated_token_addresses`, see `which_atas_exist`. The messages are
+/// `Serialize`, so the bundle can be written to disk in any serde format.
+///
+/// Stake and wrapped SAFE distributions are not supported, since they need keypairs generated
+/// during the distribution to sign.
+pub fn build_offline_bundle(
+    allocations: &[Allocation],
+    args: &DistributeTokensArgs,
+    recent_blockhash: Hash,
+    existing_associated_token_addresses: &HashSet<Pubkey>,
+) -> Result<Vec<Message>, Error> {
+    if args.stake_args.is_some() {
+        return Err(Error::UnsupportedOfflineDistribution("stake distribution"));
+    }
+    let spl_token_args = args.spl_token_args.as_ref();
+    if spl_token_args.map_or(false, |spl_token_args| spl_token_args.wrap_sol) {
+        return Err(Error::UnsupportedOfflineDistribution(
+            "wrapped SAFE distribution",
+        ));
+    }
+    validate_allocations(allocations).map_err(Error::InvalidAllocations)?;
+
+    let mut existing_associated_token_addresses = existing_associated_token_addresses.clone();
+    let instructions = allocations
+        .iter()
+        .flat_map(|allocation| {
+            let do_create_associated_token_account =
+                spl_token_args.map_or(false, |spl_token_args| {
+                    existing_associated_token_addresses.insert(recipient_token_address(
+                        allocation,
+                        &spl_token_args.mint,
+                        spl_token_args.token_program,
+                    ))
+                });
+            distribution_instructions(
+                allocation,
+                &Pubkey::default(),
+                args,
+                None,
+                do_create_associated_token_account,
+            )
+        })
+        .collect();
+    let fee_payer = args.fee_payer.pubkey();
+    let messages = pack_instructions(instructions, &fee_payer, PACKET_DATA_SIZE)
+        .into_iter()
+        .map(|instructions| {
+            let mut message = Message::new(&instructions, Some(&fee_payer));
+            message.recent_blockhash = recent_blockhash;
+            message
+        })
+        .collect();
+    Ok(messages)
+}
+
 fn build_messages(
     client: &RpcClient,
     db: &mut PickleDb,
@@ -261,6 +557,17 @@ fn build_messages(
     stake_extras: &mut StakeExtras,
     created_accounts: &mut u64,
 ) -> Result<(), Error> {
+    let mut existing_associated_token_addresses = if let Some(spl_token_args) = &args.spl_token_args
+    {
+        which_atas_exist(
+            client,
+            allocations,
+            &spl_token_args.mint,
+            spl_token_args.token_program,
+        )?
+    } else {
+        HashSet::new()
+    };
     for allocation in allocations.iter() {
         if exit.load(Ordering::SeqCst) {
             db.dump()?;
@@ -275,22 +582,21 @@ fn build_messages(
 
         let do_create_associated_token_account = if let Some(spl_token_args) = &args.spl_token_args
         {
-            let wallet_address = allocation.recipient.parse().unwrap();
-            let associated_token_address = get_associated_token_address(
-                &wallet_address,
-                &spl_token_v2_0_pubkey(&spl_token_args.mint),
-            );
-            let do_create_associated_token_account = client
-                .get_multiple_accounts(&[pubkey_from_spl_token_v2_0(&associated_token_address)])?
-                [0]
-            .is_none();
+            // Only the first allocation to a recipient without an associated token account
+            // needs to create it
+            let do_create_associated_token_account =
+                existing_associated_token_addresses.insert(recipient_token_address(
+                    allocation,
+                    &spl_token_args.mint,
+                    spl_token_args.token_program,
+                ));
             if do_create_associated_token_account {
                 *created_accounts += 1;
             }
             println!(
                 "{:<44}  {:>24}",
                 allocation.recipient,
-                real_number_string(allocation.amount, spl_token_args.decimals)
+                base_units_to_ui(allocation.amount, spl_token_args.decimals)
             );
             do_create_associated_token_account
         } else {
@@ -316,6 +622,98 @@ fn build_messages(
     Ok(())
 }
 
+/// Print what a distribution of `allocations` will do: the amount sent to each recipient, the
+/// address it is sent to, and whether an associated token account is created for it, followed by
+/// totals
+pub fn print_distribution_plan(
+    client: &RpcClient,
+    allocations: &[Allocation],
+    args: &DistributeTokensArgs,
+) -> Result<(), Error> {
+    println!(
+        "{}",
+        style(format!(
+            "{:<44}  {:>24}  {:<44}  {}",
+            "Recipient", "Amount", "Destination", "Create Account"
+        ))
+        .bold()
+    );
+    let total_amount = allocations.iter().map(|x| x.amount).sum();
+    let mut created_accounts = 0;
+    if let Some(spl_token_args) = &args.spl_token_args {
+        // Same account creation logic as `build_messages`
+        let mut existing_associated_token_addresses = which_atas_exist(
+            client,
+            allocations,
+            &spl_token_args.mint,
+            spl_token_args.token_program,
+        )?;
+        for allocation in allocations {
+            let destination = recipient_token_address(
+                allocation,
+                &spl_token_args.mint,
+                spl_token_args.token_program,
+            );
+            let do_create_associated_token_account =
+                existing_associated_token_addresses.insert(destination);
+            if do_create_associated_token_account {
+                created_accounts += 1;
+            }
+            println!(
+                "{:<44}  {:>24}  {:<44}  {}",
+                allocation.recipient,
+                base_units_to_ui(allocation.amount, spl_token_args.decimals),
+                destination,
+                if do_create_associated_token_account {
+                    "yes"
+                } else {
+                    "no"
+                },
+            );
+        }
+        println!(
+            "{} {}",
+            style("Total:").bold(),
+            Token::spl_token(total_amount, spl_token_args.decimals),
+        );
+    } else {
+        for allocation in allocations {
+            // Stake distributions send to a new stake account for each allocation
+            let destination = if args.stake_args.is_some() {
+                "new stake account"
+            } else {
+                allocation.recipient.as_str()
+            };
+            println!(
+                "{:<44}  {:>24.9}  {:<44}  {}",
+                allocation.recipient,
+                lamports_to_sol(allocation.amount),
+                destination,
+                "no",
+            );
+        }
+        println!("{} {}", style("Total:").bold(), Token::sol(total_amount));
+    }
+    println!(
+        "{} {}",
+        style("Associated token accounts to create:").bold(),
+        created_accounts,
+    );
+    Ok(())
+}
+
+// The fee payer and sender, plus the rent payer if `message` creates an associated token account
+fn message_signers<'a>(message: &Message, args: &'a DistributeTokensArgs) -> Vec<&'a dyn Signer> {
+    let mut signers = vec![&*args.fee_payer, &*args.sender_keypair];
+    if let Some(rent_payer) = &args.rent_payer {
+        // Only messages that create an associated token account need the rent payer
+        if message.account_keys.contains(&rent_payer.pubkey()) {
+            signers.push(&**rent_payer);
+        }
+    }
+    signers
+}
+
 fn send_messages(
     client: &RpcClient,
     db: &mut PickleDb,
@@ -324,17 +722,33 @@ fn send_messages(
     exit: Arc<AtomicBool>,
     messages: Vec<Message>,
     stake_extras: StakeExtras,
-) -> Result<(), Error> {
+) -> Result<Vec<FailedAllocation>, Error> {
+    let mut failed_allocations = vec![];
+    let mut record_failure = |allocation: &Allocation, error: String| {
+        eprintln!(
+            "Error sending tokens to {}: {}",
+            allocation.recipient, error
+        );
+        failed_allocations.push(FailedAllocation {
+            recipient: allocation.recipient.clone(),
+            amount: allocation.amount,
+            error,
+        });
+    };
+    let mut in_flight = vec![];
     for ((allocation, message), (new_stake_account_keypair, lockup_date)) in
         allocations.iter().zip(messages).zip(stake_extras)
     {
+        if args.max_in_flight > 0 && !args.dry_run {
+            wait_for_in_flight_capacity(client, &mut in_flight, args, &exit)?;
+        }
         if exit.load(Ordering::SeqCst) {
             db.dump()?;
             return Err(Error::ExitSignal);
         }
         let new_stake_account_address = new_stake_account_keypair.pubkey();
 
-        let mut signers = vec![&*args.fee_payer, &*args.sender_keypair];
+        let mut signers = message_signers(&message, args);
         if let Some(stake_args) = &args.stake_args {
             signers.push(&*stake_args.stake_authority);
             signers.push(&*stake_args.withdraw_authority);
@@ -342,6 +756,9 @@ fn send_messages(
             if !allocation.lockup_date.is_empty() {
                 if let Some(lockup_authority) = &stake_args.lockup_authority {
                     signers.push(&**lockup_authority);
+                } else if args.isolate_failures {
+                    record_failure(allocation, Error::MissingLockupAuthority.to_string());
+                    continue;
                 } else {
                     return Err(Error::MissingLockupAuthority);
                 }
@@ -352,20 +769,25 @@ fn send_messages(
             if args.dry_run {
                 Ok((Transaction::new_unsigned(message), std::u64::MAX))
             } else {
-                let (blockhash, _fee_calculator, last_valid_slot) = client
-                    .get_recent_blockhash_with_commitment(CommitmentConfig::default())?
-                    .value;
-                let transaction = Transaction::new(&signers, message, blockhash);
-                let config = RpcSendTransactionConfig {
-                    skip_preflight: true,
-                    ..RpcSendTransactionConfig::default()
-                };
-                client.send_transaction_with_config(&transaction, config)?;
-                Ok((transaction, last_valid_slot))
+                match distribution_blockhash(client, args) {
+                    Ok((blockhash, _fee_calculator, last_valid_slot)) => {
+                        let transaction = Transaction::new(&signers, message, blockhash);
+                        let config = RpcSendTransactionConfig {
+                            skip_preflight: true,
+                            ..RpcSendTransactionConfig::default()
+                        };
+                        client
+                            .send_transaction_with_config(&transaction, config)
+                            .map(|_| (transaction, last_valid_slot))
+                    }
+                    Err(err) if args.isolate_failures => Err(err),
+                    Err(err) => return Err(err.into()),
+                }
             }
         };
         match result {
             Ok((transaction, last_valid_slot)) => {
+                in_flight.push((transaction.signatures[0], last_valid_slot));
                 db::set_transaction_info(
                     db,
                     &allocation.recipient.parse().unwrap(),
@@ -377,12 +799,63 @@ fn send_messages(
                     lockup_date,
                 )?;
             }
-            Err(e) => {
-                eprintln!("Error sending tokens to {}: {}", allocation.recipient, e);
-            }
+            Err(e) => record_failure(allocation, e.to_string()),
         };
     }
-    Ok(())
+    Ok(failed_allocations)
+}
+
+/// The blockhash to sign the distribution's transactions with, `args.blockhash` if set or else a
+/// recent blockhash, along with its fee calculator and the last slot it may be valid at
+fn distribution_blockhash(
+    client: &RpcClient,
+    args: &DistributeTokensArgs,
+) -> ClientResult<(Hash, FeeCalculator, Slot)> {
+    let blockhash = match args.blockhash {
+        Some(blockhash) => blockhash,
+        None => {
+            return client
+                .get_recent_blockhash_with_commitment(CommitmentConfig::default())
+                .map(|response| response.value)
+        }
+    };
+    let response = client.get_fee_calculator_for_blockhash_with_commitment(
+        &blockhash,
+        CommitmentConfig::default(),
+    )?;
+    let fee_calculator = response.value.ok_or_else(|| {
+        ClientError::from(ClientErrorKind::Custom(format!(
+            "blockhash {} not found",
+            blockhash
+        )))
+    })?;
+    // The blockhash is at most as new as the bank that found it, so it expires no later than
+    // a full blockhash queue past that bank's slot
+    let last_valid_slot = response.context.slot + MAX_RECENT_BLOCKHASHES as u64;
+    Ok((blockhash, fee_calculator, last_valid_slot))
+}
+
+/// Total fee, in lamports, the cluster charges for sending `messages`, the packed messages of a
+/// distribution, at the fee rate of the blockhash the distribution signs with
+///
+/// Each message is priced the way the bank prices it, so signatures verified by the secp256k1
+/// program are counted along with the transaction signatures.
+pub fn estimate_distribution_fees(
+    client: &RpcClient,
+    messages: &[Message],
+    args: &DistributeTokensArgs,
+) -> Result<u64, Error> {
+    let (_blockhash, fee_calculator, _last_valid_slot) = distribution_blockhash(client, args)?;
+    messages_fee(&fee_calculator, messages)
+}
+
+fn messages_fee(fee_calculator: &FeeCalculator, messages: &[Message]) -> Result<u64, Error> {
+    messages
+        .iter()
+        .try_fold(0u64, |total, message| {
+            total.checked_add(fee_calculator.calculate_fee(message))
+        })
+        .ok_or(Error::FundingOverflow("transaction fees"))
 }
 
 fn distribute_allocations(
@@ -391,7 +864,7 @@ fn distribute_allocations(
     allocations: &[Allocation],
     args: &DistributeTokensArgs,
     exit: Arc<AtomicBool>,
-) -> Result<(), Error> {
+) -> Result<Vec<FailedAllocation>, Error> {
     let mut messages: Vec<Message> = vec![];
     let mut stake_extras: StakeExtras = vec![];
     let mut created_accounts = 0;
@@ -407,20 +880,33 @@ fn distribute_allocations(
         &mut created_accounts,
     )?;
 
-    let num_signatures = messages
-        .iter()
-        .map(|message| message.header.num_required_signatures as usize)
-        .sum();
     if args.spl_token_args.is_some() {
-        check_spl_token_balances(num_signatures, allocations, client, args, created_accounts)?;
+        let fees = estimate_distribution_fees(client, &messages, args)?;
+        check_spl_token_balances(fees, allocations, client, args, created_accounts)?;
     } else {
+        let num_signatures = messages
+            .iter()
+            .map(|message| message.header.num_required_signatures as usize)
+            .sum();
         check_payer_balances(num_signatures, allocations, client, args)?;
     }
 
-    send_messages(client, db, allocations, args, exit, messages, stake_extras)?;
+    if let Some(spl_token_args) = &args.spl_token_args {
+        if spl_token_args.wrap_sol && !args.dry_run {
+            println!(
+                "{} {}",
+                style("Wrapping SAFE into:").bold(),
+                spl_token_args.token_account_address,
+            );
+            wrap_sol(client, args, allocations.iter().map(|x| x.amount).sum())?;
+        }
+    }
+
+    let failed_allocations =
+        send_messages(client, db, allocations, args, exit, messages, stake_extras)?;
 
     db.dump()?;
-    Ok(())
+    Ok(failed_allocations)
 }
 
 fn read_allocations(
@@ -457,18 +943,20 @@ fn read_allocations(
             })
             .collect()
     } else if raw_amount {
-        let recipients: Vec<(String, u64)> = rdr
+        let recipients: Vec<(String, String)> = rdr
             .deserialize()
             .map(|recipient| recipient.unwrap())
             .collect();
         recipients
             .into_iter()
-            .map(|(recipient, amount)| Allocation {
-                recipient,
-                amount,
-                lockup_date: "".to_string(),
+            .map(|(recipient, amount)| {
+                Ok(Allocation {
+                    amount: parse_raw_amount(&recipient, &amount)?,
+                    recipient,
+                    lockup_date: "".to_string(),
+                })
             })
-            .collect()
+            .collect::<io::Result<_>>()?
     } else {
         let recipients: Vec<(String, f64)> = rdr
             .deserialize()
@@ -486,6 +974,126 @@ fn read_allocations(
     Ok(allocations)
 }
 
+/// Parse an amount of token base units exactly, rejecting anything but a whole number such as
+/// a "1.5" that was meant as a UI amount
+fn parse_raw_amount(recipient: &str, amount: &str) -> io::Result<u64> {
+    if amount.is_empty() || !amount.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid_raw_amount(recipient, amount));
+    }
+    amount.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("recipient {}: amount {} is too large", recipient, amount),
+        )
+    })
+}
+
+fn invalid_raw_amount(recipient: &str, amount: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "recipient {}: invalid amount {}, raw amounts must be whole base units",
+            recipient, amount
+        ),
+    )
+}
+
+#[derive(Deserialize)]
+struct JsonAllocation<T> {
+    recipient: String,
+    amount: Option<T>,
+    #[serde(default)]
+    lockup_date: String,
+}
+
+fn read_json_allocations<T: serde::de::DeserializeOwned>(
+    input_path: &str,
+) -> io::Result<Vec<JsonAllocation<T>>> {
+    let reader = BufReader::new(File::open(input_path)?);
+    let mut allocations = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        allocations.push(serde_json::from_str(&line)?);
+    }
+    Ok(allocations)
+}
+
+fn read_allocations_ndjson(
+    input_path: &str,
+    transfer_amount: Option<u64>,
+    raw_amount: bool,
+) -> io::Result<Vec<Allocation>> {
+    let missing_amount = |recipient: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("missing amount for recipient {}", recipient),
+        )
+    };
+    let allocations = if let Some(amount) = transfer_amount {
+        read_json_allocations::<serde_json::Value>(input_path)?
+            .into_iter()
+            .map(|allocation| Allocation {
+                recipient: allocation.recipient,
+                amount,
+                lockup_date: allocation.lockup_date,
+            })
+            .collect()
+    } else if raw_amount {
+        read_json_allocations::<serde_json::Number>(input_path)?
+            .into_iter()
+            .map(|allocation| {
+                let amount = allocation
+                    .amount
+                    .ok_or_else(|| missing_amount(&allocation.recipient))?;
+                let amount = amount.as_u64().ok_or_else(|| {
+                    invalid_raw_amount(&allocation.recipient, &amount.to_string())
+                })?;
+                Ok(Allocation {
+                    amount,
+                    recipient: allocation.recipient,
+                    lockup_date: allocation.lockup_date,
+                })
+            })
+            .collect::<io::Result<_>>()?
+    } else {
+        read_json_allocations::<f64>(input_path)?
+            .into_iter()
+            .map(|allocation| {
+                let amount = allocation
+                    .amount
+                    .ok_or_else(|| missing_amount(&allocation.recipient))?;
+                Ok(Allocation {
+                    recipient: allocation.recipient,
+                    amount: sol_to_lamports(amount),
+                    lockup_date: allocation.lockup_date,
+                })
+            })
+            .collect::<io::Result<_>>()?
+    };
+    Ok(allocations)
+}
+
+fn load_allocations(
+    input_path: &str,
+    input_format: Option<InputFormat>,
+    transfer_amount: Option<u64>,
+    require_lockup_heading: bool,
+    raw_amount: bool,
+) -> io::Result<Vec<Allocation>> {
+    match input_format.unwrap_or_else(|| InputFormat::from_path(input_path)) {
+        InputFormat::Csv => read_allocations(
+            input_path,
+            transfer_amount,
+            require_lockup_heading,
+            raw_amount,
+        ),
+        InputFormat::Ndjson => read_allocations_ndjson(input_path, transfer_amount, raw_amount),
+    }
+}
+
 fn new_spinner_progress_bar() -> ProgressBar {
     let progress_bar = ProgressBar::new(42);
     progress_bar
@@ -494,18 +1102,166 @@ fn new_spinner_progress_bar() -> ProgressBar {
     progress_bar
 }
 
+/// Distribution status of a single allocation, see `DistributionReport`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllocationStatus {
+    /// Every transaction for the allocation is finalized
+    Finalized,
+    /// Not yet finalized, or not sent at all in a dry run
+    Pending,
+    /// The allocation's transaction could not be sent
+    Failed(String),
+}
+
+/// Outcome of one allocation passed to `distribute()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationOutcome {
+    pub allocation: Allocation,
+    pub status: AllocationStatus,
+    /// Signatures of the transactions recorded for the allocation, including those sent by
+    /// earlier runs against the same transaction database
+    pub signatures: Vec<Signature>,
+}
+
+/// Outcome of `distribute()`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DistributionReport {
+    /// Number of transactions still awaiting confirmation, as returned by `process_allocations()`
+    pub confirmations: Option<usize>,
+    /// One outcome per allocation, in input order after merging duplicates
+    pub allocations: Vec<AllocationOutcome>,
+}
+
+impl DistributionReport {
+    pub fn failed(&self) -> impl Iterator<Item = &AllocationOutcome> {
+        self.allocations
+            .iter()
+            .filter(|outcome| matches!(outcome.status, AllocationStatus::Failed(_)))
+    }
+}
+
+fn allocation_outcomes(
+    allocations: Vec<Allocation>,
+    transaction_infos: &[TransactionInfo],
+    failed_allocations: &[FailedAllocation],
+) -> Vec<AllocationOutcome> {
+    allocations
+        .into_iter()
+        .map(|allocation| {
+            let transaction_infos: Vec<_> = transaction_infos
+                .iter()
+                .filter(|transaction_info| has_same_recipient(&allocation, transaction_info))
+                .collect();
+            let failed_allocation = failed_allocations
+                .iter()
+                .find(|failed_allocation| failed_allocation.recipient == allocation.recipient);
+            let status = if let Some(failed_allocation) = failed_allocation {
+                AllocationStatus::Failed(failed_allocation.error.clone())
+            } else if !transaction_infos.is_empty()
+                && transaction_infos
+                    .iter()
+                    .all(|transaction_info| transaction_info.finalized_date.is_some())
+            {
+                AllocationStatus::Finalized
+            } else {
+                AllocationStatus::Pending
+            };
+            let signatures = transaction_infos
+                .iter()
+                .filter_map(|transaction_info| transaction_info.transaction.signatures.first())
+                .copied()
+                .collect();
+            AllocationOutcome {
+                allocation,
+                status,
+                signatures,
+            }
+        })
+        .collect()
+}
+
+/// Distribute the allocations read from `args.input_csv`, see `distribute()`
+///
+/// Returns `Error::FailedAllocations` if `args.isolate_failures` is set and any allocation
+/// failed to send.
 pub fn process_allocations(
     client: &RpcClient,
     args: &DistributeTokensArgs,
     exit: Arc<AtomicBool>,
 ) -> Result<Option<usize>, Error> {
     let require_lockup_heading = args.stake_args.is_some();
-    let mut allocations: Vec<Allocation> = read_allocations(
+    let allocations: Vec<Allocation> = load_allocations(
         &args.input_csv,
+        args.input_format,
         args.transfer_amount,
         require_lockup_heading,
         args.spl_token_args.is_some(),
     )?;
+    let report = distribute(client, allocations, args, exit)?;
+
+    let failed: Vec<_> = report.failed().collect();
+    if !failed.is_empty() {
+        eprintln!(
+            "{}",
+            style(format!("{} allocation(s) failed:", failed.len())).red()
+        );
+        for outcome in &failed {
+            if let AllocationStatus::Failed(error) = &outcome.status {
+                eprintln!("{:<44}  {}", outcome.allocation.recipient, error);
+            }
+        }
+        if args.isolate_failures {
+            return Err(Error::FailedAllocations(failed.len()));
+        }
+    }
+
+    Ok(report.confirmations)
+}
+
+/// Distribute `allocations` as configured by `args`, ignoring `args.input_csv`,
+/// `args.input_format` and `args.transfer_amount`
+///
+/// Creates recipient token accounts, batches and sends the transactions, and confirms them,
+/// recording everything in `args.transaction_db` so that an interrupted distribution can be
+/// resumed by calling this again with the same allocations. Allocations that fail to send are
+/// reported in the returned `DistributionReport` rather than as an error.
+pub fn distribute(
+    client: &RpcClient,
+    mut allocations: Vec<Allocation>,
+    args: &DistributeTokensArgs,
+    exit: Arc<AtomicBool>,
+) -> Result<DistributionReport, Error> {
+    let start = Instant::now();
+    if args.merge_duplicates {
+        let num_allocations = allocations.len();
+        allocations = merge_duplicate_allocations(allocations)
+            .map_err(|err| Error::InvalidAllocations(vec![err]))?;
+        if allocations.len() < num_allocations {
+            println!(
+                "Merged {} duplicate allocation(s)",
+                num_allocations - allocations.len()
+            );
+        }
+    }
+    if let Err(errors) = validate_allocations(&allocations) {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        return Err(Error::InvalidAllocations(errors));
+    }
+    if let Some(spl_token_args) = &args.spl_token_args {
+        if spl_token_args.check_recipients {
+            let errors =
+                check_spl_token_recipients(client, &allocations, spl_token_args.token_program)?;
+            if !errors.is_empty() {
+                for error in &errors {
+                    eprintln!("{}", error);
+                }
+                return Err(Error::InvalidAllocations(errors));
+            }
+        }
+    }
+    let total_recipients = allocations.len();
 
     let starting_total_tokens = allocations.iter().map(|x| x.amount).sum();
     let starting_total_tokens = if let Some(spl_token_args) = &args.spl_token_args {
@@ -524,12 +1280,17 @@ pub fn process_allocations(
     // Start by finalizing any transactions from the previous run.
     let confirmations = finalize_transactions(client, &mut db, args.dry_run, exit.clone())?;
 
+    let all_allocations = allocations.clone();
     let transaction_infos = db::read_transaction_infos(&db);
     apply_previous_transactions(&mut allocations, &transaction_infos);
 
     if allocations.is_empty() {
         eprintln!("No work to do");
-        return Ok(confirmations);
+        write_summary(client, &db, args, total_recipients, &[], start)?;
+        return Ok(DistributionReport {
+            confirmations,
+            allocations: allocation_outcomes(all_allocations, &transaction_infos, &[]),
+        });
     }
 
     let distributed_tokens = transaction_infos.iter().map(|x| x.amount).sum();
@@ -558,37 +1319,406 @@ pub fn process_allocations(
         distributed_tokens + undistributed_tokens,
     );
 
+    if args.dry_run {
+        print_distribution_plan(client, &allocations, args)?;
+    }
+
     println!(
         "{}",
         style(format!("{:<44}  {:>24}", "Recipient", "Expected Balance",)).bold()
     );
 
-    distribute_allocations(client, &mut db, &allocations, args, exit.clone())?;
-
-    let opt_confirmations = finalize_transactions(client, &mut db, args.dry_run, exit)?;
+    let result = send_and_confirm_allocations(client, &mut db, &allocations, args, exit);
+    let wrapped_sol = args
+        .spl_token_args
+        .as_ref()
+        .map_or(false, |spl_token_args| spl_token_args.wrap_sol);
+    if wrapped_sol && !args.dry_run {
+        // Close the wrapped SAFE account whether or not the distribution succeeded, returning
+        // the undistributed lamports to the sender
+        match unwrap_sol(client, args) {
+            Err(err) if result.is_ok() => return Err(err),
+            Err(err) => eprintln!("Error closing the wrapped SAFE account: {}", err),
+            Ok(()) => {}
+        }
+    }
+    let (failed_allocations, opt_confirmations) = result?;
 
     if !args.dry_run {
         if let Some(output_path) = &args.output_path {
             db::write_transaction_log(&db, &output_path)?;
+            if args.isolate_failures {
+                db::write_failed_allocations(
+                    &failed_allocations,
+                    &db::failed_allocations_path(output_path),
+                )?;
+            }
         }
     }
+    write_summary(
+        client,
+        &db,
+        args,
+        total_recipients,
+        &failed_allocations,
+        start,
+    )?;
 
-    Ok(opt_confirmations)
+    Ok(DistributionReport {
+        confirmations: opt_confirmations,
+        allocations: allocation_outcomes(
+            all_allocations,
+            &db::read_transaction_infos(&db),
+            &failed_allocations,
+        ),
+    })
 }
 
-fn finalize_transactions(
+/// Send and confirm the transactions of `allocations`, returning the allocations that failed to
+/// send and the number of transactions still awaiting confirmation
+fn send_and_confirm_allocations(
     client: &RpcClient,
     db: &mut PickleDb,
-    dry_run: bool,
+    allocations: &[Allocation],
+    args: &DistributeTokensArgs,
     exit: Arc<AtomicBool>,
-) -> Result<Option<usize>, Error> {
-    if dry_run {
-        return Ok(None);
+) -> Result<(Vec<FailedAllocation>, Option<usize>), Error> {
+    let sent_before: HashSet<_> = db::read_transaction_infos(db)
+        .iter()
+        .map(|info| info.transaction.signatures[0])
+        .collect();
+    let failed_allocations = distribute_allocations(client, db, allocations, args, exit.clone())?;
+    if args.max_resubmits > 0 && !args.dry_run {
+        resubmit_expired_transactions(client, db, args, &sent_before, &exit)?;
     }
 
-    let mut opt_confirmations = update_finalized_transactions(client, db, exit.clone())?;
-
-    let progress_bar = new_spinner_progress_bar();
+    let opt_confirmations = match args.confirm_commitment {
+        Some(commitment) if !args.dry_run => {
+            confirm_transactions(client, db, commitment, exit.clone())?;
+            if commitment.is_finalized() {
+                // Record the finalized transactions in the database
+                finalize_transactions(client, db, args.dry_run, exit)?
+            } else {
+                None
+            }
+        }
+        _ => finalize_transactions(client, db, args.dry_run, exit)?,
+    };
+    Ok((failed_allocations, opt_confirmations))
+}
+
+/// Write the summary of the distribution to `args.summary_path`, if set. Fees and rent are
+/// taken from the transaction log, so they include transactions sent by earlier runs.
+fn write_summary(
+    client: &RpcClient,
+    db: &PickleDb,
+    args: &DistributeTokensArgs,
+    total_recipients: usize,
+    failed_allocations: &[FailedAllocation],
+    start: Instant,
+) -> Result<(), Error> {
+    let summary_path = match &args.summary_path {
+        Some(summary_path) if !args.dry_run => summary_path,
+        _ => return Ok(()),
+    };
+    let lamports_per_signature = client.get_recent_blockhash()?.1.lamports_per_signature;
+    let rent_per_account = if args.spl_token_args.is_some() {
+        token_account_rent_exempt_balance(client)?
+    } else {
+        0
+    };
+    let mut summary = db::DistributionSummary {
+        total_recipients,
+        failed: failed_allocations.to_vec(),
+        elapsed_secs: start.elapsed().as_secs_f64(),
+        ..db::DistributionSummary::default()
+    };
+    for transaction_info in db::read_transaction_infos(db) {
+        let transaction = &transaction_info.transaction;
+        summary.total_fees += lamports_per_signature * transaction.signatures.len() as u64;
+        if transaction_info.finalized_date.is_some() {
+            summary.succeeded += 1;
+            summary.total_amount += transaction_info.amount;
+            if let Some(spl_token_args) = &args.spl_token_args {
+                summary.total_rent += rent_per_account
+                    * created_associated_token_accounts(transaction, spl_token_args.token_program);
+            }
+        } else {
+            summary.pending += 1;
+        }
+    }
+    db::write_distribution_summary(summary_path, &summary)?;
+    Ok(())
+}
+
+/// Outcome of `confirm_signatures()`
+#[derive(Debug, Default, PartialEq)]
+pub struct SignatureConfirmations {
+    /// Signatures that reached the requested commitment
+    pub confirmed: Vec<Signature>,
+    /// Signatures of transactions that failed, with their error
+    pub failed: Vec<(Signature, TransactionError)>,
+    /// Signatures still pending when the timeout elapsed
+    pub unconfirmed: Vec<Signature>,
+}
+
+/// Poll the statuses of `signatures`, `MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS` per request,
+/// until each has either reached `commitment` or failed, or until `timeout` elapses
+pub fn confirm_signatures(
+    client: &RpcClient,
+    signatures: &[Signature],
+    commitment: CommitmentConfig,
+    timeout: Duration,
+    exit: Arc<AtomicBool>,
+) -> Result<SignatureConfirmations, Error> {
+    let start = Instant::now();
+    let progress_bar = new_spinner_progress_bar();
+    let mut confirmations = SignatureConfirmations::default();
+    let mut pending = signatures.to_vec();
+    loop {
+        pending = poll_signatures(client, &pending, commitment, &mut confirmations)?;
+        if pending.is_empty() || start.elapsed() >= timeout {
+            break;
+        }
+        if exit.load(Ordering::SeqCst) {
+            return Err(Error::ExitSignal);
+        }
+        progress_bar.set_message(&format!(
+            "[{}/{}] Confirming transactions",
+            signatures.len() - pending.len(),
+            signatures.len(),
+        ));
+
+        // Sleep for about 1 slot
+        sleep(Duration::from_millis(500));
+    }
+    progress_bar.finish_and_clear();
+    confirmations.unconfirmed = pending;
+    Ok(confirmations)
+}
+
+/// Add the `signatures` that failed or reached `commitment` to `confirmations`, returning the
+/// signatures still pending
+fn poll_signatures(
+    client: &RpcClient,
+    signatures: &[Signature],
+    commitment: CommitmentConfig,
+    confirmations: &mut SignatureConfirmations,
+) -> Result<Vec<Signature>, Error> {
+    let mut pending = vec![];
+    for chunk in signatures.chunks(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS) {
+        let statuses = client.get_signature_statuses(chunk)?.value;
+        for (signature, opt_status) in chunk.iter().zip(statuses) {
+            match opt_status {
+                Some(TransactionStatus { err: Some(err), .. }) => {
+                    confirmations.failed.push((*signature, err));
+                }
+                Some(status) if status.satisfies_commitment(commitment) => {
+                    confirmations.confirmed.push(*signature);
+                }
+                _ => pending.push(*signature),
+            }
+        }
+    }
+    Ok(pending)
+}
+
+/// Wait until fewer than `args.max_in_flight` of the `in_flight` transactions are outstanding,
+/// or until the exit signal is received. A transaction stops counting once it fails, reaches the
+/// `--confirm-commitment` level (confirmed by default), or its blockhash expires.
+fn wait_for_in_flight_capacity(
+    client: &RpcClient,
+    in_flight: &mut Vec<(Signature, Slot)>,
+    args: &DistributeTokensArgs,
+    exit: &AtomicBool,
+) -> Result<(), Error> {
+    let commitment = args
+        .confirm_commitment
+        .unwrap_or_else(CommitmentConfig::confirmed);
+    while in_flight.len() >= args.max_in_flight && !exit.load(Ordering::SeqCst) {
+        let signatures: Vec<_> = in_flight.iter().map(|(signature, _)| *signature).collect();
+        let pending: HashSet<_> = poll_signatures(
+            client,
+            &signatures,
+            commitment,
+            &mut SignatureConfirmations::default(),
+        )?
+        .into_iter()
+        .collect();
+        let slot = client.get_slot()?;
+        in_flight.retain(|(signature, last_valid_slot)| {
+            pending.contains(signature) && *last_valid_slot >= slot
+        });
+        if in_flight.len() >= args.max_in_flight {
+            // Sleep for about 1 slot
+            sleep(Duration::from_millis(500));
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the transactions sent by this run, those not in `sent_before`, to land, re-signing
+/// with a fresh blockhash and resending each one whose blockhash expires first, up to
+/// `args.max_resubmits` times. Every resubmission replaces the expired transaction in the
+/// transaction log, which keeps the expired signature in `previous_signatures`.
+fn resubmit_expired_transactions(
+    client: &RpcClient,
+    db: &mut PickleDb,
+    args: &DistributeTokensArgs,
+    sent_before: &HashSet<Signature>,
+    exit: &AtomicBool,
+) -> Result<(), Error> {
+    if args.stake_args.is_some() {
+        // The keypairs of the new stake accounts, which must sign again, are not kept
+        eprintln!("Expired stake distribution transactions are not resubmitted");
+        return Ok(());
+    }
+    let progress_bar = new_spinner_progress_bar();
+    loop {
+        if exit.load(Ordering::SeqCst) {
+            db.dump()?;
+            return Err(Error::ExitSignal);
+        }
+        let transaction_infos: Vec<_> = db::read_transaction_infos(db)
+            .into_iter()
+            .filter(|info| info.finalized_date.is_none())
+            // Filter out dry-run signatures
+            .filter(|info| info.transaction.signatures[0] != Signature::default())
+            // Only this run's transactions are known to have been signed and sent recently
+            .filter(|info| !sent_before.contains(&info.transaction.signatures[0]))
+            .collect();
+        let signatures: Vec<_> = transaction_infos
+            .iter()
+            .map(|info| info.transaction.signatures[0])
+            .collect();
+        let mut statuses = vec![];
+        for chunk in signatures.chunks(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS) {
+            statuses.extend(client.get_signature_statuses(chunk)?.value);
+        }
+        let slot = client.get_slot()?;
+
+        let mut waiting = 0;
+        for (info, opt_status) in transaction_infos.iter().zip(statuses) {
+            if opt_status.is_some() {
+                // Landed, finalize_transactions() takes it from here
+                continue;
+            }
+            if info.last_valid_slot >= slot {
+                waiting += 1;
+            } else if info.resubmits < args.max_resubmits {
+                // The status cache only covers recent slots, so make sure the transaction did
+                // not land before re-signing it, which would pay the recipient twice
+                let signature = info.transaction.signatures[0];
+                if client.get_signature_statuses_with_history(&[signature])?.value[0].is_some() {
+                    continue;
+                }
+                resubmit_transaction(client, db, args, info)?;
+                waiting += 1;
+            } else {
+                eprintln!(
+                    "Transaction with signature {} expired after {} resubmission(s)",
+                    info.transaction.signatures[0], info.resubmits
+                );
+            }
+        }
+        db.dump()?;
+        if waiting == 0 {
+            break;
+        }
+        progress_bar.set_message(&format!("Waiting for {} transactions to land", waiting));
+
+        // Sleep for about 1 slot
+        sleep(Duration::from_millis(500));
+    }
+    progress_bar.finish_and_clear();
+    Ok(())
+}
+
+// Re-sign the message of the expired transaction `info` with a fresh blockhash, send it, and
+// record it in place of the expired transaction. The resubmission is recorded even if sending
+// fails, so that it counts against `args.max_resubmits` once its own blockhash expires.
+fn resubmit_transaction(
+    client: &RpcClient,
+    db: &mut PickleDb,
+    args: &DistributeTokensArgs,
+    info: &TransactionInfo,
+) -> Result<(), Error> {
+    let (blockhash, _fee_calculator, last_valid_slot) = client
+        .get_recent_blockhash_with_commitment(CommitmentConfig::default())?
+        .value;
+    let message = info.transaction.message.clone();
+    let signers = unique_signers(message_signers(&message, args));
+    let transaction = Transaction::new(&signers, message, blockhash);
+    eprintln!(
+        "Transaction with signature {} expired, resubmitting as {} ({} of {})",
+        info.transaction.signatures[0],
+        transaction.signatures[0],
+        info.resubmits + 1,
+        args.max_resubmits,
+    );
+    let config = RpcSendTransactionConfig {
+        skip_preflight: true,
+        ..RpcSendTransactionConfig::default()
+    };
+    if let Err(err) = client.send_transaction_with_config(&transaction, config) {
+        eprintln!(
+            "Error resubmitting transaction {}: {}",
+            transaction.signatures[0], err
+        );
+    }
+    db::set_resubmitted_transaction_info(db, info, &transaction, last_valid_slot)?;
+    Ok(())
+}
+
+// Confirm the transactions of the distribution not yet finalized in the database, reporting any
+// that failed or timed out
+fn confirm_transactions(
+    client: &RpcClient,
+    db: &PickleDb,
+    commitment: CommitmentConfig,
+    exit: Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let signatures: Vec<_> = db::read_transaction_infos(db)
+        .iter()
+        .filter(|info| info.finalized_date.is_none())
+        .map(|info| info.transaction.signatures[0])
+        .filter(|sig| *sig != Signature::default()) // Filter out dry-run signatures
+        .collect();
+    let confirmations = confirm_signatures(client, &signatures, commitment, CONFIRM_TIMEOUT, exit)?;
+
+    for (signature, err) in &confirmations.failed {
+        eprintln!("Error in transaction with signature {}: {}", signature, err);
+    }
+    for signature in &confirmations.unconfirmed {
+        eprintln!(
+            "Transaction with signature {} not {:?} after {}s",
+            signature,
+            commitment.commitment,
+            CONFIRM_TIMEOUT.as_secs()
+        );
+    }
+    println!(
+        "{} of {} transactions {:?}",
+        confirmations.confirmed.len(),
+        signatures.len(),
+        commitment.commitment
+    );
+    Ok(())
+}
+
+fn finalize_transactions(
+    client: &RpcClient,
+    db: &mut PickleDb,
+    dry_run: bool,
+    exit: Arc<AtomicBool>,
+) -> Result<Option<usize>, Error> {
+    if dry_run {
+        return Ok(None);
+    }
+
+    let mut opt_confirmations = update_finalized_transactions(client, db, exit.clone())?;
+
+    let progress_bar = new_spinner_progress_bar();
 
     while opt_confirmations.is_some() {
         if let Some(confirmations) = opt_confirmations {
@@ -696,25 +1826,32 @@ fn check_payer_balances(
     client: &RpcClient,
     args: &DistributeTokensArgs,
 ) -> Result<(), Error> {
-    let mut undistributed_tokens: u64 = allocations.iter().map(|x| x.amount).sum();
-
-    let (_blockhash, fee_calculator) = client.get_recent_blockhash()?;
+    let (_blockhash, fee_calculator, _last_valid_slot) = distribution_blockhash(client, args)?;
     let fees = fee_calculator
         .lamports_per_signature
         .checked_mul(num_signatures as u64)
-        .unwrap();
+        .ok_or(Error::FundingOverflow("transaction fees"))?;
+    let requirements = compute_funding_requirements(allocations, args, fees, 0)?;
 
-    let (distribution_source, unlocked_sol_source) = if let Some(stake_args) = &args.stake_args {
-        let total_unlocked_sol = allocations.len() as u64 * stake_args.unlocked_sol;
-        undistributed_tokens -= total_unlocked_sol;
-        (
-            stake_args.stake_account_address,
-            Some((args.sender_keypair.pubkey(), total_unlocked_sol)),
-        )
-    } else {
-        (args.sender_keypair.pubkey(), None)
-    };
+    let (distribution_source, undistributed_tokens, unlocked_sol_source) =
+        if let Some(stake_args) = &args.stake_args {
+            (
+                stake_args.stake_account_address,
+                requirements.required(&FundingSource::StakeAccount),
+                Some((
+                    args.sender_keypair.pubkey(),
+                    requirements.required(&FundingSource::SystemAccount),
+                )),
+            )
+        } else {
+            (
+                args.sender_keypair.pubkey(),
+                requirements.required(&FundingSource::SystemAccount),
+                None,
+            )
+        };
 
+    let fee_payer_required = requirements.required(&FundingSource::FeePayer);
     let fee_payer_balance = client.get_balance(&args.fee_payer.pubkey())?;
     if let Some((unlocked_sol_source, total_unlocked_sol)) = unlocked_sol_source {
         let staker_balance = client.get_balance(&distribution_source)?;
@@ -725,17 +1862,20 @@ fn check_payer_balances(
             ));
         }
         if args.fee_payer.pubkey() == unlocked_sol_source {
-            if fee_payer_balance < fees + total_unlocked_sol {
+            let required = fee_payer_required
+                .checked_add(total_unlocked_sol)
+                .ok_or(Error::FundingOverflow("fees plus unlocked SAFE"))?;
+            if fee_payer_balance < required {
                 return Err(Error::InsufficientFunds(
                     vec![FundingSource::SystemAccount, FundingSource::FeePayer].into(),
-                    lamports_to_sol(fees + total_unlocked_sol).to_string(),
+                    lamports_to_sol(required).to_string(),
                 ));
             }
         } else {
-            if fee_payer_balance < fees {
+            if fee_payer_balance < fee_payer_required {
                 return Err(Error::InsufficientFunds(
                     vec![FundingSource::FeePayer].into(),
-                    lamports_to_sol(fees).to_string(),
+                    lamports_to_sol(fee_payer_required).to_string(),
                 ));
             }
             let unlocked_sol_balance = client.get_balance(&unlocked_sol_source)?;
@@ -747,17 +1887,20 @@ fn check_payer_balances(
             }
         }
     } else if args.fee_payer.pubkey() == distribution_source {
-        if fee_payer_balance < fees + undistributed_tokens {
+        let required = fee_payer_required
+            .checked_add(undistributed_tokens)
+            .ok_or(Error::FundingOverflow("fees plus total allocation amount"))?;
+        if fee_payer_balance < required {
             return Err(Error::InsufficientFunds(
                 vec![FundingSource::SystemAccount, FundingSource::FeePayer].into(),
-                lamports_to_sol(fees + undistributed_tokens).to_string(),
+                lamports_to_sol(required).to_string(),
             ));
         }
     } else {
-        if fee_payer_balance < fees {
+        if fee_payer_balance < fee_payer_required {
             return Err(Error::InsufficientFunds(
                 vec![FundingSource::FeePayer].into(),
-                lamports_to_sol(fees).to_string(),
+                lamports_to_sol(fee_payer_required).to_string(),
             ));
         }
         let sender_balance = client.get_balance(&distribution_source)?;
@@ -772,8 +1915,19 @@ fn check_payer_balances(
 }
 
 pub fn process_balances(client: &RpcClient, args: &BalancesArgs) -> Result<(), Error> {
-    let allocations: Vec<Allocation> =
-        read_allocations(&args.input_csv, None, false, args.spl_token_args.is_some())?;
+    let allocations: Vec<Allocation> = load_allocations(
+        &args.input_csv,
+        args.input_format,
+        None,
+        false,
+        args.spl_token_args.is_some(),
+    )?;
+    if let Err(errors) = parse_recipients(&allocations) {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        return Err(Error::InvalidAllocations(errors));
+    }
     let allocations = merge_allocations(&allocations);
 
     let token = if let Some(spl_token_args) = &args.spl_token_args {
@@ -792,23 +1946,366 @@ pub fn process_balances(client: &RpcClient, args: &BalancesArgs) -> Result<(), E
         .bold()
     );
 
+    let mut num_unfunded = 0;
     for allocation in &allocations {
-        if let Some(spl_token_args) = &args.spl_token_args {
-            print_token_balances(client, allocation, spl_token_args)?;
+        let funded = if let Some(spl_token_args) = &args.spl_token_args {
+            print_token_balances(client, allocation, spl_token_args, &args.display_options)?
         } else {
             let address: Pubkey = allocation.recipient.parse().unwrap();
-            let expected = lamports_to_sol(allocation.amount);
-            let actual = lamports_to_sol(client.get_balance(&address).unwrap());
+            let expected = allocation.amount;
+            let actual = client.get_balance(&address).unwrap();
             println!(
-                "{:<44}  {:>24.9}  {:>24.9}  {:>24.9}",
+                "{:<44}  {:>24}  {:>24}  {:>24}",
                 allocation.recipient,
-                expected,
-                actual,
-                actual - expected,
+                format_base_units(expected, 9, &args.display_options),
+                format_base_units(actual, 9, &args.display_options),
+                format_base_units_difference(expected, actual, 9, &args.display_options),
             );
+            actual >= expected
+        };
+        if !funded {
+            num_unfunded += 1;
         }
     }
 
+    if args.strict && num_unfunded > 0 {
+        eprintln!(
+            "{}",
+            style(format!(
+                "{} recipient(s) missing or underfunded",
+                num_unfunded
+            ))
+            .red()
+        );
+        return Err(Error::IncompleteDistribution(num_unfunded));
+    }
+    Ok(())
+}
+
+/// How far a recipient's current balance is from its allocation
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReconcileStatus {
+    NotStarted,
+    Partial,
+    Complete,
+    Overfunded,
+}
+
+impl ReconcileStatus {
+    pub fn new(expected: u64, actual: u64) -> Self {
+        if actual == expected {
+            ReconcileStatus::Complete
+        } else if actual > expected {
+            ReconcileStatus::Overfunded
+        } else if actual == 0 {
+            ReconcileStatus::NotStarted
+        } else {
+            ReconcileStatus::Partial
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    pub recipient: String,
+    pub expected: u64,
+    pub actual: u64,
+    pub status: ReconcileStatus,
+}
+
+/// Compare each allocation against the recipient's current balance, in lamports or, with
+/// `spl_token_args`, in token base units of the recipient's associated token account
+pub fn reconcile_allocations(
+    client: &RpcClient,
+    allocations: &[Allocation],
+    spl_token_args: Option<&SafeTokenArgs>,
+) -> Result<Vec<Reconciliation>, Error> {
+    let balances = if let Some(spl_token_args) = spl_token_args {
+        recipient_token_balances(
+            client,
+            allocations,
+            &spl_token_args.mint,
+            spl_token_args.token_program,
+        )?
+    } else {
+        let addresses = parse_recipients(allocations).map_err(Error::InvalidAllocations)?;
+        let mut balances = Vec::with_capacity(addresses.len());
+        for addresses in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = client.get_multiple_accounts(addresses)?;
+            balances.extend(
+                accounts
+                    .into_iter()
+                    .map(|account| account.map(|account| account.lamports).unwrap_or_default()),
+            );
+        }
+        balances
+    };
+    Ok(allocations
+        .iter()
+        .zip(balances)
+        .map(|(allocation, actual)| Reconciliation {
+            recipient: allocation.recipient.clone(),
+            expected: allocation.amount,
+            actual,
+            status: ReconcileStatus::new(allocation.amount, actual),
+        })
+        .collect())
+}
+
+pub fn process_reconcile(client: &RpcClient, args: &ReconcileArgs) -> Result<(), Error> {
+    let allocations: Vec<Allocation> = load_allocations(
+        &args.input_csv,
+        args.input_format,
+        None,
+        false,
+        args.spl_token_args.is_some(),
+    )?;
+    if let Err(errors) = parse_recipients(&allocations) {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        return Err(Error::InvalidAllocations(errors));
+    }
+    let allocations = merge_allocations(&allocations);
+    let reconciliations =
+        reconcile_allocations(client, &allocations, args.spl_token_args.as_ref())?;
+
+    let to_token = |amount| match &args.spl_token_args {
+        Some(spl_token_args) => Token::spl_token(amount, spl_token_args.decimals),
+        None => Token::sol(amount),
+    };
+    let token = if let Some(spl_token_args) = &args.spl_token_args {
+        spl_token_args.mint.to_string()
+    } else {
+        "◎".to_string()
+    };
+    println!("{} {}", style("Token:").bold(), token);
+    println!(
+        "{}",
+        style(format!(
+            "{:<44}  {:>24}  {:>24}  {:>12}",
+            "Recipient", "Expected Balance", "Actual Balance", "Status"
+        ))
+        .bold()
+    );
+    for reconciliation in &reconciliations {
+        println!(
+            "{:<44}  {:>24}  {:>24}  {:>12}",
+            reconciliation.recipient,
+            to_token(reconciliation.expected).to_string(),
+            to_token(reconciliation.actual).to_string(),
+            format!("{:?}", reconciliation.status),
+        );
+    }
+
+    let statuses = [
+        ReconcileStatus::NotStarted,
+        ReconcileStatus::Partial,
+        ReconcileStatus::Complete,
+        ReconcileStatus::Overfunded,
+    ];
+    for status in statuses.iter() {
+        let count = reconciliations
+            .iter()
+            .filter(|reconciliation| reconciliation.status == *status)
+            .count();
+        println!("{}: {}", style(format!("{:?}", status)).bold(), count);
+    }
+    let expected = reconciliations
+        .iter()
+        .try_fold(0u64, |total, r| total.checked_add(r.expected))
+        .ok_or(Error::FundingOverflow("total expected balance"))?;
+    let actual = reconciliations
+        .iter()
+        .try_fold(0u64, |total, r| total.checked_add(r.actual))
+        .ok_or(Error::FundingOverflow("total actual balance"))?;
+    println!("{} {}", style("Total expected:").bold(), to_token(expected));
+    println!("{} {}", style("Total actual:").bold(), to_token(actual));
+
+    if let Some(output_path) = &args.output_path {
+        let mut wtr = csv::WriterBuilder::new().from_path(output_path)?;
+        for reconciliation in &reconciliations {
+            wtr.serialize(reconciliation)?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+/// A recipient whose allocation differs between two allocation files, see `diff_allocations()`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AmountChange {
+    pub recipient: String,
+    pub old_amount: u64,
+    pub new_amount: u64,
+}
+
+/// What changed between two allocation files, each merged to one allocation per recipient
+#[derive(Debug, Default, PartialEq)]
+pub struct AllocationDiff {
+    /// Allocations to recipients missing from the old file, in the order of the new file
+    pub added: Vec<Allocation>,
+    /// Allocations to recipients missing from the new file, in the order of the old file
+    pub removed: Vec<Allocation>,
+    /// Recipients in both files with a different amount, in the order of the new file
+    pub changed: Vec<AmountChange>,
+}
+
+impl AllocationDiff {
+    /// Allocations that bring recipients paid the old allocations up to the new ones: each added
+    /// allocation, and the increase of each changed one. Decreases and removals can't be clawed
+    /// back by a distribution, so they are left out.
+    pub fn top_up(&self) -> Vec<Allocation> {
+        let increases = self
+            .changed
+            .iter()
+            .filter(|change| change.new_amount > change.old_amount)
+            .map(|change| Allocation {
+                recipient: change.recipient.clone(),
+                amount: change.new_amount - change.old_amount,
+                lockup_date: "".to_string(),
+            });
+        self.added.iter().cloned().chain(increases).collect()
+    }
+}
+
+/// Compare the `old` allocations, already distributed, against the `new` ones. Allocations to the
+/// same recipient are summed first, as `reconcile_allocations()` does.
+pub fn diff_allocations(old: &[Allocation], new: &[Allocation]) -> AllocationDiff {
+    let old = merge_allocations(old);
+    let new = merge_allocations(new);
+    let old_amounts: HashMap<&str, u64> = old
+        .iter()
+        .map(|allocation| (allocation.recipient.as_str(), allocation.amount))
+        .collect();
+    let new_recipients: HashSet<&str> = new
+        .iter()
+        .map(|allocation| allocation.recipient.as_str())
+        .collect();
+
+    let mut diff = AllocationDiff::default();
+    for allocation in &new {
+        match old_amounts.get(allocation.recipient.as_str()) {
+            None => diff.added.push(allocation.clone()),
+            Some(&old_amount) if old_amount != allocation.amount => {
+                diff.changed.push(AmountChange {
+                    recipient: allocation.recipient.clone(),
+                    old_amount,
+                    new_amount: allocation.amount,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|allocation| !new_recipients.contains(allocation.recipient.as_str()))
+        .cloned()
+        .collect();
+    diff
+}
+
+pub fn process_diff_allocations(args: &DiffAllocationsArgs) -> Result<(), Error> {
+    let raw_amount = args.spl_token_args.is_some();
+    let old = load_allocations(
+        &args.old_input_csv,
+        args.input_format,
+        None,
+        false,
+        raw_amount,
+    )?;
+    let new = load_allocations(
+        &args.new_input_csv,
+        args.input_format,
+        None,
+        false,
+        raw_amount,
+    )?;
+    // Allocations are merged per recipient without their lockup date, so a top-up of stake
+    // allocations would lose it
+    for (input_csv, allocations) in
+        [(&args.old_input_csv, &old), (&args.new_input_csv, &new)].iter()
+    {
+        let errors: Vec<_> = allocations
+            .iter()
+            .enumerate()
+            .filter(|(_, allocation)| !allocation.lockup_date.is_empty())
+            .map(|(i, allocation)| {
+                AllocationError::UnsupportedLockupDate(i, allocation.lockup_date.clone())
+            })
+            .collect();
+        if !errors.is_empty() {
+            for error in &errors {
+                eprintln!("{}: {}", input_csv, error);
+            }
+            return Err(Error::InvalidAllocations(errors));
+        }
+    }
+    let diff = diff_allocations(&old, &new);
+
+    let to_token = |amount| match &args.spl_token_args {
+        Some(spl_token_args) => Token::spl_token(amount, spl_token_args.decimals),
+        None => Token::sol(amount),
+    };
+    println!(
+        "{}",
+        style(format!(
+            "{:<44}  {:>24}  {:>24}",
+            "Recipient", "Old Amount", "New Amount"
+        ))
+        .bold()
+    );
+    for allocation in &diff.added {
+        let line = format!(
+            "{:<44}  {:>24}  {:>24}",
+            allocation.recipient,
+            "",
+            to_token(allocation.amount).to_string(),
+        );
+        println!("{}", style(line).green());
+    }
+    for allocation in &diff.removed {
+        let line = format!(
+            "{:<44}  {:>24}  {:>24}",
+            allocation.recipient,
+            to_token(allocation.amount).to_string(),
+            "",
+        );
+        println!("{}", style(line).red());
+    }
+    for change in &diff.changed {
+        let line = format!(
+            "{:<44}  {:>24}  {:>24}",
+            change.recipient,
+            to_token(change.old_amount).to_string(),
+            to_token(change.new_amount).to_string(),
+        );
+        println!("{}", style(line).yellow());
+    }
+
+    let top_up = diff.top_up();
+    let top_up_amount: u64 = top_up.iter().map(|allocation| allocation.amount).sum();
+    println!("{} {}", style("Added:").bold(), diff.added.len());
+    println!("{} {}", style("Removed:").bold(), diff.removed.len());
+    println!("{} {}", style("Changed:").bold(), diff.changed.len());
+    println!("{} {}", style("Top-up:").bold(), to_token(top_up_amount));
+
+    if let Some(output_path) = &args.output_path {
+        // Written in the format the distribute commands read: token base units for SPL
+        // tokens, SAFE otherwise. SAFE amounts are written digit for digit from lamports, since
+        // formatting the `f64` of `lamports_to_sol()` can round.
+        let mut wtr = csv::WriterBuilder::new().from_path(output_path)?;
+        wtr.write_record(&["recipient", "amount"])?;
+        for allocation in &top_up {
+            let amount = if raw_amount {
+                allocation.amount.to_string()
+            } else {
+                base_units_to_ui(allocation.amount, 9)
+            };
+            wtr.write_record(&[&allocation.recipient, &amount])?;
+        }
+        wtr.flush()?;
+    }
     Ok(())
 }
 
@@ -874,8 +2371,17 @@ pub fn test_process_distribute_tokens_with_client(
     let args = DistributeTokensArgs {
         sender_keypair: Box::new(sender_keypair),
         fee_payer: Box::new(fee_payer),
+        rent_payer: None,
+        confirm_commitment: None,
+        isolate_failures: false,
+        merge_duplicates: false,
+        summary_path: None,
+        max_in_flight: 0,
+        max_resubmits: 0,
+        blockhash: None,
         dry_run: false,
         input_csv,
+        input_format: None,
         transaction_db: transaction_db.clone(),
         output_path: Some(output_path.clone()),
         stake_args: None,
@@ -982,8 +2488,17 @@ pub fn test_process_distribute_stake_with_client(client: &RpcClient, sender_keyp
     };
     let args = DistributeTokensArgs {
         fee_payer: Box::new(fee_payer),
+        rent_payer: None,
+        confirm_commitment: None,
+        isolate_failures: false,
+        merge_duplicates: false,
+        summary_path: None,
+        max_in_flight: 0,
+        max_resubmits: 0,
+        blockhash: None,
         dry_run: false,
         input_csv,
+        input_format: None,
         transaction_db: transaction_db.clone(),
         output_path: Some(output_path.clone()),
         stake_args: Some(stake_args),
@@ -1035,39 +2550,510 @@ pub fn test_process_distribute_stake_with_client(client: &RpcClient, sender_keyp
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::args::SafeTokenArgs;
     use solana_core::test_validator::TestValidator;
     use solana_sdk::signature::{read_keypair_file, write_keypair_file, Signer};
     use solana_stake_program::stake_instruction::StakeInstruction;
+    use std::{fs::OpenOptions, io::Write};
     use safecoin_transaction_status::TransactionConfirmationStatus;
 
     #[test]
-    fn test_process_token_allocations() {
+    fn test_process_token_allocations() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+        test_process_distribute_tokens_with_client(&client, alice, None);
+    }
+
+    #[test]
+    fn test_process_transfer_amount_allocations() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+        test_process_distribute_tokens_with_client(&client, alice, Some(sol_to_lamports(1.5)));
+    }
+
+    #[test]
+    fn test_process_balances_strict() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+
+        let funded_recipient = solana_sdk::pubkey::new_rand();
+        let unfunded_recipient = solana_sdk::pubkey::new_rand();
+        let transaction =
+            transfer(&client, sol_to_lamports(1.0), &alice, &funded_recipient).unwrap();
+        client.send_and_confirm_transaction(&transaction).unwrap();
+
+        let file = NamedTempFile::new().unwrap();
+        let input_csv = file.path().to_str().unwrap().to_string();
+        let mut wtr = csv::WriterBuilder::new().from_writer(file);
+        wtr.serialize(("recipient", "amount")).unwrap();
+        wtr.serialize((funded_recipient.to_string(), 1.0)).unwrap();
+        wtr.flush().unwrap();
+        let mut args = BalancesArgs {
+            input_csv,
+            input_format: None,
+            spl_token_args: None,
+            display_options: crate::args::DisplayOptions::default(),
+            strict: true,
+        };
+        process_balances(&client, &args).unwrap();
+
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(
+            OpenOptions::new()
+                .append(true)
+                .open(&args.input_csv)
+                .unwrap(),
+        );
+        wtr.serialize((unfunded_recipient.to_string(), 1.0))
+            .unwrap();
+        wtr.flush().unwrap();
+        assert!(matches!(
+            process_balances(&client, &args),
+            Err(Error::IncompleteDistribution(1))
+        ));
+
+        args.strict = false;
+        process_balances(&client, &args).unwrap();
+    }
+
+    #[test]
+    fn test_process_stake_allocations() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+        test_process_distribute_stake_with_client(&client, alice);
+    }
+
+    #[test]
+    fn test_confirm_signatures() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+        let exit = Arc::new(AtomicBool::default());
+
+        let alice_balance = client.get_balance(&alice.pubkey()).unwrap();
+        let blockhash = client.get_recent_blockhash().unwrap().0;
+        let send = |lamports| {
+            let transaction = Transaction::new_signed_with_payer(
+                &[system_instruction::transfer(
+                    &alice.pubkey(),
+                    &solana_sdk::pubkey::new_rand(),
+                    lamports,
+                )],
+                Some(&alice.pubkey()),
+                &[&alice],
+                blockhash,
+            );
+            let config = RpcSendTransactionConfig {
+                skip_preflight: true,
+                ..RpcSendTransactionConfig::default()
+            };
+            client
+                .send_transaction_with_config(&transaction, config)
+                .unwrap()
+        };
+        let transferred = send(sol_to_lamports(1.0));
+        let overdrawn = send(alice_balance * 2);
+
+        let confirmations = confirm_signatures(
+            &client,
+            &[transferred, overdrawn],
+            CommitmentConfig::confirmed(),
+            Duration::from_secs(60),
+            exit.clone(),
+        )
+        .unwrap();
+        assert_eq!(confirmations.confirmed, vec![transferred]);
+        assert_eq!(confirmations.failed.len(), 1);
+        assert_eq!(confirmations.failed[0].0, overdrawn);
+        assert!(confirmations.unconfirmed.is_empty());
+
+        // Signatures never sent time out
+        let unknown = Signature::new(&[1; 64]);
+        let confirmations = confirm_signatures(
+            &client,
+            &[unknown],
+            CommitmentConfig::confirmed(),
+            Duration::from_secs(1),
+            exit,
+        )
+        .unwrap();
+        assert!(confirmations.confirmed.is_empty());
+        assert!(confirmations.failed.is_empty());
+        assert_eq!(confirmations.unconfirmed, vec![unknown]);
+    }
+
+    #[test]
+    fn test_wait_for_in_flight_capacity() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+        let exit = AtomicBool::default();
+
+        let (blockhash, _fee_calculator, last_valid_slot) = client
+            .get_recent_blockhash_with_commitment(CommitmentConfig::default())
+            .unwrap()
+            .value;
+        let mut in_flight: Vec<_> = (1..=2)
+            .map(|lamports| {
+                let transaction = Transaction::new_signed_with_payer(
+                    &[system_instruction::transfer(
+                        &alice.pubkey(),
+                        &solana_sdk::pubkey::new_rand(),
+                        lamports,
+                    )],
+                    Some(&alice.pubkey()),
+                    &[&alice],
+                    blockhash,
+                );
+                (
+                    client.send_transaction(&transaction).unwrap(),
+                    last_valid_slot,
+                )
+            })
+            .collect();
+        let args = DistributeTokensArgs {
+            max_in_flight: 2,
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
+        };
+
+        // Returns once the confirmed transactions free up a slot
+        wait_for_in_flight_capacity(&client, &mut in_flight, &args, &exit).unwrap();
+        assert!(in_flight.len() < 2);
+
+        // Transactions whose blockhash expired no longer count
+        let mut in_flight = vec![(Signature::new(&[1; 64]), 0)];
+        let args = DistributeTokensArgs {
+            max_in_flight: 1,
+            ..args
+        };
+        wait_for_in_flight_capacity(&client, &mut in_flight, &args, &exit).unwrap();
+        assert!(in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_distribution_blockhash() {
+        let alice = Keypair::new();
+        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
+        let url = test_validator.rpc_url();
+        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
+        let dir = tempdir().unwrap();
+        let db_file = dir
+            .path()
+            .join("blockhash.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut db = db::open_db(&db_file, false).unwrap();
+
+        let blockhash = client.get_recent_blockhash().unwrap().0;
+        let args = DistributeTokensArgs {
+            blockhash: Some(blockhash),
+            transaction_db: db_file,
+            ..DistributeTokensArgs::for_test(
+                Box::new(Keypair::from_bytes(&alice.to_bytes()).unwrap()),
+                Box::new(alice),
+            )
+        };
+        let (distribution_blockhash, _fee_calculator, last_valid_slot) =
+            distribution_blockhash(&client, &args).unwrap();
+        assert_eq!(distribution_blockhash, blockhash);
+        assert!(last_valid_slot >= MAX_RECENT_BLOCKHASHES as u64);
+
+        // Transactions are signed with the given blockhash
+        let recipient = solana_sdk::pubkey::new_rand();
+        let allocation = Allocation {
+            recipient: recipient.to_string(),
+            amount: sol_to_lamports(1.0),
+            lockup_date: "".to_string(),
+        };
+        let message = Message::new(
+            &[system_instruction::transfer(
+                &args.sender_keypair.pubkey(),
+                &recipient,
+                allocation.amount,
+            )],
+            Some(&args.fee_payer.pubkey()),
+        );
+        send_messages(
+            &client,
+            &mut db,
+            &[allocation],
+            &args,
+            Arc::new(AtomicBool::new(false)),
+            vec![message],
+            vec![(Keypair::new(), None)],
+        )
+        .unwrap();
+        let transaction_infos = db::read_transaction_infos(&db);
+        assert_eq!(transaction_infos.len(), 1);
+        assert_eq!(
+            transaction_infos[0].transaction.message.recent_blockhash,
+            blockhash
+        );
+
+        // An unknown blockhash is an error
+        let args = DistributeTokensArgs {
+            blockhash: Some(Hash::new_unique()),
+            ..args
+        };
+        assert!(distribution_blockhash(&client, &args).is_err());
+    }
+
+    #[test]
+    fn test_resubmit_expired_transactions() {
         let alice = Keypair::new();
         let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
         let url = test_validator.rpc_url();
-
         let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
-        test_process_distribute_tokens_with_client(&client, alice, None);
+        let exit = AtomicBool::default();
+        let dir = tempdir().unwrap();
+        let db_file = dir.path().join("resubmit.db").to_str().unwrap().to_string();
+        let mut db = db::open_db(&db_file, false).unwrap();
+
+        // Record a transaction that was never sent, and whose blockhash has expired
+        let recipient = solana_sdk::pubkey::new_rand();
+        let message = Message::new(
+            &[system_instruction::transfer(
+                &alice.pubkey(),
+                &recipient,
+                sol_to_lamports(1.0),
+            )],
+            Some(&alice.pubkey()),
+        );
+        let blockhash = client.get_recent_blockhash().unwrap().0;
+        let expired_transaction = Transaction::new(&[&alice], message, blockhash);
+        db::set_transaction_info(
+            &mut db,
+            &recipient,
+            sol_to_lamports(1.0),
+            &expired_transaction,
+            None,
+            false,
+            0,
+            None,
+        )
+        .unwrap();
+        while client.get_slot().unwrap() == 0 {
+            sleep(Duration::from_millis(100));
+        }
+
+        let args = DistributeTokensArgs {
+            max_resubmits: 1,
+            transaction_db: db_file,
+            ..DistributeTokensArgs::for_test(
+                Box::new(Keypair::from_bytes(&alice.to_bytes()).unwrap()),
+                Box::new(alice),
+            )
+        };
+        resubmit_expired_transactions(&client, &mut db, &args, &HashSet::new(), &exit).unwrap();
+
+        // The expired transaction is replaced by its resubmission, which landed
+        let transaction_infos = db::read_transaction_infos(&db);
+        assert_eq!(transaction_infos.len(), 1);
+        let transaction_info = &transaction_infos[0];
+        assert_eq!(transaction_info.recipient, recipient);
+        assert_eq!(transaction_info.resubmits, 1);
+        assert_ne!(
+            transaction_info.transaction.signatures[0],
+            expired_transaction.signatures[0]
+        );
+        assert_eq!(
+            client.get_balance(&recipient).unwrap(),
+            sol_to_lamports(1.0)
+        );
     }
 
     #[test]
-    fn test_process_transfer_amount_allocations() {
+    fn test_reconcile_status() {
+        assert_eq!(ReconcileStatus::new(10, 0), ReconcileStatus::NotStarted);
+        assert_eq!(ReconcileStatus::new(10, 4), ReconcileStatus::Partial);
+        assert_eq!(ReconcileStatus::new(10, 10), ReconcileStatus::Complete);
+        assert_eq!(ReconcileStatus::new(10, 11), ReconcileStatus::Overfunded);
+        assert_eq!(ReconcileStatus::new(0, 0), ReconcileStatus::Complete);
+    }
+
+    #[test]
+    fn test_reconcile_allocations() {
         let alice = Keypair::new();
         let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
         let url = test_validator.rpc_url();
-
         let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
-        test_process_distribute_tokens_with_client(&client, alice, Some(sol_to_lamports(1.5)));
+
+        let expected = sol_to_lamports(1.0);
+        let funded = [0, expected / 2, expected, expected * 2];
+        let recipients: Vec<Pubkey> = funded
+            .iter()
+            .map(|_| solana_sdk::pubkey::new_rand())
+            .collect();
+        for (recipient, lamports) in recipients.iter().zip(&funded) {
+            if *lamports > 0 {
+                let transaction = Transaction::new_signed_with_payer(
+                    &[system_instruction::transfer(
+                        &alice.pubkey(),
+                        recipient,
+                        *lamports,
+                    )],
+                    Some(&alice.pubkey()),
+                    &[&alice],
+                    client.get_recent_blockhash().unwrap().0,
+                );
+                client.send_and_confirm_transaction(&transaction).unwrap();
+            }
+        }
+
+        let allocations: Vec<Allocation> = recipients
+            .iter()
+            .map(|recipient| Allocation {
+                recipient: recipient.to_string(),
+                amount: expected,
+                lockup_date: "".to_string(),
+            })
+            .collect();
+        let reconciliations = reconcile_allocations(&client, &allocations, None).unwrap();
+        assert_eq!(
+            reconciliations
+                .iter()
+                .map(|reconciliation| (reconciliation.actual, reconciliation.status))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, ReconcileStatus::NotStarted),
+                (expected / 2, ReconcileStatus::Partial),
+                (expected, ReconcileStatus::Complete),
+                (expected * 2, ReconcileStatus::Overfunded),
+            ]
+        );
+        for (reconciliation, allocation) in reconciliations.iter().zip(&allocations) {
+            assert_eq!(reconciliation.recipient, allocation.recipient);
+            assert_eq!(reconciliation.expected, allocation.amount);
+        }
+
+        let mut invalid_allocations = allocations.clone();
+        invalid_allocations[1].recipient = "bad".to_string();
+        assert!(matches!(
+            reconcile_allocations(&client, &invalid_allocations, None),
+            Err(Error::InvalidAllocations(errors))
+                if errors == vec![AllocationError::InvalidRecipient(1, "bad".to_string())]
+        ));
     }
 
     #[test]
-    fn test_process_stake_allocations() {
-        let alice = Keypair::new();
-        let test_validator = TestValidator::with_no_fees(alice.pubkey(), None);
-        let url = test_validator.rpc_url();
+    fn test_diff_allocations() {
+        let allocation = |recipient: &Pubkey, amount: u64| Allocation {
+            recipient: recipient.to_string(),
+            amount,
+            lockup_date: "".to_string(),
+        };
+        let alice = solana_sdk::pubkey::new_rand();
+        let bob = solana_sdk::pubkey::new_rand();
+        let carol = solana_sdk::pubkey::new_rand();
+        let dave = solana_sdk::pubkey::new_rand();
+        let eve = solana_sdk::pubkey::new_rand();
+        let old = vec![
+            allocation(&alice, 10),
+            allocation(&bob, 20),
+            allocation(&carol, 30),
+            allocation(&dave, 40),
+        ];
+        let new = vec![
+            allocation(&eve, 50),
+            allocation(&alice, 10),
+            // Allocations to the same recipient are summed
+            allocation(&bob, 15),
+            allocation(&bob, 10),
+            allocation(&carol, 5),
+        ];
 
-        let client = RpcClient::new_with_commitment(url, CommitmentConfig::processed());
-        test_process_distribute_stake_with_client(&client, alice);
+        let diff = diff_allocations(&old, &new);
+        assert_eq!(
+            diff,
+            AllocationDiff {
+                added: vec![allocation(&eve, 50)],
+                removed: vec![allocation(&dave, 40)],
+                changed: vec![
+                    AmountChange {
+                        recipient: bob.to_string(),
+                        old_amount: 20,
+                        new_amount: 25,
+                    },
+                    AmountChange {
+                        recipient: carol.to_string(),
+                        old_amount: 30,
+                        new_amount: 5,
+                    },
+                ],
+            }
+        );
+        // Decreases and removals are not topped up
+        assert_eq!(
+            diff.top_up(),
+            vec![allocation(&eve, 50), allocation(&bob, 5)]
+        );
+
+        assert_eq!(diff_allocations(&old, &old), AllocationDiff::default());
+    }
+
+    #[test]
+    fn test_process_diff_allocations() {
+        let alice = solana_sdk::pubkey::new_rand();
+        let bob = solana_sdk::pubkey::new_rand();
+        let ndjson_file = || {
+            tempfile::Builder::new()
+                .suffix(".ndjson")
+                .tempfile()
+                .unwrap()
+        };
+        let mut old_file = ndjson_file();
+        writeln!(old_file, r#"{{"recipient":"{}","amount":1}}"#, alice).unwrap();
+        old_file.flush().unwrap();
+        let mut new_file = ndjson_file();
+        writeln!(new_file, r#"{{"recipient":"{}","amount":2.5}}"#, alice).unwrap();
+        writeln!(new_file, r#"{{"recipient":"{}","amount":0.25}}"#, bob).unwrap();
+        new_file.flush().unwrap();
+        let output_file = NamedTempFile::new().unwrap();
+        let mut args = DiffAllocationsArgs {
+            old_input_csv: old_file.path().to_str().unwrap().to_string(),
+            new_input_csv: new_file.path().to_str().unwrap().to_string(),
+            input_format: None,
+            spl_token_args: None,
+            output_path: Some(output_file.path().to_str().unwrap().to_string()),
+        };
+
+        process_diff_allocations(&args).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output_file.path()).unwrap(),
+            format!("recipient,amount\n{},0.25\n{},1.5\n", bob, alice)
+        );
+
+        // Stake allocations would lose their lockup date in the top-up
+        let mut stake_file = ndjson_file();
+        writeln!(
+            stake_file,
+            r#"{{"recipient":"{}","amount":2.5,"lockup_date":"2021-01-07T00:00:00Z"}}"#,
+            alice
+        )
+        .unwrap();
+        stake_file.flush().unwrap();
+        args.new_input_csv = stake_file.path().to_str().unwrap().to_string();
+        assert!(matches!(
+            process_diff_allocations(&args),
+            Err(Error::InvalidAllocations(errors))
+                if errors == vec![AllocationError::UnsupportedLockupDate(
+                    0,
+                    "2021-01-07T00:00:00Z".to_string()
+                )]
+        ));
     }
 
     #[test]
@@ -1211,6 +3197,320 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_allocations_ndjson() {
+        let pubkey0 = solana_sdk::pubkey::new_rand();
+        let pubkey1 = solana_sdk::pubkey::new_rand();
+        let mut file = tempfile::Builder::new()
+            .suffix(".ndjson")
+            .tempfile()
+            .unwrap();
+        let input_path = file.path().to_str().unwrap().to_string();
+        writeln!(file, r#"{{"recipient":"{}","amount":42}}"#, pubkey0).unwrap();
+        writeln!(file).unwrap();
+        writeln!(
+            file,
+            r#"{{"recipient":"{}","amount":43,"lockup_date":"2021-01-07T00:00:00Z"}}"#,
+            pubkey1
+        )
+        .unwrap();
+        file.flush().unwrap();
+
+        let expected_allocations = vec![
+            Allocation {
+                recipient: pubkey0.to_string(),
+                amount: sol_to_lamports(42.0),
+                lockup_date: "".to_string(),
+            },
+            Allocation {
+                recipient: pubkey1.to_string(),
+                amount: sol_to_lamports(43.0),
+                lockup_date: "2021-01-07T00:00:00Z".to_string(),
+            },
+        ];
+        assert_eq!(
+            load_allocations(&input_path, None, None, false, false).unwrap(),
+            expected_allocations
+        );
+        assert_eq!(
+            load_allocations(&input_path, Some(InputFormat::Ndjson), None, false, true)
+                .unwrap()
+                .iter()
+                .map(|allocation| allocation.amount)
+                .collect::<Vec<_>>(),
+            vec![42, 43]
+        );
+        assert_eq!(
+            load_allocations(&input_path, None, Some(7), false, false)
+                .unwrap()
+                .iter()
+                .map(|allocation| allocation.amount)
+                .collect::<Vec<_>>(),
+            vec![7, 7]
+        );
+    }
+
+    #[test]
+    fn test_read_allocations_zero_decimal_mint() {
+        let pubkey0 = solana_sdk::pubkey::new_rand();
+        let pubkey1 = solana_sdk::pubkey::new_rand();
+        let write_csv = |amount1: &str| {
+            let file = NamedTempFile::new().unwrap();
+            let mut wtr = csv::WriterBuilder::new().from_writer(file.reopen().unwrap());
+            wtr.serialize(("recipient", "amount")).unwrap();
+            wtr.serialize((pubkey0.to_string(), "1")).unwrap();
+            wtr.serialize((pubkey1.to_string(), amount1)).unwrap();
+            wtr.flush().unwrap();
+            file
+        };
+
+        // Whole quantities of a 0-decimal mint are read exactly, even beyond f64 precision
+        let file = write_csv("9007199254740993");
+        let input_csv = file.path().to_str().unwrap();
+        let allocations = load_allocations(input_csv, None, None, false, true).unwrap();
+        assert_eq!(
+            allocations
+                .iter()
+                .map(|allocation| allocation.amount)
+                .collect::<Vec<_>>(),
+            vec![1, 9_007_199_254_740_993]
+        );
+        assert_eq!(
+            Token::spl_token(allocations.iter().map(|x| x.amount).sum(), 0).to_string(),
+            "9007199254740994 tokens"
+        );
+
+        let file = write_csv("1.5");
+        let input_csv = file.path().to_str().unwrap();
+        let err = load_allocations(input_csv, None, None, false, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&pubkey1.to_string()));
+        assert!(err.to_string().contains("whole base units"));
+
+        let mut file = tempfile::Builder::new()
+            .suffix(".ndjson")
+            .tempfile()
+            .unwrap();
+        writeln!(file, r#"{{"recipient":"{}","amount":1.5}}"#, pubkey0).unwrap();
+        file.flush().unwrap();
+        let input_path = file.path().to_str().unwrap();
+        let err = load_allocations(input_path, None, None, false, true).unwrap_err();
+        assert!(err.to_string().contains("whole base units"));
+    }
+
+    #[test]
+    fn test_compute_funding_requirements() {
+        let allocations = vec![
+            Allocation {
+                recipient: solana_sdk::pubkey::new_rand().to_string(),
+                amount: sol_to_lamports(2.0),
+                lockup_date: "".to_string(),
+            },
+            Allocation {
+                recipient: solana_sdk::pubkey::new_rand().to_string(),
+                amount: sol_to_lamports(3.0),
+                lockup_date: "".to_string(),
+            },
+        ];
+        let mut args = DistributeTokensArgs {
+            dry_run: true,
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
+        };
+
+        let requirements = compute_funding_requirements(&allocations, &args, 10, 0).unwrap();
+        assert_eq!(requirements.total_tokens, sol_to_lamports(5.0));
+        assert_eq!(requirements.total_lamports_fees, 10);
+        assert_eq!(requirements.required(&FundingSource::FeePayer), 10);
+        assert_eq!(
+            requirements.required(&FundingSource::SystemAccount),
+            sol_to_lamports(5.0)
+        );
+        assert_eq!(requirements.required(&FundingSource::StakeAccount), 0);
+
+        args.stake_args = Some(StakeArgs {
+            stake_account_address: Pubkey::default(),
+            stake_authority: Box::new(Keypair::new()),
+            withdraw_authority: Box::new(Keypair::new()),
+            lockup_authority: None,
+            unlocked_sol: sol_to_lamports(1.0),
+        });
+        let requirements = compute_funding_requirements(&allocations, &args, 10, 0).unwrap();
+        assert_eq!(
+            requirements.required(&FundingSource::StakeAccount),
+            sol_to_lamports(3.0)
+        );
+        assert_eq!(
+            requirements.required(&FundingSource::SystemAccount),
+            sol_to_lamports(2.0)
+        );
+
+        args.stake_args = None;
+        args.spl_token_args = Some(SafeTokenArgs::default());
+        let requirements = compute_funding_requirements(&allocations, &args, 10, 7).unwrap();
+        assert_eq!(requirements.total_rent, 7);
+        assert_eq!(requirements.required(&FundingSource::FeePayer), 17);
+        assert_eq!(
+            requirements.required(&FundingSource::SafeTokenAccount),
+            sol_to_lamports(5.0)
+        );
+        assert_eq!(requirements.required(&FundingSource::SystemAccount), 0);
+
+        args.rent_payer = Some(Box::new(Keypair::new()));
+        let requirements = compute_funding_requirements(&allocations, &args, 10, 7).unwrap();
+        assert_eq!(requirements.required(&FundingSource::FeePayer), 10);
+        assert_eq!(requirements.required(&FundingSource::RentPayer), 7);
+
+        // Requirements that don't fit in a u64 are errors, not wrapped
+        args.rent_payer = None;
+        assert!(matches!(
+            compute_funding_requirements(&allocations, &args, u64::MAX, 7),
+            Err(Error::FundingOverflow(_))
+        ));
+        let mut large_allocations = allocations.clone();
+        large_allocations[0].amount = u64::MAX;
+        assert!(matches!(
+            compute_funding_requirements(&large_allocations, &args, 10, 7),
+            Err(Error::FundingOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn test_pack_instructions() {
+        let fee_payer = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let instruction =
+            |data_len| Instruction::new_with_bytes(program_id, &vec![0; data_len], vec![]);
+        let size = |instructions: &[Instruction]| {
+            let message = Message::new(instructions, Some(&fee_payer));
+            let transaction = Transaction::new_unsigned(message.clone());
+            let size = bincode::serialized_size(&transaction).unwrap() as usize;
+            assert_eq!(signed_transaction_size(&message), size);
+            size
+        };
+
+        assert!(pack_instructions(vec![], &fee_payer, PACKET_DATA_SIZE).is_empty());
+
+        let instructions = vec![instruction(100), instruction(200), instruction(300)];
+        let full_size = size(&instructions);
+        assert_eq!(
+            pack_instructions(instructions.clone(), &fee_payer, full_size),
+            vec![instructions.clone()]
+        );
+
+        // One byte short of fitting everything, the last instruction moves to a new transaction
+        assert_eq!(
+            pack_instructions(instructions.clone(), &fee_payer, full_size - 1),
+            vec![instructions[..2].to_vec(), instructions[2..].to_vec()]
+        );
+
+        // Exactly full with the first two
+        let two_size = size(&instructions[..2]);
+        assert_eq!(
+            pack_instructions(instructions.clone(), &fee_payer, two_size),
+            vec![instructions[..2].to_vec(), instructions[2..].to_vec()]
+        );
+        assert_eq!(
+            pack_instructions(instructions.clone(), &fee_payer, two_size - 1),
+            vec![
+                instructions[..1].to_vec(),
+                instructions[1..2].to_vec(),
+                instructions[2..].to_vec()
+            ]
+        );
+
+        // Oversized instructions get a transaction of their own
+        let instructions = vec![instruction(10), instruction(2_000), instruction(10)];
+        assert_eq!(
+            pack_instructions(instructions.clone(), &fee_payer, PACKET_DATA_SIZE),
+            vec![
+                instructions[..1].to_vec(),
+                instructions[1..2].to_vec(),
+                instructions[2..].to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_offline_bundle() {
+        let recent_blockhash = Hash::new_unique();
+        let allocations: Vec<_> = (0..30)
+            .map(|_| Allocation {
+                recipient: Pubkey::new_unique().to_string(),
+                amount: 1,
+                lockup_date: "".to_string(),
+            })
+            .collect();
+        let mut args =
+            DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()));
+
+        let messages =
+            build_offline_bundle(&allocations, &args, recent_blockhash, &HashSet::new()).unwrap();
+        assert!(messages.len() > 1);
+        let mut num_transfers = 0;
+        for message in &messages {
+            assert_eq!(message.recent_blockhash, recent_blockhash);
+            assert_eq!(message.account_keys[0], args.fee_payer.pubkey());
+            assert!(signed_transaction_size(message) <= PACKET_DATA_SIZE);
+            num_transfers += message.instructions.len();
+        }
+        assert_eq!(num_transfers, allocations.len());
+
+        // An associated token account is only created for recipients without one
+        let mint = Pubkey::new_unique();
+        args.spl_token_args = Some(SafeTokenArgs {
+            token_account_address: Pubkey::new_unique(),
+            mint,
+            decimals: 2,
+            ..SafeTokenArgs::default()
+        });
+        let existing_associated_token_addresses: HashSet<_> = allocations[..10]
+            .iter()
+            .map(|allocation| recipient_token_address(allocation, &mint, TokenProgram::Legacy))
+            .collect();
+        let messages = build_offline_bundle(
+            &allocations,
+            &args,
+            recent_blockhash,
+            &existing_associated_token_addresses,
+        )
+        .unwrap();
+        let num_created: u64 = messages
+            .iter()
+            .map(|message| {
+                created_associated_token_accounts(
+                    &Transaction::new_unsigned(message.clone()),
+                    TokenProgram::Legacy,
+                )
+            })
+            .sum();
+        assert_eq!(num_created, 20);
+
+        let mut invalid_allocations = allocations.clone();
+        invalid_allocations[3].recipient = "bad".to_string();
+        assert!(matches!(
+            build_offline_bundle(
+                &invalid_allocations,
+                &args,
+                recent_blockhash,
+                &HashSet::new()
+            ),
+            Err(Error::InvalidAllocations(_))
+        ));
+
+        args.spl_token_args = None;
+        args.stake_args = Some(StakeArgs {
+            stake_account_address: Pubkey::new_unique(),
+            stake_authority: Box::new(Keypair::new()),
+            withdraw_authority: Box::new(Keypair::new()),
+            lockup_authority: None,
+            unlocked_sol: 0,
+        });
+        assert!(matches!(
+            build_offline_bundle(&allocations, &args, recent_blockhash, &HashSet::new()),
+            Err(Error::UnsupportedOfflineDistribution(_))
+        ));
+    }
+
     #[test]
     fn test_apply_previous_transactions() {
         let alice = solana_sdk::pubkey::new_rand();
@@ -1240,6 +3540,96 @@ mod tests {
         assert_eq!(allocations[0].recipient, alice.to_string());
     }
 
+    #[test]
+    fn test_allocation_outcomes() {
+        let alice = solana_sdk::pubkey::new_rand();
+        let bob = solana_sdk::pubkey::new_rand();
+        let carol = solana_sdk::pubkey::new_rand();
+        let allocation = |recipient: &Pubkey| Allocation {
+            recipient: recipient.to_string(),
+            amount: sol_to_lamports(1.0),
+            lockup_date: "".to_string(),
+        };
+        let allocations = vec![allocation(&alice), allocation(&bob), allocation(&carol)];
+        let signature = Signature::new(&[1; 64]);
+        let transaction_infos = vec![TransactionInfo {
+            recipient: alice,
+            amount: sol_to_lamports(1.0),
+            finalized_date: Some(Utc::now()),
+            transaction: Transaction {
+                signatures: vec![signature],
+                ..Transaction::default()
+            },
+            ..TransactionInfo::default()
+        }];
+        let failed_allocations = vec![FailedAllocation {
+            recipient: bob.to_string(),
+            amount: sol_to_lamports(1.0),
+            error: "blockhash not found".to_string(),
+        }];
+
+        let outcomes = allocation_outcomes(allocations, &transaction_infos, &failed_allocations);
+        assert_eq!(
+            outcomes,
+            vec![
+                AllocationOutcome {
+                    allocation: allocation(&alice),
+                    status: AllocationStatus::Finalized,
+                    signatures: vec![signature],
+                },
+                AllocationOutcome {
+                    allocation: allocation(&bob),
+                    status: AllocationStatus::Failed("blockhash not found".to_string()),
+                    signatures: vec![],
+                },
+                AllocationOutcome {
+                    allocation: allocation(&carol),
+                    status: AllocationStatus::Pending,
+                    signatures: vec![],
+                },
+            ]
+        );
+        let report = DistributionReport {
+            confirmations: None,
+            allocations: outcomes,
+        };
+        assert_eq!(report.failed().count(), 1);
+    }
+
+    #[test]
+    fn test_messages_fee() {
+        let payer = Pubkey::new_unique();
+        let recipient = Pubkey::new_unique();
+        let transfer = Message::new(
+            &[system_instruction::transfer(&payer, &recipient, 1)],
+            Some(&payer),
+        );
+        // Signatures verified by the secp256k1 program are charged like transaction signatures
+        let secp256k1 = Message::new(
+            &[Instruction::new_with_bytes(
+                solana_sdk::secp256k1_program::id(),
+                &[2],
+                vec![],
+            )],
+            Some(&payer),
+        );
+        let fee_calculator = FeeCalculator::new(10);
+
+        assert_eq!(messages_fee(&fee_calculator, &[]).unwrap(), 0);
+        assert_eq!(
+            messages_fee(&fee_calculator, &[transfer.clone()]).unwrap(),
+            10
+        );
+        assert_eq!(
+            messages_fee(&fee_calculator, &[transfer.clone(), secp256k1]).unwrap(),
+            40
+        );
+        assert!(matches!(
+            messages_fee(&FeeCalculator::new(u64::MAX), &[transfer.clone(), transfer]),
+            Err(Error::FundingOverflow(_))
+        ));
+    }
+
     #[test]
     fn test_has_same_recipient() {
         let alice_pubkey = solana_sdk::pubkey::new_rand();
@@ -1289,6 +3679,89 @@ mod tests {
         )); // Same recipient, same lockups
     }
 
+    #[test]
+    fn test_validate_allocations() {
+        let alice = solana_sdk::pubkey::new_rand().to_string();
+        let bob = solana_sdk::pubkey::new_rand().to_string();
+        let lockup_date = "2021-01-07T00:00:00Z".to_string();
+        let mut allocations = vec![
+            Allocation {
+                recipient: alice.clone(),
+                amount: 1,
+                lockup_date: "".to_string(),
+            },
+            Allocation {
+                recipient: alice.clone(),
+                amount: 1,
+                lockup_date: lockup_date.clone(),
+            },
+            Allocation {
+                recipient: bob.clone(),
+                amount: 1,
+                lockup_date: "".to_string(),
+            },
+        ];
+        assert_eq!(validate_allocations(&allocations), Ok(()));
+
+        allocations.push(Allocation {
+            recipient: "not a pubkey".to_string(),
+            amount: 0,
+            lockup_date: "not a date".to_string(),
+        });
+        allocations.push(Allocation {
+            recipient: alice.clone(),
+            amount: 2,
+            lockup_date,
+        });
+        assert_eq!(
+            validate_allocations(&allocations),
+            Err(vec![
+                AllocationError::InvalidRecipient(3, "not a pubkey".to_string()),
+                AllocationError::ZeroAmount(3),
+                AllocationError::InvalidLockupDate(3, "not a date".to_string()),
+                AllocationError::DuplicateRecipient(4, 1, alice),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_duplicate_allocations() {
+        let alice = solana_sdk::pubkey::new_rand().to_string();
+        let bob = solana_sdk::pubkey::new_rand().to_string();
+        let lockup_date = "2021-01-07T00:00:00Z".to_string();
+        let allocation = |recipient: &str, amount: u64, lockup_date: &str| Allocation {
+            recipient: recipient.to_string(),
+            amount,
+            lockup_date: lockup_date.to_string(),
+        };
+        let allocations = vec![
+            allocation(&alice, 1, ""),
+            allocation(&bob, 2, ""),
+            allocation(&alice, 3, ""),
+            allocation(&alice, 4, &lockup_date),
+        ];
+        let merged = merge_duplicate_allocations(allocations).unwrap();
+        assert_eq!(
+            merged,
+            vec![
+                allocation(&alice, 4, ""),
+                allocation(&bob, 2, ""),
+                allocation(&alice, 4, &lockup_date),
+            ]
+        );
+        assert_eq!(validate_allocations(&merged), Ok(()));
+
+        let allocations = vec![
+            allocation(&alice, 1, ""),
+            allocation(&bob, 2, ""),
+            allocation(&alice, u64::MAX, ""),
+        ];
+        assert_eq!(
+            merge_duplicate_allocations(allocations),
+            Err(AllocationError::MergedAmountOverflow(2, alice))
+        );
+    }
+
     const SET_LOCKUP_INDEX: usize = 5;
 
     #[test]
@@ -1310,15 +3783,8 @@ mod tests {
             unlocked_sol: sol_to_lamports(1.0),
         };
         let args = DistributeTokensArgs {
-            fee_payer: Box::new(Keypair::new()),
-            dry_run: false,
-            input_csv: "".to_string(),
-            transaction_db: "".to_string(),
-            output_path: None,
             stake_args: Some(stake_args),
-            spl_token_args: None,
-            sender_keypair: Box::new(Keypair::new()),
-            transfer_amount: None,
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
         };
         let lockup_date = lockup_date_str.parse().unwrap();
         let instructions = distribution_instructions(
@@ -1359,15 +3825,11 @@ mod tests {
             lockup_date: "".to_string(),
         }];
         let args = DistributeTokensArgs {
-            sender_keypair: read_keypair_file(sender_keypair_file).unwrap().into(),
-            fee_payer: read_keypair_file(fee_payer).unwrap().into(),
-            dry_run: false,
-            input_csv: "".to_string(),
-            transaction_db: "".to_string(),
-            output_path: None,
             stake_args,
-            spl_token_args: None,
-            transfer_amount: None,
+            ..DistributeTokensArgs::for_test(
+                read_keypair_file(sender_keypair_file).unwrap().into(),
+                read_keypair_file(fee_payer).unwrap().into(),
+            )
         };
         (allocations, args)
     }
@@ -1789,15 +4251,8 @@ mod tests {
 
         // This is just dummy data; Args will not affect messages built
         let args = DistributeTokensArgs {
-            sender_keypair: Box::new(Keypair::new()),
-            fee_payer: Box::new(Keypair::new()),
             dry_run: true,
-            input_csv: "".to_string(),
-            transaction_db: "".to_string(),
-            output_path: None,
-            stake_args: None,
-            spl_token_args: None,
-            transfer_amount: None,
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
         };
         let allocation = Allocation {
             recipient: recipient.to_string(),
@@ -1869,6 +4324,8 @@ mod tests {
                 transaction,
                 last_valid_slot,
                 lockup_date: None,
+                resubmits: 0,
+                previous_signatures: vec![],
             }
         );
         assert_eq!(messages.len(), 0);
@@ -1911,15 +4368,8 @@ mod tests {
 
         // This is just dummy data; Args will not affect messages
         let args = DistributeTokensArgs {
-            sender_keypair: Box::new(Keypair::new()),
-            fee_payer: Box::new(Keypair::new()),
             dry_run: true,
-            input_csv: "".to_string(),
-            transaction_db: "".to_string(),
-            output_path: None,
-            stake_args: None,
-            spl_token_args: None,
-            transfer_amount: None,
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
         };
         let allocation = Allocation {
             recipient: recipient.to_string(),
@@ -1973,6 +4423,8 @@ mod tests {
             transaction,
             last_valid_slot,
             lockup_date: None,
+            resubmits: 0,
+            previous_signatures: vec![],
         }));
         assert!(transaction_info.contains(&TransactionInfo {
             recipient,
@@ -1982,6 +4434,8 @@ mod tests {
             transaction: Transaction::new_unsigned(message),
             last_valid_slot: std::u64::MAX,
             lockup_date: None,
+            resubmits: 0,
+            previous_signatures: vec![],
         }));
 
         // Next dump should write record written in last send_messages call
@@ -2027,15 +4481,8 @@ mod tests {
         };
         // This is just dummy data; Args will not affect messages
         let args = DistributeTokensArgs {
-            sender_keypair: Box::new(sender_keypair),
-            fee_payer: Box::new(fee_payer),
             dry_run: true,
-            input_csv: "".to_string(),
-            transaction_db: "".to_string(),
-            output_path: None,
-            stake_args: None,
-            spl_token_args: None,
-            transfer_amount: None,
+            ..DistributeTokensArgs::for_test(Box::new(sender_keypair), Box::new(fee_payer))
         };
 
         let exit = Arc::new(AtomicBool::new(false));
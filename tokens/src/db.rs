@@ -3,7 +3,11 @@ use pickledb::{error::Error, PickleDb, PickleDbDumpPolicy};
 use serde::{Deserialize, Serialize};
 use solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature, transaction::Transaction};
 use safecoin_transaction_status::TransactionStatus;
-use std::{cmp::Ordering, fs, io, path::Path};
+use std::{
+    cmp::Ordering,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TransactionInfo {
@@ -14,6 +18,14 @@ pub struct TransactionInfo {
     pub transaction: Transaction,
     pub last_valid_slot: Slot,
     pub lockup_date: Option<DateTime<Utc>>,
+    /// Number of times the transaction was re-signed with a fresh blockhash after its blockhash
+    /// expired, see `DistributeTokensArgs::max_resubmits`
+    #[serde(default)]
+    pub resubmits: usize,
+    /// Signatures of the expired transactions that this one was resubmitted in place of, oldest
+    /// first
+    #[serde(default)]
+    pub previous_signatures: Vec<Signature>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
@@ -24,6 +36,35 @@ struct SignedTransactionInfo {
     new_stake_account_address: String,
     finalized_date: Option<DateTime<Utc>>,
     signature: String,
+    #[serde(default)]
+    resubmits: usize,
+}
+
+/// An allocation whose transaction could not be sent, see `DistributeTokensArgs::isolate_failures`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailedAllocation {
+    pub recipient: String,
+    pub amount: u64,
+    pub error: String,
+}
+
+/// Outcome of a distribution, taken from its transaction log so that it covers earlier runs
+/// of a resumed distribution, see `write_distribution_summary()`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct DistributionSummary {
+    pub total_recipients: usize,
+    /// Transactions finalized on-chain
+    pub succeeded: usize,
+    /// Transactions sent but not yet finalized
+    pub pending: usize,
+    pub failed: Vec<FailedAllocation>,
+    /// Lamports, or token base units, moved by the succeeded transactions
+    pub total_amount: u64,
+    /// Lamports paid in fees for the logged transactions, at the current fee rate
+    pub total_fees: u64,
+    /// Lamports paid to fund the rent of created accounts
+    pub total_rent: u64,
+    pub elapsed_secs: f64,
 }
 
 impl Default for TransactionInfo {
@@ -40,6 +81,8 @@ impl Default for TransactionInfo {
             transaction,
             last_valid_slot: 0,
             lockup_date: None,
+            resubmits: 0,
+            previous_signatures: vec![],
         }
     }
 }
@@ -89,12 +132,39 @@ pub fn write_transaction_log<P: AsRef<Path>>(db: &PickleDb, path: &P) -> Result<
                 .unwrap_or_else(|| "".to_string()),
             finalized_date: info.finalized_date,
             signature: info.transaction.signatures[0].to_string(),
+            resubmits: info.resubmits,
         };
         wtr.serialize(&signed_info)?;
     }
     wtr.flush()
 }
 
+/// Where the failed allocations of a distribution logged to `output_path` are written:
+/// `output_path` with a `failed.csv` extension
+pub fn failed_allocations_path(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("failed.csv")
+}
+
+pub fn write_failed_allocations<P: AsRef<Path>>(
+    failed_allocations: &[FailedAllocation],
+    path: &P,
+) -> Result<(), io::Error> {
+    let mut wtr = csv::WriterBuilder::new().from_path(path)?;
+    for failed_allocation in failed_allocations {
+        wtr.serialize(failed_allocation)?;
+    }
+    wtr.flush()
+}
+
+pub fn write_distribution_summary<P: AsRef<Path>>(
+    path: &P,
+    summary: &DistributionSummary,
+) -> Result<(), io::Error> {
+    let file = fs::File::create(path)?;
+    serde_json::to_writer_pretty(file, summary)?;
+    Ok(())
+}
+
 pub fn read_transaction_infos(db: &PickleDb) -> Vec<TransactionInfo> {
     db.iter()
         .map(|kv| kv.get_value::<TransactionInfo>().unwrap())
@@ -120,12 +190,37 @@ pub fn set_transaction_info(
         transaction: transaction.clone(),
         last_valid_slot,
         lockup_date,
+        resubmits: 0,
+        previous_signatures: vec![],
     };
     let signature = transaction.signatures[0];
     db.set(&signature.to_string(), &transaction_info)?;
     Ok(())
 }
 
+// Replace the record of a transaction whose blockhash expired with that of `transaction`, the same
+// message re-signed with a fresh blockhash, counting the resubmission. The expired signature is
+// kept in `previous_signatures`, so every attempt of the allocation stays traceable.
+pub fn set_resubmitted_transaction_info(
+    db: &mut PickleDb,
+    expired_info: &TransactionInfo,
+    transaction: &Transaction,
+    last_valid_slot: Slot,
+) -> Result<(), Error> {
+    let mut previous_signatures = expired_info.previous_signatures.clone();
+    previous_signatures.push(expired_info.transaction.signatures[0]);
+    let transaction_info = TransactionInfo {
+        transaction: transaction.clone(),
+        last_valid_slot,
+        resubmits: expired_info.resubmits + 1,
+        previous_signatures,
+        ..expired_info.clone()
+    };
+    db.rem(&expired_info.transaction.signatures[0].to_string())?;
+    db.set(&transaction.signatures[0].to_string(), &transaction_info)?;
+    Ok(())
+}
+
 // Set the finalized bit in the database if the transaction is rooted.
 // Remove the TransactionInfo from the database if the transaction failed.
 // Return the number of confirmations on the transaction or None if either
@@ -201,6 +296,7 @@ pub(crate) fn check_output_file(path: &str, db: &PickleDb) {
                 .unwrap_or_else(|| "".to_string()),
             finalized_date: info.finalized_date,
             signature: info.transaction.signatures[0].to_string(),
+            resubmits: info.resubmits,
         })
         .collect();
     assert_eq!(logged_infos, transaction_infos);
@@ -263,6 +359,51 @@ mod tests {
         assert_eq!(signed_infos, vec![signed_info]);
     }
 
+    #[test]
+    fn test_write_failed_allocations() {
+        assert_eq!(
+            failed_allocations_path("/tmp/log.csv"),
+            PathBuf::from("/tmp/log.failed.csv")
+        );
+
+        let failed_allocations = vec![FailedAllocation {
+            recipient: Pubkey::default().to_string(),
+            amount: 42,
+            error: "Missing lockup authority".to_string(),
+        }];
+        let csv_file = NamedTempFile::new().unwrap();
+        write_failed_allocations(&failed_allocations, &csv_file).unwrap();
+
+        let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(csv_file);
+        let read_allocations: Vec<FailedAllocation> =
+            rdr.deserialize().map(|entry| entry.unwrap()).collect();
+        assert_eq!(read_allocations, failed_allocations);
+    }
+
+    #[test]
+    fn test_write_distribution_summary() {
+        let summary = DistributionSummary {
+            total_recipients: 3,
+            succeeded: 1,
+            pending: 1,
+            failed: vec![FailedAllocation {
+                recipient: Pubkey::default().to_string(),
+                amount: 42,
+                error: "Missing lockup authority".to_string(),
+            }],
+            total_amount: 100,
+            total_fees: 5_000,
+            total_rent: 0,
+            elapsed_secs: 1.5,
+        };
+        let json_file = NamedTempFile::new().unwrap();
+        write_distribution_summary(&json_file, &summary).unwrap();
+
+        let read_summary: DistributionSummary =
+            serde_json::from_reader(fs::File::open(json_file.path()).unwrap()).unwrap();
+        assert_eq!(read_summary, summary);
+    }
+
     #[test]
     fn test_update_finalized_transaction_not_landed() {
         // Keep waiting for a transaction that hasn't landed yet.
@@ -295,6 +436,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_resubmitted_transaction_info() {
+        let mut db =
+            PickleDb::new_yaml(NamedTempFile::new().unwrap(), PickleDbDumpPolicy::NeverDump);
+        let expired_info = TransactionInfo {
+            recipient: solana_sdk::pubkey::new_rand(),
+            amount: 42,
+            ..TransactionInfo::default()
+        };
+        let expired_signature = expired_info.transaction.signatures[0];
+        db.set(&expired_signature.to_string(), &expired_info).unwrap();
+
+        let transaction = Transaction {
+            signatures: vec![Signature::new(&[1; 64])],
+            ..Transaction::default()
+        };
+        set_resubmitted_transaction_info(&mut db, &expired_info, &transaction, 10).unwrap();
+        let resubmitted_info = TransactionInfo {
+            transaction: transaction.clone(),
+            last_valid_slot: 10,
+            resubmits: 1,
+            previous_signatures: vec![expired_signature],
+            ..expired_info
+        };
+        assert_eq!(read_transaction_infos(&db), vec![resubmitted_info.clone()]);
+
+        // Each resubmission is counted
+        let transaction = Transaction {
+            signatures: vec![Signature::new(&[2; 64])],
+            ..Transaction::default()
+        };
+        set_resubmitted_transaction_info(&mut db, &resubmitted_info, &transaction, 20).unwrap();
+        let transaction_infos = read_transaction_infos(&db);
+        assert_eq!(transaction_infos.len(), 1);
+        assert_eq!(transaction_infos[0].transaction, transaction);
+        assert_eq!(transaction_infos[0].resubmits, 2);
+        assert_eq!(
+            transaction_infos[0].previous_signatures,
+            vec![expired_signature, Signature::new(&[1; 64])]
+        );
+    }
+
     #[test]
     fn test_update_finalized_transaction_confirming() {
         // Keep waiting for a transaction that is still being confirmed.
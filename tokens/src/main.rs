@@ -44,6 +44,14 @@ fn main() -> Result<(), Box<dyn Error>> {
             spl_token::update_decimals(&client, &mut args.spl_token_args)?;
             commands::process_balances(&client, &args)?;
         }
+        Command::Reconcile(mut args) => {
+            spl_token::update_decimals(&client, &mut args.spl_token_args)?;
+            commands::process_reconcile(&client, &args)?;
+        }
+        Command::DiffAllocations(mut args) => {
+            spl_token::update_decimals(&client, &mut args.spl_token_args)?;
+            commands::process_diff_allocations(&args)?;
+        }
         Command::TransactionLog(args) => {
             commands::process_transaction_log(&args)?;
         }
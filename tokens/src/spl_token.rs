@@ -1,25 +1,136 @@
 use crate::{
-    args::{DistributeTokensArgs, SafeTokenArgs},
-    commands::{Allocation, Error, FundingSource},
+    args::{DisplayOptions, DistributeTokensArgs, SafeTokenArgs},
+    commands::{
+        compute_funding_requirements, parse_recipients, Allocation, AllocationError, Error,
+        FundingSource,
+    },
 };
 use console::style;
 use safecoin_account_decoder::parse_token::{
-    pubkey_from_spl_token_v2_0, real_number_string, real_number_string_trimmed,
-    spl_token_v2_0_pubkey,
+    pubkey_from_spl_token_v2_0, real_number_string_trimmed, spl_token_v2_0_pubkey,
 };
-use safecoin_client::rpc_client::RpcClient;
-use solana_sdk::{instruction::Instruction, native_token::lamports_to_sol};
-use safecoin_transaction_status::parse_token::spl_token_v2_0_instruction;
-use safe_associated_token_account_v1_0::{
-    create_associated_token_account, get_associated_token_address,
+use safecoin_client::{rpc_client::RpcClient, rpc_request::MAX_MULTIPLE_ACCOUNTS};
+use solana_runtime::inline_spl_token_v2_0::new_token_program;
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+    signature::{unique_signers, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::Transaction,
 };
+use safecoin_transaction_status::parse_token::spl_token_v2_0_instruction;
 use spl_token_v2_0::{
     solana_program::program_pack::Pack,
     state::{Account as SafeTokenAccount, Mint},
 };
+use std::{collections::HashSet, str::FromStr};
+
+/// Token program a distribution sends tokens through
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenProgram {
+    /// The safe-token v2.0 program
+    Legacy,
+    /// The token program that safe-token is migrating to
+    New,
+}
+
+impl Default for TokenProgram {
+    fn default() -> Self {
+        Self::Legacy
+    }
+}
+
+impl FromStr for TokenProgram {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(Self::Legacy),
+            "new" => Ok(Self::New),
+            _ => Err(format!("unknown token program: {}", s)),
+        }
+    }
+}
+
+impl TokenProgram {
+    pub fn program_id(&self) -> Pubkey {
+        match self {
+            Self::Legacy => pubkey_from_spl_token_v2_0(&spl_token_v2_0::id()),
+            Self::New => new_token_program::id(),
+        }
+    }
+
+    /// The associated token account program derives addresses from the token program id, so
+    /// both token programs share it
+    pub fn associated_token_program_id(&self) -> Pubkey {
+        pubkey_from_spl_token_v2_0(&safe_associated_token_account_v1_0::id())
+    }
+
+    /// Address of the associated token account of `wallet_address` for `mint` under this token
+    /// program
+    pub fn associated_token_address(&self, wallet_address: &Pubkey, mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                wallet_address.as_ref(),
+                self.program_id().as_ref(),
+                mint.as_ref(),
+            ],
+            &self.associated_token_program_id(),
+        )
+        .0
+    }
+
+    /// Instruction creating the associated token account of `wallet_address` for `mint`, funded
+    /// by `funding_address`
+    pub fn create_associated_token_account(
+        &self,
+        funding_address: &Pubkey,
+        wallet_address: &Pubkey,
+        mint: &Pubkey,
+    ) -> Instruction {
+        Instruction {
+            program_id: self.associated_token_program_id(),
+            accounts: vec![
+                AccountMeta::new(*funding_address, true),
+                AccountMeta::new(self.associated_token_address(wallet_address, mint), false),
+                AccountMeta::new_readonly(*wallet_address, false),
+                AccountMeta::new_readonly(*mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(self.program_id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data: vec![],
+        }
+    }
+}
+
+/// Whether `mint` is the wrapped SAFE mint
+pub fn is_native_mint(mint: &Pubkey) -> bool {
+    *mint == pubkey_from_spl_token_v2_0(&spl_token_v2_0::native_mint::id())
+}
+
+/// Seed of the temporary wrapped SAFE account. The account is derived from the sender alone, so
+/// that a resumed distribution finds the account of the interrupted run; a sender can therefore
+/// only run one wrapped SAFE distribution at a time.
+const WRAPPED_SOL_SEED: &str = "wrapped-safe-distribution";
+
+/// Address of the temporary wrapped SAFE account that `sender` distributes from, see
+/// `SafeTokenArgs::wrap_sol`
+pub fn wrapped_sol_address(sender: &Pubkey, token_program: TokenProgram) -> Pubkey {
+    Pubkey::create_with_seed(sender, WRAPPED_SOL_SEED, &token_program.program_id()).unwrap()
+}
 
 pub fn update_token_args(client: &RpcClient, args: &mut Option<SafeTokenArgs>) -> Result<(), Error> {
     if let Some(spl_token_args) = args {
+        if spl_token_args.wrap_sol {
+            // The wrapped SAFE account is only created once the distribution starts
+            spl_token_args.mint = pubkey_from_spl_token_v2_0(&spl_token_v2_0::native_mint::id());
+            spl_token_args.decimals = spl_token_v2_0::native_mint::DECIMALS;
+            return Ok(());
+        }
         let sender_account = client
             .get_account(&spl_token_args.token_account_address)
             .unwrap_or_default();
@@ -34,16 +145,226 @@ pub fn update_token_args(client: &RpcClient, args: &mut Option<SafeTokenArgs>) -
 pub fn update_decimals(client: &RpcClient, args: &mut Option<SafeTokenArgs>) -> Result<(), Error> {
     if let Some(spl_token_args) = args {
         let mint_account = client.get_account(&spl_token_args.mint).unwrap_or_default();
-        let mint = Mint::unpack(&mint_account.data)?;
-        spl_token_args.decimals = mint.decimals;
+        match Mint::unpack(&mint_account.data) {
+            Ok(mint) => spl_token_args.decimals = mint.decimals,
+            Err(err) if spl_token_args.allow_unchecked_transfer => {
+                eprintln!(
+                    "{}",
+                    style(format!(
+                        "Warning: unable to load mint {} ({}), sending unchecked transfers. \
+                        Amounts are base units and are not verified against the mint.",
+                        spl_token_args.mint, err
+                    ))
+                    .yellow()
+                );
+                spl_token_args.unchecked_transfer = true;
+            }
+            Err(err) => return Err(err.into()),
+        }
     }
     Ok(())
 }
 
+#[deprecated(
+    since = "1.6.19",
+    note = "Please use ui_amount_to_base_units() instead"
+)]
 pub fn spl_token_amount(amount: f64, decimals: u8) -> u64 {
     (amount * 10_usize.pow(decimals as u32) as f64) as u64
 }
 
+/// Convert a UI amount string such as "12.5" into base units of a token with `decimals`, without
+/// going through floating point
+pub fn ui_amount_to_base_units(ui_amount: &str, decimals: u8) -> Result<u64, Error> {
+    let invalid = |reason: &str| Error::InvalidUiAmount(ui_amount.to_string(), reason.to_string());
+
+    let (integer, fraction) = match ui_amount.find('.') {
+        Some(index) => (&ui_amount[..index], &ui_amount[index + 1..]),
+        None => (ui_amount, ""),
+    };
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(invalid("no digits"));
+    }
+    if !integer
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid("not a decimal number"));
+    }
+    if decimals == 0 && !fraction.is_empty() {
+        return Err(invalid(
+            "fractional amount, the token has 0 decimals so only whole amounts can be sent",
+        ));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(invalid(&format!(
+            "more than {} fractional digits",
+            decimals
+        )));
+    }
+
+    let overflow = || invalid("too large");
+    let parse = |digits: &str| {
+        if digits.is_empty() {
+            Ok(0)
+        } else {
+            digits.parse::<u64>().map_err(|_| overflow())
+        }
+    };
+    let integer_scale = 10u64.checked_pow(decimals as u32).ok_or_else(overflow)?;
+    let fraction_scale = 10u64.pow((decimals as usize - fraction.len()) as u32);
+    parse(integer)?
+        .checked_mul(integer_scale)
+        .and_then(|integer| {
+            parse(fraction)
+                .ok()?
+                .checked_mul(fraction_scale)?
+                .checked_add(integer)
+        })
+        .ok_or_else(overflow)
+}
+
+/// Format `amount` base units of a token with `decimals` as a UI amount string, with trailing
+/// zeros trimmed
+pub fn base_units_to_ui(amount: u64, decimals: u8) -> String {
+    real_number_string_trimmed(amount, decimals)
+}
+
+/// Format `amount` base units with `decimals` for a report, with trailing zeros trimmed. Rounding
+/// to `max_fraction_digits` is done on the integer amount, so the result stays exact.
+pub fn format_base_units(amount: u64, decimals: u8, display_options: &DisplayOptions) -> String {
+    let (amount, decimals) = match display_options.max_fraction_digits {
+        Some(max_fraction_digits) if max_fraction_digits < decimals => {
+            let rounded = 10u128
+                .checked_pow(u32::from(decimals - max_fraction_digits))
+                .map(|divisor| (u128::from(amount) + divisor / 2) / divisor)
+                .unwrap_or_default();
+            (rounded as u64, max_fraction_digits)
+        }
+        _ => (amount, decimals),
+    };
+    let ui_amount = real_number_string_trimmed(amount, decimals);
+    if !display_options.group_separators {
+        return ui_amount;
+    }
+    let (integer, fraction) = match ui_amount.find('.') {
+        Some(point) => ui_amount.split_at(point),
+        None => (ui_amount.as_str(), ""),
+    };
+    let mut grouped = String::with_capacity(ui_amount.len() + integer.len() / 3);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(fraction);
+    grouped
+}
+
+/// Format the signed difference `actual - expected` of two base unit amounts for a report
+pub fn format_base_units_difference(
+    expected: u64,
+    actual: u64,
+    decimals: u8,
+    display_options: &DisplayOptions,
+) -> String {
+    if actual >= expected {
+        format_base_units(actual - expected, decimals, display_options)
+    } else {
+        format!(
+            "-{}",
+            format_base_units(expected - actual, decimals, display_options)
+        )
+    }
+}
+
+/// The associated token account of the allocation recipient under `token_program`, which the
+/// tokens are sent to
+pub fn recipient_token_address(
+    allocation: &Allocation,
+    mint: &Pubkey,
+    token_program: TokenProgram,
+) -> Pubkey {
+    let wallet_address = allocation.recipient.parse().unwrap();
+    token_program.associated_token_address(&wallet_address, mint)
+}
+
+/// Derive the associated token account of each of `recipients` for `mint` under `program`,
+/// returning (recipient, associated token account) pairs in the order of `recipients`
+pub fn derive_atas(
+    recipients: &[Pubkey],
+    mint: &Pubkey,
+    program: TokenProgram,
+) -> Vec<(Pubkey, Pubkey)> {
+    recipients
+        .iter()
+        .map(|recipient| {
+            (
+                *recipient,
+                program.associated_token_address(recipient, mint),
+            )
+        })
+        .collect()
+}
+
+fn allocation_atas(
+    allocations: &[Allocation],
+    mint: &Pubkey,
+    token_program: TokenProgram,
+) -> Result<Vec<Pubkey>, Error> {
+    let recipients = parse_recipients(allocations).map_err(Error::InvalidAllocations)?;
+    Ok(derive_atas(&recipients, mint, token_program)
+        .into_iter()
+        .map(|(_, associated_token_address)| associated_token_address)
+        .collect())
+}
+
+/// Look up the associated token accounts of all allocation recipients, one RPC request per
+/// `MAX_MULTIPLE_ACCOUNTS` recipients, and return the addresses of those that already exist
+pub fn which_atas_exist(
+    client: &RpcClient,
+    allocations: &[Allocation],
+    mint: &Pubkey,
+    token_program: TokenProgram,
+) -> Result<HashSet<Pubkey>, Error> {
+    let associated_token_addresses = allocation_atas(allocations, mint, token_program)?;
+    let mut existing_addresses = HashSet::new();
+    for addresses in associated_token_addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = client.get_multiple_accounts(addresses)?;
+        existing_addresses.extend(
+            addresses
+                .iter()
+                .zip(accounts)
+                .filter_map(|(address, account)| account.map(|_| *address)),
+        );
+    }
+    Ok(existing_addresses)
+}
+
+/// Look up the token balance of each allocation recipient's associated token account, one RPC
+/// request per `MAX_MULTIPLE_ACCOUNTS` recipients. Missing accounts have a zero balance.
+pub fn recipient_token_balances(
+    client: &RpcClient,
+    allocations: &[Allocation],
+    mint: &Pubkey,
+    token_program: TokenProgram,
+) -> Result<Vec<u64>, Error> {
+    let associated_token_addresses = allocation_atas(allocations, mint, token_program)?;
+    let mut balances = Vec::with_capacity(allocations.len());
+    for addresses in associated_token_addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = client.get_multiple_accounts(addresses)?;
+        balances.extend(accounts.into_iter().map(|account| {
+            account
+                .and_then(|account| SafeTokenAccount::unpack(&account.data).ok())
+                .map(|token_account| token_account.amount)
+                .unwrap_or_default()
+        }));
+    }
+    Ok(balances)
+}
+
 pub fn build_spl_token_instructions(
     allocation: &Allocation,
     args: &DistributeTokensArgs,
@@ -53,39 +374,163 @@ pub fn build_spl_token_instructions(
         .spl_token_args
         .as_ref()
         .expect("spl_token_args must be some");
+    let token_program = spl_token_args.token_program;
     let wallet_address = allocation.recipient.parse().unwrap();
-    let associated_token_address = get_associated_token_address(
-        &wallet_address,
-        &spl_token_v2_0_pubkey(&spl_token_args.mint),
-    );
+    let associated_token_address =
+        token_program.associated_token_address(&wallet_address, &spl_token_args.mint);
     let mut instructions = vec![];
     if do_create_associated_token_account {
-        let create_associated_token_account_instruction = create_associated_token_account(
-            &spl_token_v2_0_pubkey(&args.fee_payer.pubkey()),
+        let rent_payer = args.rent_payer.as_ref().unwrap_or(&args.fee_payer);
+        instructions.push(token_program.create_associated_token_account(
+            &rent_payer.pubkey(),
             &wallet_address,
-            &spl_token_v2_0_pubkey(&spl_token_args.mint),
-        );
-        instructions.push(spl_token_v2_0_instruction(
-            create_associated_token_account_instruction,
+            &spl_token_args.mint,
         ));
     }
-    let spl_instruction = spl_token_v2_0::instruction::transfer_checked(
-        &spl_token_v2_0::id(),
-        &spl_token_v2_0_pubkey(&spl_token_args.token_account_address),
-        &spl_token_v2_0_pubkey(&spl_token_args.mint),
-        &associated_token_address,
-        &spl_token_v2_0_pubkey(&args.sender_keypair.pubkey()),
-        &[],
-        allocation.amount,
-        spl_token_args.decimals,
-    )
+    // The safe-token instruction builders only accept the legacy program id; both programs
+    // share the instruction layout, so the target program is set afterwards
+    let spl_instruction = if spl_token_args.unchecked_transfer {
+        // Without the mint's decimals the token program cannot check the amount scale or the
+        // mint of the source account, see `SafeTokenArgs::unchecked_transfer`
+        spl_token_v2_0::instruction::transfer(
+            &spl_token_v2_0::id(),
+            &spl_token_v2_0_pubkey(&spl_token_args.token_account_address),
+            &spl_token_v2_0_pubkey(&associated_token_address),
+            &spl_token_v2_0_pubkey(&args.sender_keypair.pubkey()),
+            &[],
+            allocation.amount,
+        )
+    } else {
+        spl_token_v2_0::instruction::transfer_checked(
+            &spl_token_v2_0::id(),
+            &spl_token_v2_0_pubkey(&spl_token_args.token_account_address),
+            &spl_token_v2_0_pubkey(&spl_token_args.mint),
+            &spl_token_v2_0_pubkey(&associated_token_address),
+            &spl_token_v2_0_pubkey(&args.sender_keypair.pubkey()),
+            &[],
+            allocation.amount,
+            spl_token_args.decimals,
+        )
+    }
     .unwrap();
-    instructions.push(spl_token_v2_0_instruction(spl_instruction));
+    let mut instruction = spl_token_v2_0_instruction(spl_instruction);
+    instruction.program_id = token_program.program_id();
+    instructions.push(instruction);
     instructions
 }
 
+/// Create the temporary wrapped SAFE account of `args` and fund it with `amount` lamports from
+/// the sender, on top of its rent-exempt balance. An account left behind by an interrupted run is
+/// closed first, since its balance no longer matches what is left to distribute.
+pub fn wrap_sol(client: &RpcClient, args: &DistributeTokensArgs, amount: u64) -> Result<(), Error> {
+    unwrap_sol(client, args)?;
+    let spl_token_args = args
+        .spl_token_args
+        .as_ref()
+        .expect("spl_token_args must be some");
+    let wrapped_sol_address = spl_token_args.token_account_address;
+    let token_program = spl_token_args.token_program;
+    let sender = args.sender_keypair.pubkey();
+    let rent_exempt_balance =
+        client.get_minimum_balance_for_rent_exemption(SafeTokenAccount::LEN)?;
+    let mut initialize_account = spl_token_v2_0_instruction(
+        spl_token_v2_0::instruction::initialize_account(
+            &spl_token_v2_0::id(),
+            &spl_token_v2_0_pubkey(&wrapped_sol_address),
+            &spl_token_v2_0::native_mint::id(),
+            &spl_token_v2_0_pubkey(&sender),
+        )
+        .unwrap(),
+    );
+    initialize_account.program_id = token_program.program_id();
+    let instructions = vec![
+        system_instruction::create_account_with_seed(
+            &sender,
+            &wrapped_sol_address,
+            &sender,
+            WRAPPED_SOL_SEED,
+            rent_exempt_balance + amount,
+            SafeTokenAccount::LEN as u64,
+            &token_program.program_id(),
+        ),
+        initialize_account,
+    ];
+    send_wrapped_sol_transaction(
+        client,
+        args,
+        &instructions,
+        vec![&*args.fee_payer, &*args.sender_keypair],
+    )
+}
+
+/// Close the temporary wrapped SAFE account of `args`, if it exists, returning any undistributed
+/// lamports and its rent to the sender
+pub fn unwrap_sol(client: &RpcClient, args: &DistributeTokensArgs) -> Result<(), Error> {
+    let spl_token_args = args
+        .spl_token_args
+        .as_ref()
+        .expect("spl_token_args must be some");
+    if client.get_balance(&spl_token_args.token_account_address)? == 0 {
+        return Ok(());
+    }
+    let sender = spl_token_v2_0_pubkey(&args.sender_keypair.pubkey());
+    let mut instruction = spl_token_v2_0_instruction(
+        spl_token_v2_0::instruction::close_account(
+            &spl_token_v2_0::id(),
+            &spl_token_v2_0_pubkey(&spl_token_args.token_account_address),
+            &sender,
+            &sender,
+            &[],
+        )
+        .unwrap(),
+    );
+    instruction.program_id = spl_token_args.token_program.program_id();
+    send_wrapped_sol_transaction(
+        client,
+        args,
+        &[instruction],
+        vec![&*args.fee_payer, &*args.sender_keypair],
+    )
+}
+
+fn send_wrapped_sol_transaction(
+    client: &RpcClient,
+    args: &DistributeTokensArgs,
+    instructions: &[Instruction],
+    signers: Vec<&dyn Signer>,
+) -> Result<(), Error> {
+    let message = Message::new(instructions, Some(&args.fee_payer.pubkey()));
+    let (blockhash, _fee_calculator) = client.get_recent_blockhash()?;
+    let transaction = Transaction::new(&unique_signers(signers), message, blockhash);
+    client.send_and_confirm_transaction_with_spinner(&transaction)?;
+    Ok(())
+}
+
+/// Number of associated token accounts created by `transaction` for `token_program`
+pub fn created_associated_token_accounts(
+    transaction: &Transaction,
+    token_program: TokenProgram,
+) -> u64 {
+    let associated_token_program_id = token_program.associated_token_program_id();
+    let message = &transaction.message;
+    message
+        .instructions
+        .iter()
+        .filter(|instruction| {
+            message.account_keys[instruction.program_id_index as usize]
+                == associated_token_program_id
+        })
+        .count() as u64
+}
+
+pub fn token_account_rent_exempt_balance(client: &RpcClient) -> Result<u64, Error> {
+    Ok(client.get_minimum_balance_for_rent_exemption(SafeTokenAccount::LEN)?)
+}
+
+/// Check that the payers and the token source can fund the distribution. `fees` is the total fee
+/// of its messages, see `estimate_distribution_fees()`
 pub fn check_spl_token_balances(
-    num_signatures: usize,
+    fees: u64,
     allocations: &[Allocation],
     client: &RpcClient,
     args: &DistributeTokensArgs,
@@ -95,75 +540,213 @@ pub fn check_spl_token_balances(
         .spl_token_args
         .as_ref()
         .expect("spl_token_args must be some");
-    let allocation_amount: u64 = allocations.iter().map(|x| x.amount).sum();
-
-    let fee_calculator = client.get_recent_blockhash()?.1;
-    let fees = fee_calculator
-        .lamports_per_signature
-        .checked_mul(num_signatures as u64)
-        .unwrap();
 
     let token_account_rent_exempt_balance =
         client.get_minimum_balance_for_rent_exemption(SafeTokenAccount::LEN)?;
-    let account_creation_amount = created_accounts * token_account_rent_exempt_balance;
+    let account_creation_amount = created_accounts
+        .checked_mul(token_account_rent_exempt_balance)
+        .ok_or(Error::FundingOverflow("associated token account rent"))?;
+    let requirements =
+        compute_funding_requirements(allocations, args, fees, account_creation_amount)?;
+    let fee_payer_required = requirements.required(&FundingSource::FeePayer);
     let fee_payer_balance = client.get_balance(&args.fee_payer.pubkey())?;
-    if fee_payer_balance < fees + account_creation_amount {
+    if fee_payer_balance < fee_payer_required {
         return Err(Error::InsufficientFunds(
             vec![FundingSource::FeePayer].into(),
-            lamports_to_sol(fees + account_creation_amount).to_string(),
+            lamports_to_sol(fee_payer_required).to_string(),
         ));
     }
-    let source_token_account = client
-        .get_account(&spl_token_args.token_account_address)
-        .unwrap_or_default();
-    let source_token = SafeTokenAccount::unpack(&source_token_account.data)?;
-    if source_token.amount < allocation_amount {
+    let rent_payer_required = requirements.required(&FundingSource::RentPayer);
+    if let Some(rent_payer) = &args.rent_payer {
+        if rent_payer_required > 0
+            && client.get_balance(&rent_payer.pubkey())? < rent_payer_required
+        {
+            return Err(Error::InsufficientFunds(
+                vec![FundingSource::RentPayer].into(),
+                lamports_to_sol(rent_payer_required).to_string(),
+            ));
+        }
+    }
+    let allocation_amount = requirements.required(&FundingSource::SafeTokenAccount);
+    let source_balance = if spl_token_args.wrap_sol {
+        // The wrapped SAFE account is only funded from the sender once these checks pass, after
+        // any account left by an interrupted run is closed back to the sender
+        let sender_balance = client.get_balance(&args.sender_keypair.pubkey())?;
+        let leftover_balance = client.get_balance(&spl_token_args.token_account_address)?;
+        sender_balance
+            .saturating_add(leftover_balance)
+            .saturating_sub(token_account_rent_exempt_balance)
+    } else {
+        let source_token_account = client
+            .get_account(&spl_token_args.token_account_address)
+            .unwrap_or_default();
+        SafeTokenAccount::unpack(&source_token_account.data)?.amount
+    };
+    if source_balance < allocation_amount {
         return Err(Error::InsufficientFunds(
             vec![FundingSource::SafeTokenAccount].into(),
-            real_number_string_trimmed(allocation_amount, spl_token_args.decimals),
+            base_units_to_ui(allocation_amount, spl_token_args.decimals),
         ));
     }
     Ok(())
 }
 
+/// Check that the token account at `address` belongs to `token_program` and holds
+/// `expected_mint` tokens on behalf of `expected_owner`
+pub fn verify_recipient_token_account(
+    address: &Pubkey,
+    account: &Account,
+    expected_owner: &Pubkey,
+    expected_mint: &Pubkey,
+    token_program: TokenProgram,
+) -> Result<(), Error> {
+    let invalid = |reason: String| Error::InvalidRecipientTokenAccount(*address, reason);
+    if account.owner != token_program.program_id() {
+        return Err(invalid(format!(
+            "owned by program {}, not the token program",
+            account.owner
+        )));
+    }
+    let token_account = SafeTokenAccount::unpack(&account.data)
+        .map_err(|err| invalid(format!("not a token account: {}", err)))?;
+    let owner = pubkey_from_spl_token_v2_0(&token_account.owner);
+    if owner != *expected_owner {
+        return Err(invalid(format!(
+            "owner is {}, expected {}",
+            owner, expected_owner
+        )));
+    }
+    let mint = pubkey_from_spl_token_v2_0(&token_account.mint);
+    if mint != *expected_mint {
+        return Err(invalid(format!(
+            "mint is {}, expected {}",
+            mint, expected_mint
+        )));
+    }
+    Ok(())
+}
+
+/// Look up each allocation recipient, one RPC request per `MAX_MULTIPLE_ACCOUNTS` recipients,
+/// and report those that are token accounts rather than wallets. Tokens sent to them would land
+/// in an associated token account that only a token account can own, and so be lost.
+///
+/// A recipient is a token account by the same rule as `verify_recipient_token_account`: it is
+/// owned by `token_program` and holds a token account. Recipients owned by other programs,
+/// including the other token program, are warned about but accepted, since they may be
+/// program-controlled wallets. Expects the recipients to have passed `validate_allocations`.
+pub fn check_spl_token_recipients(
+    client: &RpcClient,
+    allocations: &[Allocation],
+    token_program: TokenProgram,
+) -> Result<Vec<AllocationError>, Error> {
+    let recipients: Vec<Pubkey> = allocations
+        .iter()
+        .map(|allocation| allocation.recipient.parse().unwrap())
+        .collect();
+    let mut errors = vec![];
+    for (chunk_index, chunk) in recipients.chunks(MAX_MULTIPLE_ACCOUNTS).enumerate() {
+        let accounts = client.get_multiple_accounts(chunk)?;
+        for (offset, (recipient, account)) in chunk.iter().zip(accounts).enumerate() {
+            let i = chunk_index * MAX_MULTIPLE_ACCOUNTS + offset;
+            if let Some(error) =
+                account.and_then(|account| check_recipient(i, recipient, &account, token_program))
+            {
+                errors.push(error);
+            }
+        }
+    }
+    Ok(errors)
+}
+
+fn check_recipient(
+    i: usize,
+    recipient: &Pubkey,
+    account: &Account,
+    token_program: TokenProgram,
+) -> Option<AllocationError> {
+    if account.owner == system_program::id() {
+        return None;
+    }
+    match SafeTokenAccount::unpack(&account.data) {
+        Ok(token_account) if account.owner == token_program.program_id() => {
+            Some(AllocationError::TokenAccountRecipient(
+                i,
+                recipient.to_string(),
+                pubkey_from_spl_token_v2_0(&token_account.mint),
+            ))
+        }
+        _ => {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "allocation {}: recipient {} is owned by program {}, not the system program",
+                    i, recipient, account.owner
+                ))
+                .yellow()
+            );
+            None
+        }
+    }
+}
+
+/// Print the expected and actual token balance of the allocation recipient, returning false if
+/// its associated token account does not exist or holds less than the allocation
 pub fn print_token_balances(
     client: &RpcClient,
     allocation: &Allocation,
     spl_token_args: &SafeTokenArgs,
-) -> Result<(), Error> {
+    display_options: &DisplayOptions,
+) -> Result<bool, Error> {
     let address = allocation.recipient.parse().unwrap();
     let expected = allocation.amount;
-    let associated_token_address = get_associated_token_address(
-        &spl_token_v2_0_pubkey(&address),
-        &spl_token_v2_0_pubkey(&spl_token_args.mint),
+    let associated_token_address = recipient_token_address(
+        allocation,
+        &spl_token_args.mint,
+        spl_token_args.token_program,
     );
     let recipient_account = client
-        .get_account(&pubkey_from_spl_token_v2_0(&associated_token_address))
+        .get_account(&associated_token_address)
         .unwrap_or_default();
-    let (actual, difference) = if let Ok(recipient_token) =
-        SafeTokenAccount::unpack(&recipient_account.data)
-    {
-        let actual_ui_amount = real_number_string(recipient_token.amount, spl_token_args.decimals);
-        let delta_string =
-            real_number_string(recipient_token.amount - expected, spl_token_args.decimals);
-        (
-            style(format!("{:>24}", actual_ui_amount)),
-            format!("{:>24}", delta_string),
-        )
-    } else {
-        (
-            style("Associated token account not yet created".to_string()).yellow(),
-            "".to_string(),
-        )
-    };
+    let (actual, difference, funded) =
+        if let Ok(recipient_token) = SafeTokenAccount::unpack(&recipient_account.data) {
+            verify_recipient_token_account(
+                &associated_token_address,
+                &recipient_account,
+                &address,
+                &spl_token_args.mint,
+                spl_token_args.token_program,
+            )?;
+            let actual_ui_amount = format_base_units(
+                recipient_token.amount,
+                spl_token_args.decimals,
+                display_options,
+            );
+            let delta_string = format_base_units_difference(
+                expected,
+                recipient_token.amount,
+                spl_token_args.decimals,
+                display_options,
+            );
+            (
+                style(format!("{:>24}", actual_ui_amount)),
+                format!("{:>24}", delta_string),
+                recipient_token.amount >= expected,
+            )
+        } else {
+            (
+                style("Associated token account not yet created".to_string()).yellow(),
+                "".to_string(),
+                false,
+            )
+        };
     println!(
         "{:<44}  {:>24}  {:>24}  {:>24}",
         allocation.recipient,
-        real_number_string(expected, spl_token_args.decimals),
+        format_base_units(expected, spl_token_args.decimals, display_options),
         actual,
         difference,
     );
-    Ok(())
+    Ok(funded)
 }
 
 #[cfg(test)]
@@ -179,4 +762,408 @@ mod tests {
     // async fn test_check_spl_token_balances()
     //
     // https://github.com/fair-exchange/safecoin/blob/5511d52c6284013a24ced10966d11d8f4585799e/tokens/src/spl_token.rs#L490-L685
+
+    use super::*;
+    use safe_associated_token_account_v1_0::get_associated_token_address;
+    use solana_sdk::signature::Keypair;
+    use spl_token_v2_0::{instruction::TokenInstruction, state::AccountState};
+
+    #[test]
+    fn test_created_associated_token_accounts() {
+        let sender = Keypair::new();
+        let mint = Pubkey::new_unique();
+        let allocation = Allocation {
+            recipient: Pubkey::new_unique().to_string(),
+            amount: 1,
+            lockup_date: "".to_string(),
+        };
+        let args = DistributeTokensArgs {
+            dry_run: true,
+            spl_token_args: Some(SafeTokenArgs {
+                token_account_address: Pubkey::new_unique(),
+                mint,
+                decimals: 2,
+                ..SafeTokenArgs::default()
+            }),
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
+        };
+        for (do_create, expected) in [(false, 0), (true, 1)].iter() {
+            let instructions = build_spl_token_instructions(&allocation, &args, *do_create);
+            let message = Message::new(&instructions, Some(&sender.pubkey()));
+            let transaction = Transaction::new_unsigned(message);
+            assert_eq!(
+                created_associated_token_accounts(&transaction, TokenProgram::Legacy),
+                *expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_spl_token_balances_overflow() {
+        let client = RpcClient::new_mock("succeeds".to_string());
+        let allocations = vec![Allocation {
+            recipient: Pubkey::new_unique().to_string(),
+            amount: 1,
+            lockup_date: "".to_string(),
+        }];
+        let args = DistributeTokensArgs {
+            dry_run: true,
+            spl_token_args: Some(SafeTokenArgs::default()),
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
+        };
+
+        // The rent of the created accounts overflows
+        assert!(matches!(
+            check_spl_token_balances(0, &allocations, &client, &args, u64::MAX),
+            Err(Error::FundingOverflow(_))
+        ));
+
+        // The fees plus the rent of the created accounts overflow
+        let rent_exempt_balance = client
+            .get_minimum_balance_for_rent_exemption(SafeTokenAccount::LEN)
+            .unwrap();
+        let fees = u64::MAX - rent_exempt_balance + 1;
+        assert!(matches!(
+            check_spl_token_balances(fees, &allocations, &client, &args, 1),
+            Err(Error::FundingOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_native_mint() {
+        assert!(is_native_mint(&pubkey_from_spl_token_v2_0(
+            &spl_token_v2_0::native_mint::id()
+        )));
+        assert!(!is_native_mint(&Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn test_wrapped_sol_address() {
+        let sender = Pubkey::new_unique();
+        let address = wrapped_sol_address(&sender, TokenProgram::Legacy);
+        // Stable across runs, so a resumed distribution reuses the account
+        assert_eq!(address, wrapped_sol_address(&sender, TokenProgram::Legacy));
+        assert_ne!(address, wrapped_sol_address(&sender, TokenProgram::New));
+        assert_ne!(
+            address,
+            wrapped_sol_address(&Pubkey::new_unique(), TokenProgram::Legacy)
+        );
+    }
+
+    #[test]
+    fn test_ui_amount_to_base_units() {
+        assert_eq!(ui_amount_to_base_units("12.5", 2).unwrap(), 1_250);
+        assert_eq!(ui_amount_to_base_units("12", 2).unwrap(), 1_200);
+        assert_eq!(ui_amount_to_base_units("12.", 2).unwrap(), 1_200);
+        assert_eq!(ui_amount_to_base_units(".05", 2).unwrap(), 5);
+        assert_eq!(ui_amount_to_base_units("0.1", 9).unwrap(), 100_000_000);
+        assert_eq!(ui_amount_to_base_units("7", 0).unwrap(), 7);
+        assert_eq!(
+            ui_amount_to_base_units("18446744073709551615", 0).unwrap(),
+            u64::MAX
+        );
+
+        // Too many fractional digits
+        assert!(ui_amount_to_base_units("1.234", 2).is_err());
+        assert!(ui_amount_to_base_units("1.5", 0).is_err());
+        assert!(ui_amount_to_base_units("1.0", 0).is_err());
+        assert!(ui_amount_to_base_units("0.9999999999999999999", 0).is_err());
+        // Overflow
+        assert!(ui_amount_to_base_units("18446744073709551616", 0).is_err());
+        assert!(ui_amount_to_base_units("18446744073709551615", 1).is_err());
+        assert!(ui_amount_to_base_units("1", 20).is_err());
+        // Malformed
+        assert!(ui_amount_to_base_units("", 2).is_err());
+        assert!(ui_amount_to_base_units(".", 2).is_err());
+        assert!(ui_amount_to_base_units("-1", 2).is_err());
+        assert!(ui_amount_to_base_units("1.2.3", 2).is_err());
+        assert!(ui_amount_to_base_units("1e3", 2).is_err());
+    }
+
+    #[test]
+    fn test_verify_recipient_token_account() {
+        let address = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let token_account = |owner: &Pubkey, mint: &Pubkey, program_id: &Pubkey| {
+            let mut data = vec![0; SafeTokenAccount::LEN];
+            SafeTokenAccount::pack(
+                SafeTokenAccount {
+                    mint: spl_token_v2_0_pubkey(mint),
+                    owner: spl_token_v2_0_pubkey(owner),
+                    amount: 42,
+                    state: AccountState::Initialized,
+                    ..SafeTokenAccount::default()
+                },
+                &mut data,
+            )
+            .unwrap();
+            Account {
+                lamports: 1,
+                data,
+                owner: *program_id,
+                ..Account::default()
+            }
+        };
+        let legacy = TokenProgram::Legacy;
+        let token_program_id = legacy.program_id();
+
+        let account = token_account(&owner, &mint, &token_program_id);
+        assert!(verify_recipient_token_account(&address, &account, &owner, &mint, legacy).is_ok());
+
+        // Wrong wallet owner
+        let account = token_account(&Pubkey::new_unique(), &mint, &token_program_id);
+        assert!(matches!(
+            verify_recipient_token_account(&address, &account, &owner, &mint, legacy),
+            Err(Error::InvalidRecipientTokenAccount(..))
+        ));
+
+        // Wrong mint
+        let account = token_account(&owner, &Pubkey::new_unique(), &token_program_id);
+        assert!(verify_recipient_token_account(&address, &account, &owner, &mint, legacy).is_err());
+
+        // Not owned by the token program
+        let account = token_account(&owner, &mint, &Pubkey::new_unique());
+        assert!(verify_recipient_token_account(&address, &account, &owner, &mint, legacy).is_err());
+
+        // Not a token account
+        let account = Account::new(1, 0, &token_program_id);
+        assert!(verify_recipient_token_account(&address, &account, &owner, &mint, legacy).is_err());
+    }
+
+    #[test]
+    fn test_check_recipient() {
+        let recipient = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut data = vec![0; SafeTokenAccount::LEN];
+        SafeTokenAccount::pack(
+            SafeTokenAccount {
+                mint: spl_token_v2_0_pubkey(&mint),
+                owner: spl_token_v2_0_pubkey(&Pubkey::new_unique()),
+                state: AccountState::Initialized,
+                ..SafeTokenAccount::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+
+        let wallet = Account::new(1, 0, &system_program::id());
+        assert_eq!(
+            check_recipient(0, &recipient, &wallet, TokenProgram::Legacy),
+            None
+        );
+
+        for program in &[TokenProgram::Legacy, TokenProgram::New] {
+            let token_account = Account {
+                lamports: 1,
+                data: data.clone(),
+                owner: program.program_id(),
+                ..Account::default()
+            };
+            assert_eq!(
+                check_recipient(3, &recipient, &token_account, *program),
+                Some(AllocationError::TokenAccountRecipient(
+                    3,
+                    recipient.to_string(),
+                    mint
+                ))
+            );
+        }
+
+        // A token account of the other token program is not one the distribution sends through
+        let new_token_account = Account {
+            lamports: 1,
+            data: data.clone(),
+            owner: TokenProgram::New.program_id(),
+            ..Account::default()
+        };
+        assert_eq!(
+            check_recipient(0, &recipient, &new_token_account, TokenProgram::Legacy),
+            None
+        );
+
+        // Token account data owned by another program is only warned about
+        let program_account = Account {
+            lamports: 1,
+            data,
+            owner: Pubkey::new_unique(),
+            ..Account::default()
+        };
+        assert_eq!(
+            check_recipient(0, &recipient, &program_account, TokenProgram::Legacy),
+            None
+        );
+    }
+
+    #[test]
+    fn test_build_spl_token_instructions_unchecked_transfer() {
+        let allocation = Allocation {
+            recipient: Pubkey::new_unique().to_string(),
+            amount: 42,
+            lockup_date: "".to_string(),
+        };
+        let mut args = DistributeTokensArgs {
+            dry_run: true,
+            spl_token_args: Some(SafeTokenArgs {
+                token_account_address: Pubkey::new_unique(),
+                mint: Pubkey::new_unique(),
+                decimals: 2,
+                ..SafeTokenArgs::default()
+            }),
+            ..DistributeTokensArgs::for_test(Box::new(Keypair::new()), Box::new(Keypair::new()))
+        };
+
+        let instructions = build_spl_token_instructions(&allocation, &args, false);
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(
+            TokenInstruction::unpack(&instructions[0].data).unwrap(),
+            TokenInstruction::TransferChecked {
+                amount: 42,
+                decimals: 2
+            }
+        );
+
+        args.spl_token_args.as_mut().unwrap().unchecked_transfer = true;
+        let instructions = build_spl_token_instructions(&allocation, &args, false);
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(
+            TokenInstruction::unpack(&instructions[0].data).unwrap(),
+            TokenInstruction::Transfer { amount: 42 }
+        );
+
+        let new_program_id = TokenProgram::New.program_id();
+        args.spl_token_args.as_mut().unwrap().token_program = TokenProgram::New;
+        let instructions = build_spl_token_instructions(&allocation, &args, true);
+        assert_eq!(instructions.len(), 2);
+        assert!(instructions[0]
+            .accounts
+            .iter()
+            .any(|meta| meta.pubkey == new_program_id));
+        assert_eq!(instructions[1].program_id, new_program_id);
+    }
+
+    #[test]
+    fn test_derive_atas() {
+        let recipients = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let mint = Pubkey::new_unique();
+        assert!(derive_atas(&[], &mint, TokenProgram::Legacy).is_empty());
+
+        let atas = derive_atas(&recipients, &mint, TokenProgram::Legacy);
+        assert_eq!(
+            atas,
+            recipients
+                .iter()
+                .map(|recipient| (
+                    *recipient,
+                    pubkey_from_spl_token_v2_0(&get_associated_token_address(
+                        &spl_token_v2_0_pubkey(recipient),
+                        &spl_token_v2_0_pubkey(&mint),
+                    ))
+                ))
+                .collect::<Vec<_>>()
+        );
+
+        let new_program_id = new_token_program::id();
+        let ata_program_id = TokenProgram::New.associated_token_program_id();
+        let atas = derive_atas(&recipients, &mint, TokenProgram::New);
+        for (recipient, (atas_recipient, ata)) in recipients.iter().zip(atas) {
+            assert_eq!(atas_recipient, *recipient);
+            assert_eq!(
+                ata,
+                Pubkey::find_program_address(
+                    &[recipient.as_ref(), new_program_id.as_ref(), mint.as_ref()],
+                    &ata_program_id,
+                )
+                .0
+            );
+        }
+    }
+
+    #[test]
+    fn test_token_program() {
+        let wallet_address = Pubkey::new_unique();
+        let funding_address = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+
+        let legacy = TokenProgram::Legacy;
+        assert_eq!(
+            legacy.associated_token_address(&wallet_address, &mint),
+            pubkey_from_spl_token_v2_0(&get_associated_token_address(
+                &spl_token_v2_0_pubkey(&wallet_address),
+                &spl_token_v2_0_pubkey(&mint),
+            ))
+        );
+        assert_eq!(
+            legacy.create_associated_token_account(&funding_address, &wallet_address, &mint),
+            spl_token_v2_0_instruction(
+                safe_associated_token_account_v1_0::create_associated_token_account(
+                    &spl_token_v2_0_pubkey(&funding_address),
+                    &spl_token_v2_0_pubkey(&wallet_address),
+                    &spl_token_v2_0_pubkey(&mint),
+                )
+            )
+        );
+
+        let new = TokenProgram::New;
+        assert_ne!(new.program_id(), legacy.program_id());
+        assert_eq!(
+            new.associated_token_program_id(),
+            legacy.associated_token_program_id()
+        );
+        assert_ne!(
+            new.associated_token_address(&wallet_address, &mint),
+            legacy.associated_token_address(&wallet_address, &mint)
+        );
+    }
+
+    #[test]
+    fn test_base_units_to_ui() {
+        assert_eq!(base_units_to_ui(0, 0), "0");
+        assert_eq!(base_units_to_ui(0, 9), "0");
+        assert_eq!(base_units_to_ui(1_200, 0), "1200");
+        assert_eq!(base_units_to_ui(1_200, 2), "12");
+        assert_eq!(base_units_to_ui(1_250, 2), "12.5");
+        assert_eq!(base_units_to_ui(1, 9), "0.000000001");
+        assert_eq!(base_units_to_ui(u64::MAX, 19), "1.8446744073709551615");
+        assert_eq!(base_units_to_ui(u64::MAX, 20), "0.18446744073709551615");
+        for ui_amount in &["0.000000001", "12.5", "1.8446744073709551615"] {
+            let decimals = ui_amount.len() as u8 - ui_amount.find('.').unwrap() as u8 - 1;
+            let amount = ui_amount_to_base_units(ui_amount, decimals).unwrap();
+            assert_eq!(base_units_to_ui(amount, decimals), *ui_amount);
+        }
+    }
+
+    #[test]
+    fn test_format_base_units() {
+        let default = DisplayOptions::default();
+        let grouped = DisplayOptions {
+            group_separators: true,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(format_base_units(1_234_500, 3, &default), "1234.5");
+        assert_eq!(format_base_units(1_234_500, 3, &grouped), "1,234.5");
+        assert_eq!(format_base_units(0, 9, &grouped), "0");
+        assert_eq!(format_base_units(123, 0, &grouped), "123");
+        assert_eq!(format_base_units(1_234_567, 0, &grouped), "1,234,567");
+        assert_eq!(format_base_units(123_456_789, 9, &grouped), "0.123456789");
+
+        let rounded = |max_fraction_digits| DisplayOptions {
+            group_separators: true,
+            max_fraction_digits: Some(max_fraction_digits),
+        };
+        assert_eq!(format_base_units(1_234_567, 3, &rounded(2)), "1,234.57");
+        assert_eq!(format_base_units(1_234_567, 3, &rounded(5)), "1,234.567");
+        assert_eq!(format_base_units(1_500, 3, &rounded(0)), "2");
+        assert_eq!(format_base_units(1_499, 3, &rounded(0)), "1");
+        assert_eq!(format_base_units(u64::MAX, 19, &rounded(0)), "2");
+        assert_eq!(format_base_units(u64::MAX, u8::MAX, &rounded(0)), "0");
+
+        assert_eq!(format_base_units_difference(4, 10, 0, &grouped), "6");
+        assert_eq!(format_base_units_difference(10, 4, 0, &grouped), "-6");
+        assert_eq!(
+            format_base_units_difference(1_000_000, 0, 0, &grouped),
+            "-1,000,000"
+        );
+        assert_eq!(format_base_units_difference(5, 5, 2, &grouped), "0");
+    }
 }
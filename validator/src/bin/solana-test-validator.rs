@@ -347,6 +347,13 @@ fn main() {
                         println!("Error: invalid address {}: {}", address, err);
                         exit(1);
                     });
+                    if programs
+                        .iter()
+                        .any(|program: &ProgramInfo| program.program_id == address)
+                    {
+                        println!("Error: duplicate program address {}", address);
+                        exit(1);
+                    }
 
                     let program_path = PathBuf::from(program);
                     if !program_path.exists() {